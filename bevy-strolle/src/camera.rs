@@ -8,4 +8,5 @@ use strolle as st;
 #[derive(Clone, Debug, Default, Component)]
 pub struct StrolleCamera {
     pub mode: st::CameraMode,
+    pub light_sampling: st::LightSampling,
 }
@@ -2,6 +2,7 @@ mod camera;
 mod event;
 pub mod graph;
 mod rendering_node;
+mod skinning;
 mod stages;
 mod state;
 mod sun;
@@ -64,7 +65,18 @@ impl st::Params for EngineParams {
     type InstanceHandle = Entity;
     type LightHandle = Entity;
     type MaterialHandle = AssetId<StandardMaterial>;
-    type MeshHandle = AssetId<Mesh>;
+    type MeshHandle = MeshHandle;
+}
+
+/// Mesh, as seen by the engine.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum MeshHandle {
+    /// Mesh asset, shared by all entities that use it.
+    Asset(AssetId<Mesh>),
+
+    /// Mesh deformed (through skinning or morph targets) specifically for
+    /// given entity; see: [`skinning`].
+    Entity(Entity),
 }
 
 impl ops::Deref for EngineResource {
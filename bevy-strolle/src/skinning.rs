@@ -0,0 +1,326 @@
+//! Skinning and morph targets.
+//!
+//! Since Strolle builds its BVHs on the CPU, deformed meshes are evaluated on
+//! the CPU as well - each skinned (or morphed) entity gets its own copy of the
+//! mesh, which is then uploaded as a regular mesh and, since the number of its
+//! triangles doesn't change, just refitted in the BVH.
+//!
+//! To keep the extraction cheap, only joint matrices and morph-target weights
+//! get extracted each frame - meshes themselves are extracted once (see:
+//! [`DeformableMesh`]) and the deformation happens during the prepare stage.
+
+use bevy::math::Mat3;
+use bevy::prelude::*;
+use bevy::reflect::Struct;
+use bevy::render::mesh::VertexAttributeValues;
+
+/// Render-world copy of a mesh that can be deformed through skinning or morph
+/// targets.
+#[derive(Debug)]
+pub(crate) struct DeformableMesh {
+    mesh: Mesh,
+    joint_indices: Vec<[u16; 4]>,
+    joint_weights: Vec<[f32; 4]>,
+    morph_targets: Option<MorphTargets>,
+}
+
+impl DeformableMesh {
+    /// Returns `None` if the mesh's morph targets haven't been loaded yet.
+    pub fn new(mesh: &Mesh, images: &Assets<Image>) -> Option<Self> {
+        let morph_targets = if mesh.has_morph_targets() {
+            let image = morph_targets(mesh).and_then(|id| images.get(id))?;
+
+            Some(MorphTargets::new(image))
+        } else {
+            None
+        };
+
+        let joint_indices = match mesh.attribute(Mesh::ATTRIBUTE_JOINT_INDEX) {
+            Some(VertexAttributeValues::Uint16x4(indices)) => indices.clone(),
+            _ => Vec::new(),
+        };
+
+        let joint_weights = match mesh.attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT) {
+            Some(VertexAttributeValues::Float32x4(weights)) => weights.clone(),
+            _ => Vec::new(),
+        };
+
+        Some(Self {
+            mesh: mesh.clone(),
+            joint_indices,
+            joint_weights,
+            morph_targets,
+        })
+    }
+
+    pub fn mesh(&self) -> &Mesh {
+        &self.mesh
+    }
+
+    /// Deforms the mesh using given morph-target weights and joint matrices.
+    ///
+    /// Joint matrices should map from the mesh's bind-pose into the entity's
+    /// local-space, so that the deformed mesh can be transformed by the
+    /// entity's transform just like a regular mesh.
+    pub fn deform(&self, weights: &[f32], joints: &[Mat4]) -> DeformedVertices {
+        let float3 = |attribute| {
+            self.mesh
+                .attribute(attribute)
+                .and_then(VertexAttributeValues::as_float3)
+                .unwrap_or_default()
+                .iter()
+                .copied()
+                .map(Vec3::from)
+                .collect::<Vec<_>>()
+        };
+
+        let mut positions = float3(Mesh::ATTRIBUTE_POSITION);
+        let mut normals = float3(Mesh::ATTRIBUTE_NORMAL);
+
+        let mut tangents: Vec<_> =
+            match self.mesh.attribute(Mesh::ATTRIBUTE_TANGENT) {
+                Some(VertexAttributeValues::Float32x4(tangents)) => {
+                    tangents.iter().copied().map(Vec4::from).collect()
+                }
+                _ => Vec::new(),
+            };
+
+        if let Some(targets) = &self.morph_targets {
+            targets.apply(weights, &mut positions, &mut normals, &mut tangents);
+        }
+
+        if !joints.is_empty() {
+            skin(
+                &self.joint_indices,
+                &self.joint_weights,
+                joints,
+                &mut positions,
+                &mut normals,
+                &mut tangents,
+            );
+        }
+
+        DeformedVertices {
+            positions: positions.iter().map(Vec3::to_array).collect(),
+            normals: normals.iter().map(Vec3::to_array).collect(),
+            tangents: tangents.iter().map(Vec4::to_array).collect(),
+        }
+    }
+}
+
+/// Vertices of a deformed mesh; see: [`DeformableMesh::deform()`].
+///
+/// Each attribute is either empty (if the mesh doesn't have it) or contains
+/// the same number of items as the original mesh.
+#[derive(Debug, Default)]
+pub(crate) struct DeformedVertices {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tangents: Vec<[f32; 4]>,
+}
+
+fn skin(
+    joint_indices: &[[u16; 4]],
+    joint_weights: &[[f32; 4]],
+    joints: &[Mat4],
+    positions: &mut [Vec3],
+    normals: &mut [Vec3],
+    tangents: &mut [Vec4],
+) {
+    for (vertex_idx, (indices, weights)) in
+        joint_indices.iter().zip(joint_weights).enumerate()
+    {
+        let matrix = indices
+            .iter()
+            .zip(weights)
+            .filter_map(|(&joint_idx, &weight)| {
+                Some(*joints.get(joint_idx as usize)? * weight)
+            })
+            .fold(Mat4::ZERO, |sum, matrix| sum + matrix);
+
+        let matrix3 = Mat3::from_mat4(matrix);
+
+        if let Some(position) = positions.get_mut(vertex_idx) {
+            *position = matrix.transform_point3(*position);
+        }
+
+        if let Some(normal) = normals.get_mut(vertex_idx) {
+            *normal = (matrix3.inverse().transpose() * *normal).normalize();
+        }
+
+        if let Some(tangent) = tangents.get_mut(vertex_idx) {
+            *tangent =
+                (matrix3 * tangent.truncate()).normalize().extend(tangent.w);
+        }
+    }
+}
+
+/// Returns handle of the image containing mesh's morph targets.
+///
+/// Bevy 0.12 doesn't expose this handle through `Mesh`'s public interface
+/// (`Mesh::morph_targets()` appears only in Bevy 0.13), but it's available
+/// through reflection.
+fn morph_targets(mesh: &Mesh) -> Option<&Handle<Image>> {
+    mesh.field("morph_targets")?
+        .downcast_ref::<Option<Handle<Image>>>()?
+        .as_ref()
+}
+
+/// Decoded Bevy's morph targets image; see: `MorphTargetImage`.
+///
+/// Each layer of the image corresponds to a single morph target and contains
+/// displacements of positions, normals and tangents of consecutive vertices,
+/// one `f32` component per pixel.
+#[derive(Debug)]
+struct MorphTargets {
+    data: Vec<f32>,
+    layer_size: usize,
+    layers: usize,
+}
+
+impl MorphTargets {
+    const COMPONENTS: usize = 9;
+
+    fn new(image: &Image) -> Self {
+        let size = image.texture_descriptor.size;
+
+        Self {
+            data: image
+                .data
+                .chunks_exact(4)
+                .map(|bytes| {
+                    f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                })
+                .collect(),
+            layer_size: (size.width * size.height) as usize,
+            layers: size.depth_or_array_layers as usize,
+        }
+    }
+
+    fn apply(
+        &self,
+        weights: &[f32],
+        positions: &mut [Vec3],
+        normals: &mut [Vec3],
+        tangents: &mut [Vec4],
+    ) {
+        for (target_idx, &weight) in weights.iter().enumerate() {
+            if weight == 0.0 || target_idx >= self.layers {
+                continue;
+            }
+
+            for (vertex_idx, position) in positions.iter_mut().enumerate() {
+                *position += weight * self.get(target_idx, vertex_idx, 0);
+            }
+
+            for (vertex_idx, normal) in normals.iter_mut().enumerate() {
+                *normal += weight * self.get(target_idx, vertex_idx, 3);
+            }
+
+            for (vertex_idx, tangent) in tangents.iter_mut().enumerate() {
+                *tangent +=
+                    (weight * self.get(target_idx, vertex_idx, 6)).extend(0.0);
+            }
+        }
+
+        for normal in normals {
+            *normal = normal.normalize_or_zero();
+        }
+    }
+
+    fn get(&self, target_idx: usize, vertex_idx: usize, offset: usize) -> Vec3 {
+        let idx = target_idx * self.layer_size
+            + vertex_idx * Self::COMPONENTS
+            + offset;
+
+        let component =
+            |idx: usize| self.data.get(idx).copied().unwrap_or_default();
+
+        Vec3::new(component(idx), component(idx + 1), component(idx + 2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    fn assert_vec3_eq(expected: Vec3, actual: Vec3) {
+        assert!(
+            expected.abs_diff_eq(actual, 1e-5),
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn skinning() {
+        let joints = [
+            Mat4::from_translation(Vec3::new(2.0, 0.0, 0.0)),
+            Mat4::from_translation(Vec3::new(0.0, 2.0, 0.0)),
+            Mat4::from_rotation_z(FRAC_PI_2),
+        ];
+
+        let mut positions = [Vec3::X, Vec3::X, Vec3::X];
+        let mut normals = [Vec3::X, Vec3::X, Vec3::X];
+        let mut tangents = [Vec4::new(0.0, 1.0, 0.0, -1.0); 3];
+
+        skin(
+            &[[0, 1, 0, 0], [2, 0, 0, 0], [0, 1, 7, 0]],
+            &[
+                [0.5, 0.5, 0.0, 0.0],
+                [1.0, 0.0, 0.0, 0.0],
+                [0.5, 0.5, 0.0, 0.0],
+            ],
+            &joints,
+            &mut positions,
+            &mut normals,
+            &mut tangents,
+        );
+
+        // Vertex #0 is influenced by both translations, half-and-half
+        assert_vec3_eq(Vec3::new(2.0, 1.0, 0.0), positions[0]);
+        assert_vec3_eq(Vec3::X, normals[0]);
+        assert_vec3_eq(Vec3::Y, tangents[0].truncate());
+
+        // Vertex #1 is rotated, together with its normal and tangent (but
+        // tangent's handedness stays the same)
+        assert_vec3_eq(Vec3::Y, positions[1]);
+        assert_vec3_eq(Vec3::Y, normals[1]);
+        assert_vec3_eq(-Vec3::X, tangents[1].truncate());
+        assert_eq!(-1.0, tangents[1].w);
+
+        // Vertex #2 refers to a missing joint, which gets ignored
+        assert_vec3_eq(positions[0], positions[2]);
+    }
+
+    #[test]
+    fn morphing() {
+        #[rustfmt::skip]
+        let targets = MorphTargets {
+            data: vec![
+                // Target #0
+                1.0, 0.0, 0.0, /* */ 0.0, 1.0, 0.0, /* */ 0.0, 0.0, 1.0,
+                // Target #1
+                0.0, 2.0, 0.0, /* */ 0.0, 0.0, 0.0, /* */ 0.0, 0.0, 0.0,
+            ],
+            layer_size: 9,
+            layers: 2,
+        };
+
+        let mut positions = [Vec3::ZERO];
+        let mut normals = [Vec3::Z];
+        let mut tangents = [Vec4::new(1.0, 0.0, 0.0, 1.0)];
+
+        targets.apply(
+            &[0.5, 1.0, 1.0],
+            &mut positions,
+            &mut normals,
+            &mut tangents,
+        );
+
+        assert_vec3_eq(Vec3::new(0.5, 2.0, 0.0), positions[0]);
+        assert_vec3_eq(Vec3::new(0.0, 0.5, 1.0).normalize(), normals[0]);
+        assert_eq!(Vec4::new(1.0, 0.0, 0.5, 1.0), tangents[0]);
+    }
+}
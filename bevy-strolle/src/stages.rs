@@ -10,6 +10,11 @@ pub(crate) fn setup(render_app: &mut App) {
         extract::meshes.in_set(RenderSet::ExtractCommands),
     );
 
+    render_app.add_systems(
+        ExtractSchedule,
+        extract::skinned_meshes.in_set(RenderSet::ExtractCommands),
+    );
+
     render_app.add_systems(
         ExtractSchedule,
         extract::materials.in_set(RenderSet::ExtractCommands),
@@ -42,6 +47,13 @@ pub(crate) fn setup(render_app: &mut App) {
 
     render_app.add_systems(Render, prepare::meshes.in_set(RenderSet::Prepare));
 
+    render_app.add_systems(
+        Render,
+        prepare::skinned_meshes
+            .in_set(RenderSet::Prepare)
+            .after(prepare::meshes),
+    );
+
     render_app
         .add_systems(Render, prepare::materials.in_set(RenderSet::Prepare));
 
@@ -50,6 +62,7 @@ pub(crate) fn setup(render_app: &mut App) {
         prepare::instances
             .in_set(RenderSet::Prepare)
             .after(prepare::meshes)
+            .after(prepare::skinned_meshes)
             .after(prepare::materials),
     );
 
@@ -2,20 +2,23 @@ use std::f32::consts::PI;
 
 use bevy::prelude::*;
 use bevy::render::camera::{CameraProjection, CameraRenderGraph};
+use bevy::render::mesh::morph::MeshMorphWeights;
+use bevy::render::mesh::skinning::{SkinnedMesh, SkinnedMeshInverseBindposes};
 use bevy::render::texture::{ImageSampler, ImageSamplerDescriptor};
 use bevy::render::view::RenderLayers;
 use bevy::render::Extract;
-use bevy::utils::HashSet;
+use bevy::utils::{HashMap, HashSet};
 use strolle as st;
 
+use crate::skinning::DeformableMesh;
 use crate::state::{
     ExtractedCamera, ExtractedImage, ExtractedImageData, ExtractedImages,
     ExtractedInstance, ExtractedInstances, ExtractedLight, ExtractedLights,
     ExtractedMaterial, ExtractedMaterials, ExtractedMesh, ExtractedMeshes,
-    ExtractedSun,
+    ExtractedSkinnedMesh, ExtractedSkinnedMeshes, ExtractedSun,
 };
-use crate::utils::color_to_vec3;
-use crate::{StrolleCamera, StrolleEvent, StrolleSun};
+use crate::utils::{color_to_vec3, render_layers_to_visibility_mask};
+use crate::{MeshHandle, StrolleCamera, StrolleEvent, StrolleSun};
 
 pub(crate) fn meshes(
     mut commands: Commands,
@@ -57,6 +60,154 @@ pub(crate) fn meshes(
     });
 }
 
+/// Extracts poses (i.e. joint matrices and morph-target weights) of entities
+/// that use skinning or morph targets, together with their meshes (once per
+/// mesh); see: [`crate::skinning`].
+///
+/// Poses get extracted again only when they change, so entities that are not
+/// currently animated don't cost anything.
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
+pub(crate) fn skinned_meshes(
+    mut commands: Commands,
+    mut events: Extract<EventReader<AssetEvent<Mesh>>>,
+    entities: Extract<
+        Query<
+            (
+                Entity,
+                &Handle<Mesh>,
+                &GlobalTransform,
+                Option<&SkinnedMesh>,
+                Option<&MeshMorphWeights>,
+            ),
+            Or<(With<SkinnedMesh>, With<MeshMorphWeights>)>,
+        >,
+    >,
+    joints: Extract<Query<&GlobalTransform>>,
+    meshes: Extract<Res<Assets<Mesh>>>,
+    images: Extract<Res<Assets<Image>>>,
+    inverse_bindposes: Extract<Res<Assets<SkinnedMeshInverseBindposes>>>,
+    mut poses: Local<HashMap<Entity, SkinnedMeshPose>>,
+    mut extracted_sources: Local<HashSet<AssetId<Mesh>>>,
+) {
+    let mut sources = Vec::new();
+    let mut removed_sources = Vec::new();
+    let mut changed = Vec::new();
+    let mut alive = HashSet::new();
+
+    for event in events.read() {
+        if let AssetEvent::Modified { id } | AssetEvent::Removed { id } = event
+        {
+            if extracted_sources.remove(id) {
+                removed_sources.push(*id);
+            }
+
+            // Entities using this mesh have to be deformed again, even if
+            // their poses stay the same
+            poses.retain(|_, pose| pose.mesh_handle != *id);
+        }
+    }
+
+    for (entity, mesh_handle, transform, skin, weights) in entities.iter() {
+        alive.insert(entity);
+
+        let Some(mesh) = meshes.get(mesh_handle) else {
+            continue;
+        };
+
+        let joints = if let Some(skin) = skin {
+            let Some(inverse_bindposes) =
+                inverse_bindposes.get(&skin.inverse_bindposes)
+            else {
+                continue;
+            };
+
+            // Joints are given in the world-space, but our mesh is going to be
+            // transformed by the entity's transform anyway, so we have to get
+            // rid of it here
+            let world_to_local = transform.compute_matrix().inverse();
+
+            let joints: Option<Vec<_>> = skin
+                .joints
+                .iter()
+                .zip(inverse_bindposes.iter())
+                .map(|(&joint, inverse_bindpose)| {
+                    let joint = joints.get(joint).ok()?;
+
+                    Some(
+                        world_to_local
+                            * joint.compute_matrix()
+                            * *inverse_bindpose,
+                    )
+                })
+                .collect();
+
+            let Some(joints) = joints else {
+                continue;
+            };
+
+            joints
+        } else {
+            Vec::new()
+        };
+
+        let pose = SkinnedMeshPose {
+            mesh_handle: mesh_handle.id(),
+            joints,
+            weights: weights
+                .map(|weights| weights.weights().to_vec())
+                .unwrap_or_default(),
+        };
+
+        if poses.get(&entity) == Some(&pose) {
+            continue;
+        }
+
+        if !extracted_sources.contains(&pose.mesh_handle) {
+            let Some(source) = DeformableMesh::new(mesh, &images) else {
+                continue;
+            };
+
+            extracted_sources.insert(pose.mesh_handle);
+            sources.push((pose.mesh_handle, source));
+        }
+
+        changed.push(ExtractedSkinnedMesh {
+            handle: entity,
+            mesh_handle: pose.mesh_handle,
+            joints: pose.joints.clone(),
+            weights: pose.weights.clone(),
+        });
+
+        poses.insert(entity, pose);
+    }
+
+    let mut removed = Vec::new();
+
+    poses.retain(|entity, _| {
+        if alive.contains(entity) {
+            true
+        } else {
+            removed.push(*entity);
+            false
+        }
+    });
+
+    commands.insert_resource(ExtractedSkinnedMeshes {
+        sources,
+        removed_sources,
+        changed,
+        removed,
+    });
+}
+
+#[derive(PartialEq)]
+pub(crate) struct SkinnedMeshPose {
+    mesh_handle: AssetId<Mesh>,
+    joints: Vec<Mat4>,
+    weights: Vec<f32>,
+}
+
 pub(crate) fn materials(
     mut commands: Commands,
     mut events: Extract<EventReader<AssetEvent<StandardMaterial>>>,
@@ -199,6 +350,8 @@ pub(crate) fn instances(
                 &GlobalTransform,
                 &InheritedVisibility,
                 Option<&RenderLayers>,
+                Has<SkinnedMesh>,
+                Has<MeshMorphWeights>,
             ),
             Or<(
                 Changed<Handle<Mesh>>,
@@ -206,6 +359,8 @@ pub(crate) fn instances(
                 Changed<GlobalTransform>,
                 Changed<InheritedVisibility>,
                 Changed<RenderLayers>,
+                Added<SkinnedMesh>,
+                Added<MeshMorphWeights>,
             )>,
         >,
     >,
@@ -223,6 +378,8 @@ pub(crate) fn instances(
                 transform,
                 visibility,
                 layers,
+                has_skin,
+                has_morph_weights,
             )| {
                 if !visibility.get() {
                     // TODO inefficient; we should push only if the object was
@@ -231,23 +388,20 @@ pub(crate) fn instances(
                     return None;
                 }
 
-                // TODO this is invalid (but good enough for now); instead, we
-                //      should probably propagate the layers up to the BVH
-                //      leaves and adjust the raytracer to read those
-                if let Some(layers) = layers {
-                    if *layers != RenderLayers::all() {
-                        // TODO inefficient; we should push only if the object
-                        //      was visible before
-                        removed.push(handle);
-                        return None;
-                    }
-                }
+                // Deformed meshes are unique to each entity; see:
+                // `skinned_meshes()`
+                let mesh_handle = if has_skin || has_morph_weights {
+                    MeshHandle::Entity(handle)
+                } else {
+                    MeshHandle::Asset(mesh_handle.id())
+                };
 
                 Some(ExtractedInstance {
                     handle,
-                    mesh_handle: mesh_handle.id(),
+                    mesh_handle,
                     material_handle: material_handle.id(),
                     xform: transform.affine(),
+                    visibility_mask: render_layers_to_visibility_mask(layers),
                 })
             },
         )
@@ -345,6 +499,7 @@ pub(crate) fn cameras(
             &Projection,
             &GlobalTransform,
             Option<&StrolleCamera>,
+            Option<&RenderLayers>,
         )>,
     >,
 ) {
@@ -355,6 +510,7 @@ pub(crate) fn cameras(
         projection,
         transform,
         strolle_camera,
+        layers,
     ) in cameras.iter()
     {
         if !camera.is_active || **camera_render_graph != crate::graph::NAME {
@@ -367,6 +523,8 @@ pub(crate) fn cameras(
             transform: transform.compute_matrix(),
             projection: projection.get_projection_matrix(),
             mode: strolle_camera.map(|camera| camera.mode),
+            light_sampling: strolle_camera.map(|camera| camera.light_sampling),
+            visibility_mask: render_layers_to_visibility_mask(layers),
         });
     }
 }
@@ -8,16 +8,17 @@ use bevy::render::render_resource::PrimitiveTopology;
 use bevy::render::renderer::{RenderDevice, RenderQueue};
 use bevy::render::view::ViewTarget;
 use bevy::utils::hashbrown::hash_map::Entry;
-use bevy::utils::HashSet;
+use bevy::utils::{HashMap, HashSet};
 use strolle as st;
 
+use crate::skinning::{DeformableMesh, DeformedVertices};
 use crate::state::{
     ExtractedCamera, ExtractedImageData, ExtractedImages, ExtractedInstances,
-    ExtractedLights, ExtractedMaterials, ExtractedMeshes, ExtractedSun,
-    SyncedCamera, SyncedState,
+    ExtractedLights, ExtractedMaterials, ExtractedMeshes,
+    ExtractedSkinnedMeshes, ExtractedSun, SyncedCamera, SyncedState,
 };
 use crate::utils::color_to_vec4;
-use crate::EngineResource;
+use crate::{EngineResource, MeshHandle};
 
 pub(crate) fn meshes(
     mut engine: ResMut<EngineResource>,
@@ -28,96 +29,161 @@ pub(crate) fn meshes(
         .iter()
         .chain(meshes.changed.iter().map(|mesh| &mesh.handle))
     {
-        engine.remove_mesh(handle);
+        engine.remove_mesh(&MeshHandle::Asset(*handle));
     }
 
     for mesh in mem::take(&mut meshes.changed) {
-        if mesh.mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+        insert_mesh(
+            &mut engine,
+            MeshHandle::Asset(mesh.handle),
+            &mesh.mesh,
+            None,
+        );
+    }
+}
+
+pub(crate) fn skinned_meshes(
+    mut engine: ResMut<EngineResource>,
+    mut meshes: ResMut<ExtractedSkinnedMeshes>,
+    mut sources: Local<HashMap<AssetId<Mesh>, DeformableMesh>>,
+) {
+    for handle in mem::take(&mut meshes.removed) {
+        engine.remove_mesh(&MeshHandle::Entity(handle));
+    }
+
+    for handle in mem::take(&mut meshes.removed_sources) {
+        sources.remove(&handle);
+    }
+
+    sources.extend(mem::take(&mut meshes.sources));
+
+    // Since deformed meshes keep their number of triangles, re-inserting them
+    // just updates their triangles and refits their BVHs
+    for mesh in mem::take(&mut meshes.changed) {
+        let Some(source) = sources.get(&mesh.mesh_handle) else {
             continue;
+        };
+
+        let vertices = source.deform(&mesh.weights, &mesh.joints);
+
+        insert_mesh(
+            &mut engine,
+            MeshHandle::Entity(mesh.handle),
+            source.mesh(),
+            Some(&vertices),
+        );
+    }
+}
+
+fn insert_mesh(
+    engine: &mut EngineResource,
+    handle: MeshHandle,
+    mesh: &Mesh,
+    vertices: Option<&DeformedVertices>,
+) {
+    if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+        return;
+    }
+
+    let prepared = match prepare_mesh(mesh, vertices) {
+        Ok(prepared) => prepared,
+
+        Err(err) => {
+            error!("Couldn't prepare mesh {:?}: {}", handle, err);
+            return;
         }
+    };
 
-        let mesh_positions = mesh
-            .mesh
-            .attribute(Mesh::ATTRIBUTE_POSITION)
-            .and_then(VertexAttributeValues::as_float3)
-            .unwrap_or_else(|| {
-                panic!("mesh {:?} has no positions", mesh.handle);
-            });
-
-        let mesh_normals = mesh
-            .mesh
-            .attribute(Mesh::ATTRIBUTE_NORMAL)
-            .and_then(VertexAttributeValues::as_float3)
-            .unwrap_or_else(|| {
-                panic!("mesh {:?} has no normals", mesh.handle);
-            });
-
-        let mesh_uvs = mesh
-            .mesh
-            .attribute(Mesh::ATTRIBUTE_UV_0)
-            .map(|uvs| match uvs {
-                VertexAttributeValues::Float32x2(uvs) => uvs,
-                _ => {
-                    panic!(
-                        "mesh {:?} uses unsupported format for UVs",
-                        mesh.handle
-                    )
-                }
-            })
-            .map(|uvs| uvs.as_slice())
-            .unwrap_or(&[]);
-
-        let mesh_tans = mesh
-            .mesh
-            .attribute(Mesh::ATTRIBUTE_TANGENT)
-            .map(|uvs| match uvs {
-                VertexAttributeValues::Float32x4(tangents) => tangents,
-                _ => panic!(
-                    "mesh {:?} uses unsupported format for tangents",
-                    mesh.handle
-                ),
-            })
-            .map(|tangents| tangents.as_slice())
-            .unwrap_or(&[]);
-
-        let mesh_indices: Vec<_> = mesh
-            .mesh
-            .indices()
-            .unwrap_or_else(|| {
-                panic!("mesh {:?} has no indices", mesh.handle);
-            })
-            .iter()
-            .collect();
-
-        let mesh_triangles: Vec<_> = mesh_indices
-            .chunks(3)
-            .map(|vs| {
-                let position0 = mesh_positions[vs[0]];
-                let position1 = mesh_positions[vs[1]];
-                let position2 = mesh_positions[vs[2]];
-
-                let normal0 = mesh_normals[vs[0]];
-                let normal1 = mesh_normals[vs[1]];
-                let normal2 = mesh_normals[vs[2]];
-
-                let uv0 = mesh_uvs.get(vs[0]).copied().unwrap_or_default();
-                let uv1 = mesh_uvs.get(vs[1]).copied().unwrap_or_default();
-                let uv2 = mesh_uvs.get(vs[2]).copied().unwrap_or_default();
-
-                let tan0 = mesh_tans.get(vs[0]).copied().unwrap_or_default();
-                let tan1 = mesh_tans.get(vs[1]).copied().unwrap_or_default();
-                let tan2 = mesh_tans.get(vs[2]).copied().unwrap_or_default();
-
-                st::MeshTriangle::default()
-                    .with_positions([position0, position1, position2])
-                    .with_normals([normal0, normal1, normal2])
-                    .with_uvs([uv0, uv1, uv2])
-                    .with_tangents([tan0, tan1, tan2])
-            })
-            .collect();
-
-        engine.insert_mesh(mesh.handle, st::Mesh::new(mesh_triangles));
+    if let Err(err) = engine.try_insert_mesh(handle, prepared) {
+        error!("Couldn't insert mesh {:?}: {}", handle, err);
+
+        // Make sure we don't keep rendering the mesh's previous version
+        engine.remove_mesh(&handle);
+    }
+}
+
+/// Converts given mesh into Strolle's one, optionally replacing its vertices
+/// with deformed ones; see: [`DeformableMesh`].
+fn prepare_mesh(
+    mesh: &Mesh,
+    vertices: Option<&DeformedVertices>,
+) -> Result<st::Mesh, st::StrolleError> {
+    let invalid = |reason: &str| st::StrolleError::InvalidMesh(reason.into());
+
+    let mesh_positions = mesh
+        .attribute(Mesh::ATTRIBUTE_POSITION)
+        .and_then(VertexAttributeValues::as_float3)
+        .ok_or_else(|| invalid("mesh has no positions"))?;
+
+    let mesh_normals = mesh
+        .attribute(Mesh::ATTRIBUTE_NORMAL)
+        .and_then(VertexAttributeValues::as_float3)
+        .ok_or_else(|| invalid("mesh has no normals"))?;
+
+    let mesh_uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+        Some(VertexAttributeValues::Float32x2(uvs)) => uvs.as_slice(),
+        Some(_) => return Err(invalid("mesh uses unsupported format for UVs")),
+        None => &[],
+    };
+
+    let mesh_tans = match mesh.attribute(Mesh::ATTRIBUTE_TANGENT) {
+        Some(VertexAttributeValues::Float32x4(tangents)) => tangents.as_slice(),
+        Some(_) => {
+            return Err(invalid("mesh uses unsupported format for tangents"));
+        }
+        None => &[],
+    };
+
+    let (mesh_positions, mesh_normals, mesh_tans) = match vertices {
+        Some(vertices) => (
+            vertices.positions.as_slice(),
+            vertices.normals.as_slice(),
+            vertices.tangents.as_slice(),
+        ),
+        None => (mesh_positions, mesh_normals, mesh_tans),
+    };
+
+    let mesh_indices: Vec<_> = mesh
+        .indices()
+        .ok_or_else(|| invalid("mesh has no indices"))?
+        .iter()
+        .collect();
+
+    if mesh_indices
+        .iter()
+        .any(|&idx| idx >= mesh_positions.len() || idx >= mesh_normals.len())
+    {
+        return Err(invalid("mesh has out-of-bounds indices"));
     }
+
+    let mesh_triangles = mesh_indices
+        .chunks_exact(3)
+        .map(|vs| {
+            let position0 = mesh_positions[vs[0]];
+            let position1 = mesh_positions[vs[1]];
+            let position2 = mesh_positions[vs[2]];
+
+            let normal0 = mesh_normals[vs[0]];
+            let normal1 = mesh_normals[vs[1]];
+            let normal2 = mesh_normals[vs[2]];
+
+            let uv0 = mesh_uvs.get(vs[0]).copied().unwrap_or_default();
+            let uv1 = mesh_uvs.get(vs[1]).copied().unwrap_or_default();
+            let uv2 = mesh_uvs.get(vs[2]).copied().unwrap_or_default();
+
+            let tan0 = mesh_tans.get(vs[0]).copied().unwrap_or_default();
+            let tan1 = mesh_tans.get(vs[1]).copied().unwrap_or_default();
+            let tan2 = mesh_tans.get(vs[2]).copied().unwrap_or_default();
+
+            st::MeshTriangle::default()
+                .with_positions([position0, position1, position2])
+                .with_normals([normal0, normal1, normal2])
+                .with_uvs([uv0, uv1, uv2])
+                .with_tangents([tan0, tan1, tan2])
+        })
+        .collect();
+
+    Ok(st::Mesh::new(mesh_triangles))
 }
 
 pub(crate) fn materials(
@@ -235,14 +301,20 @@ pub(crate) fn instances(
     }
 
     for entry in mem::take(&mut instances.changed) {
-        engine.insert_instance(
-            entry.handle,
-            st::Instance::new(
-                entry.mesh_handle,
-                entry.material_handle,
-                entry.xform,
-            ),
-        );
+        let instance = st::Instance::new(
+            entry.mesh_handle,
+            entry.material_handle,
+            entry.xform,
+        )
+        .with_visibility_mask(entry.visibility_mask);
+
+        if let Err(err) = engine.try_insert_instance(entry.handle, instance) {
+            error!("Couldn't insert instance {:?}: {}", entry.handle, err);
+
+            // Make sure we don't keep rendering the instance's previous
+            // version
+            engine.remove_instance(&entry.handle);
+        }
     }
 }
 
@@ -311,6 +383,8 @@ pub(crate) fn cameras(
 
             transform: ext_camera.transform,
             projection: ext_camera.projection,
+            visibility_mask: ext_camera.visibility_mask,
+            light_sampling: ext_camera.light_sampling.unwrap_or_default(),
         };
 
         match state.cameras.entry(entity) {
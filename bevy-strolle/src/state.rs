@@ -4,7 +4,8 @@ use bevy::render::renderer::{RenderDevice, RenderQueue};
 use bevy::utils::HashMap;
 use strolle as st;
 
-use crate::EngineParams;
+use crate::skinning::DeformableMesh;
+use crate::{EngineParams, MeshHandle};
 
 #[derive(Default, Resource)]
 pub(crate) struct SyncedState {
@@ -45,6 +46,25 @@ pub(crate) struct ExtractedMesh {
     pub mesh: Mesh,
 }
 
+#[derive(Debug, Resource)]
+pub(crate) struct ExtractedSkinnedMeshes {
+    /// Meshes used by skinned entities that haven't been extracted before (or
+    /// that have been modified since).
+    pub sources: Vec<(AssetId<Mesh>, DeformableMesh)>,
+    pub removed_sources: Vec<AssetId<Mesh>>,
+
+    pub changed: Vec<ExtractedSkinnedMesh>,
+    pub removed: Vec<Entity>,
+}
+
+#[derive(Debug)]
+pub(crate) struct ExtractedSkinnedMesh {
+    pub handle: Entity,
+    pub mesh_handle: AssetId<Mesh>,
+    pub joints: Vec<Mat4>,
+    pub weights: Vec<f32>,
+}
+
 #[derive(Debug, Resource)]
 pub(crate) struct ExtractedMaterials {
     pub changed: Vec<ExtractedMaterial>,
@@ -86,9 +106,10 @@ pub(crate) struct ExtractedInstances {
 #[derive(Debug)]
 pub(crate) struct ExtractedInstance {
     pub handle: Entity,
-    pub mesh_handle: AssetId<Mesh>,
+    pub mesh_handle: MeshHandle,
     pub material_handle: AssetId<StandardMaterial>,
     pub xform: Affine3A,
+    pub visibility_mask: u32,
 }
 
 #[derive(Debug, Resource)]
@@ -108,6 +129,8 @@ pub(crate) struct ExtractedCamera {
     pub transform: Mat4,
     pub projection: Mat4,
     pub mode: Option<st::CameraMode>,
+    pub light_sampling: Option<st::LightSampling>,
+    pub visibility_mask: u32,
 }
 
 #[derive(Debug, Resource)]
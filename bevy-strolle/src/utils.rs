@@ -1,5 +1,6 @@
 use bevy::math::{vec3, vec4};
 use bevy::prelude::*;
+use bevy::render::view::RenderLayers;
 
 pub fn color_to_vec3(color: Color) -> Vec3 {
     let [r, g, b, _] = color.as_linear_rgba_f32();
@@ -12,3 +13,13 @@ pub fn color_to_vec4(color: Color) -> Vec4 {
 
     vec4(r, g, b, a)
 }
+
+/// Converts render layers into Strolle's visibility mask; entities without
+/// the component are - same as in Bevy - located on the default layer.
+pub fn render_layers_to_visibility_mask(layers: Option<&RenderLayers>) -> u32 {
+    layers
+        .copied()
+        .unwrap_or_default()
+        .iter()
+        .fold(0, |mask, layer| mask | (1 << layer))
+}
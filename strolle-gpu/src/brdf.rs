@@ -4,7 +4,7 @@ use glam::{vec3, Vec3, Vec4Swizzles};
 #[cfg(target_arch = "spirv")]
 use spirv_std::num_traits::Float;
 
-use crate::{F32Ext, GBufferEntry, Hit, Vec3Ext, WhiteNoise};
+use crate::{F32Ext, GBufferEntry, Hit, RayKind, Vec3Ext, WhiteNoise};
 
 #[derive(Clone, Copy)]
 pub struct DiffuseBrdf<'a> {
@@ -31,6 +31,7 @@ impl<'a> DiffuseBrdf<'a> {
             direction: wnoise.sample_hemisphere(self.gbuffer.normal),
             throughput: self.gbuffer.base_color.xyz()
                 * (1.0 - self.gbuffer.metallic),
+            kind: RayKind::INDIRECT,
         }
     }
 }
@@ -170,6 +171,7 @@ impl<'a> SpecularBrdf<'a> {
                     break BrdfSample {
                         direction: l,
                         throughput: value.radiance / value.probability,
+                        kind: RayKind::REFLECTION,
                     };
                 }
             }
@@ -226,6 +228,10 @@ pub struct BrdfValue {
 pub struct BrdfSample {
     pub direction: Vec3,
     pub throughput: Vec3,
+
+    /// Kind of ray that should be traced in the sampled direction - i.e.
+    /// whether this sample comes from the diffuse or the specular lobe.
+    pub kind: RayKind,
 }
 
 impl BrdfSample {
@@ -233,6 +239,7 @@ impl BrdfSample {
         Self {
             direction: Default::default(),
             throughput: Default::default(),
+            kind: Default::default(),
         }
     }
 
@@ -266,3 +273,41 @@ fn ggx_schlick_masking_term(n_dot_l: f32, n_dot_v: f32, roughness: f32) -> f32 {
 fn f_schlick_vec(f0: Vec3, f90: f32, v_dot_h: f32) -> Vec3 {
     f0 + (f90 - f0) * (1.0 - v_dot_h).max(0.001).powf(5.0)
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::{UVec2, Vec4};
+
+    use super::*;
+
+    #[test]
+    fn layered_sample_kind() {
+        let hit = |metallic| Hit {
+            origin: Vec3::Z,
+            direction: -Vec3::Z,
+            point: Vec3::ZERO,
+            gbuffer: GBufferEntry {
+                base_color: Vec4::ONE,
+                normal: Vec3::Z,
+                metallic,
+                roughness: 0.5,
+                reflectance: 0.5,
+                ..Default::default()
+            },
+        };
+
+        let mut wnoise = WhiteNoise::new(1234, UVec2::ZERO);
+
+        // Purely diffuse surfaces bounce indirect rays, purely metallic ones
+        // bounce reflections
+        for _ in 0..16 {
+            let sample = LayeredBrdf::sample(&mut wnoise, hit(0.0));
+
+            assert!(sample.kind == RayKind::INDIRECT);
+
+            let sample = LayeredBrdf::sample(&mut wnoise, hit(1.0));
+
+            assert!(sample.is_invalid() || sample.kind == RayKind::REFLECTION);
+        }
+    }
+}
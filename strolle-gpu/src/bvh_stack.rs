@@ -0,0 +1,83 @@
+use spirv_std::arch::IndexUnchecked;
+
+use crate::{BvhStack, BVH_STACK_SIZE};
+
+/// Stack of nodes yet-to-be-visited when traversing the BVH; see:
+/// [`crate::Ray::traverse()`].
+pub trait BvhTraversalStack {
+    fn push(&mut self, ptr: u32);
+
+    /// Pops the most recently pushed pointer; must be called only if the stack
+    /// is not empty.
+    fn pop(&mut self) -> u32;
+
+    fn is_empty(&self) -> bool;
+}
+
+/// Stack living in the per-workgroup memory, used by the shaders; see:
+/// [`BvhStack`].
+pub struct WorkgroupBvhStack<'a> {
+    stack: BvhStack<'a>,
+
+    /// Where this particular thread's stack starts at
+    begins_at: usize,
+
+    /// Index into the `stack` array; our stack spans from `begins_at` up to +
+    /// `BVH_STACK_SIZE` items
+    ptr: usize,
+}
+
+impl<'a> WorkgroupBvhStack<'a> {
+    pub fn new(stack: BvhStack<'a>, local_idx: u32) -> Self {
+        let begins_at = (local_idx as usize) * BVH_STACK_SIZE;
+
+        Self {
+            stack,
+            begins_at,
+            ptr: begins_at,
+        }
+    }
+}
+
+impl BvhTraversalStack for WorkgroupBvhStack<'_> {
+    fn push(&mut self, ptr: u32) {
+        // Trees get serialized so that this never happens (see: `strolle`'s
+        // BVH serializer), but if it does, it's better to skip some nodes than
+        // to overwrite other threads' stacks
+        if self.ptr >= self.begins_at + BVH_STACK_SIZE {
+            return;
+        }
+
+        unsafe {
+            *self.stack.index_unchecked_mut(self.ptr) = ptr;
+        }
+
+        self.ptr += 1;
+    }
+
+    fn pop(&mut self) -> u32 {
+        self.ptr -= 1;
+
+        unsafe { *self.stack.index_unchecked(self.ptr) }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ptr <= self.begins_at
+    }
+}
+
+/// Stack used when traversing the BVH on the CPU.
+#[cfg(not(target_arch = "spirv"))]
+impl BvhTraversalStack for Vec<u32> {
+    fn push(&mut self, ptr: u32) {
+        Vec::push(self, ptr);
+    }
+
+    fn pop(&mut self) -> u32 {
+        Vec::pop(self).expect("stack is empty")
+    }
+
+    fn is_empty(&self) -> bool {
+        Vec::is_empty(self)
+    }
+}
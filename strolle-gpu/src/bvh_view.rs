@@ -1,12 +1,68 @@
-use glam::Vec4;
+use glam::{vec3, Affine3A, Vec3, Vec4, Vec4Swizzles};
 use spirv_std::arch::IndexUnchecked;
 
+use crate::Affine3AExt;
+
+/// View into the BVH buffer.
+///
+/// The buffer contains a two-level acceleration structure: a top-level BVH
+/// built over instances, where each instance points at a bottom-level BVH
+/// built over its mesh's triangles (in object-space, so that all instances of
+/// the same mesh share their triangles and bottom-level BVH).
+///
+/// Layout:
+///
+/// - `[0]` is the header, `x` containing pointer to the top-level BVH's root
+///   (or [`Self::SENTINEL`] if there are no instances),
+///
+/// - internal node is `4 x Vec4` - `[left_min, op]`, `[left_max, right_ptr]`,
+///   `[right_min, _]`, `[right_max, _]`, with the left child located right
+///   after the node,
+///
+/// - wide internal node (used instead of the binary one when the BVH is built
+///   as a 4-ary tree) is `7 x Vec4` - `[child1_ptr, child2_ptr, child3_ptr,
+///   op]`, followed by bounds of all four children stored axis-by-axis
+///   (`min_x`, `min_y`, `min_z`, `max_x`, `max_y`, `max_z`), with the first
+///   child located right after the node; missing children have their pointers
+///   set to [`Self::SENTINEL`] and bounds set to infinity,
+///
+/// - quantized wide internal node is `4 x Vec4` (for 8-bit quantization) or
+///   `5 x Vec4` (for 16-bit quantization) - `[child1_ptr, child2_ptr,
+///   child3_ptr, op]`, `[origin, scales]`, followed by the quantized bounds
+///   stored axis-by-axis (same as above), with values for consecutive children
+///   packed into consecutive bits of `u32`s; `scales` contains biased
+///   exponents of power-of-two scales for each axis (8 bits per axis) and
+///   children's bounds are equal to `origin + quantized_value * scale`,
+///
+/// - instance (top-level leaf) is `5 x Vec4` - `[flags, blas_ptr, material_id,
+///   op]`, followed by the instance's world-to-object transformation, followed
+///   by `[instance_idx, visibility_mask, _, _]`,
+///
+/// - triangle (bottom-level leaf) is `1 x Vec4` - `[flags, triangle_id, _,
+///   op]`,
+///
+/// ... where flags' bit 0 says whether there are more entries directly
+/// following this one (i.e. whether it's a leaf containing multiple entries)
+/// and - for instances - bit 1 says whether instance's material uses alpha
+/// blending, while bits 2..6 say which kinds of rays can hit the instance
+/// (see: [`crate::RayKind`]).
 #[derive(Clone, Copy)]
 pub struct BvhView<'a> {
     buffer: &'a [Vec4],
 }
 
 impl<'a> BvhView<'a> {
+    pub const OP_INTERNAL: u32 = 0;
+    pub const OP_TRIANGLE: u32 = 1;
+    pub const OP_INSTANCE: u32 = 2;
+    pub const OP_INTERNAL_WIDE: u32 = 3;
+    pub const OP_INTERNAL_WIDE_Q8: u32 = 4;
+    pub const OP_INTERNAL_WIDE_Q16: u32 = 5;
+
+    /// Special pointer that, when pushed on the stack, denotes the end of a
+    /// bottom-level BVH.
+    pub const SENTINEL: u32 = u32::MAX;
+
     pub fn new(buffer: &'a [Vec4]) -> Self {
         Self { buffer }
     }
@@ -14,4 +70,142 @@ impl<'a> BvhView<'a> {
     pub fn get(&self, ptr: u32) -> Vec4 {
         unsafe { *self.buffer.index_unchecked(ptr as usize) }
     }
+
+    pub fn root(&self) -> u32 {
+        self.get(0).x.to_bits()
+    }
+
+    /// Returns whether given op denotes a wide internal node (quantized or
+    /// not).
+    pub fn is_wide(op: u32) -> bool {
+        op == Self::OP_INTERNAL_WIDE
+            || op == Self::OP_INTERNAL_WIDE_Q8
+            || op == Self::OP_INTERNAL_WIDE_Q16
+    }
+
+    /// Returns the number of `Vec4`s occupied by wide internal node with given
+    /// op.
+    pub fn wide_node_size(op: u32) -> u32 {
+        if op == Self::OP_INTERNAL_WIDE {
+            7
+        } else if op == Self::OP_INTERNAL_WIDE_Q8 {
+            4
+        } else {
+            5
+        }
+    }
+
+    /// Returns bounds of given child of the wide internal node located at
+    /// given pointer.
+    ///
+    /// Quantized bounds are decoded conservatively, i.e. the returned box is
+    /// never smaller than the original one.
+    pub fn wide_node_child(
+        &self,
+        ptr: u32,
+        op: u32,
+        child_idx: u32,
+    ) -> (Vec3, Vec3) {
+        if op == Self::OP_INTERNAL_WIDE {
+            let min = vec3(
+                component(self.get(ptr + 1), child_idx),
+                component(self.get(ptr + 2), child_idx),
+                component(self.get(ptr + 3), child_idx),
+            );
+
+            let max = vec3(
+                component(self.get(ptr + 4), child_idx),
+                component(self.get(ptr + 5), child_idx),
+                component(self.get(ptr + 6), child_idx),
+            );
+
+            (min, max)
+        } else {
+            let d1 = self.get(ptr + 1);
+            let origin = d1.xyz();
+            let scales = d1.w.to_bits();
+
+            let scale = vec3(
+                f32::from_bits((scales & 0xff) << 23),
+                f32::from_bits(((scales >> 8) & 0xff) << 23),
+                f32::from_bits(((scales >> 16) & 0xff) << 23),
+            );
+
+            let min = origin
+                + scale
+                    * vec3(
+                        self.quantized(ptr, op, 0, child_idx),
+                        self.quantized(ptr, op, 1, child_idx),
+                        self.quantized(ptr, op, 2, child_idx),
+                    );
+
+            let max = origin
+                + scale
+                    * vec3(
+                        self.quantized(ptr, op, 3, child_idx),
+                        self.quantized(ptr, op, 4, child_idx),
+                        self.quantized(ptr, op, 5, child_idx),
+                    );
+
+            // Since scales are powers of two, multiplications above are exact,
+            // but additions might've gotten rounded inwards - so let's expand
+            // the box by an ulp, just in case
+            (
+                min - min.abs() * f32::EPSILON,
+                max + max.abs() * f32::EPSILON,
+            )
+        }
+    }
+
+    /// Returns quantized value located at given row (`min_x`, `min_y`, ...,
+    /// `max_z`) for given child of the quantized wide node.
+    fn quantized(&self, ptr: u32, op: u32, row: u32, child_idx: u32) -> f32 {
+        let (word_idx, bits) = if op == Self::OP_INTERNAL_WIDE_Q8 {
+            (row, 8)
+        } else {
+            (2 * row + child_idx / 2, 16)
+        };
+
+        let word =
+            component(self.get(ptr + 2 + word_idx / 4), word_idx % 4).to_bits();
+
+        let shift = (child_idx * bits) % 32;
+        let mask = (1 << bits) - 1;
+
+        ((word >> shift) & mask) as f32
+    }
+
+    /// Returns world-to-object transformation of instance located at given
+    /// pointer.
+    pub fn instance_xform(&self, ptr: u32) -> Affine3A {
+        Affine3A::decode([
+            self.get(ptr + 1),
+            self.get(ptr + 2),
+            self.get(ptr + 3),
+        ])
+    }
+
+    /// Returns index of instance located at given pointer, i.e. the index
+    /// under which the instance has been given to the top-level BVH builder.
+    pub fn instance_idx(&self, ptr: u32) -> u32 {
+        self.get(ptr + 4).x.to_bits()
+    }
+
+    /// Returns visibility mask of instance located at given pointer; rays skip
+    /// instances whose masks don't overlap with theirs.
+    pub fn instance_visibility_mask(&self, ptr: u32) -> u32 {
+        self.get(ptr + 4).y.to_bits()
+    }
+}
+
+fn component(vec: Vec4, idx: u32) -> f32 {
+    if idx == 0 {
+        vec.x
+    } else if idx == 1 {
+        vec.y
+    } else if idx == 2 {
+        vec.z
+    } else {
+        vec.w
+    }
 }
@@ -3,7 +3,7 @@ use glam::{vec2, IVec2, Mat4, UVec2, Vec2, Vec3, Vec4, Vec4Swizzles};
 #[cfg(target_arch = "spirv")]
 use spirv_std::num_traits::Float;
 
-use crate::Ray;
+use crate::{Ray, RayKind};
 
 #[repr(C)]
 #[derive(Clone, Copy, Default, Pod, Zeroable)]
@@ -91,6 +91,8 @@ impl Camera {
         let near_plane = self.ndc_to_world.project_point3(ndc.extend(1.0));
 
         Ray::new(near_plane, (far_plane - near_plane).normalize())
+            .with_visibility_mask(self.visibility_mask())
+            .with_kind(RayKind::CAMERA)
     }
 
     /// Returns camera's approximate origin, without taking into account the
@@ -105,6 +107,18 @@ impl Camera {
         self.data.x.to_bits() + self.data.y.to_bits()
     }
 
+    /// Returns mask of instances visible to this camera; see:
+    /// [`Ray::with_visibility_mask()`].
+    pub fn visibility_mask(&self) -> u32 {
+        self.data.z.to_bits()
+    }
+
+    /// Returns whether direct lighting should pick its candidates from the
+    /// light grid instead of the light tree; see: [`crate::LightGrid`].
+    pub fn uses_light_grid(&self) -> bool {
+        self.data.w.to_bits() == 1
+    }
+
     pub fn is_eq(&self, rhs: &Self) -> bool {
         if !self
             .projection_view
@@ -117,6 +131,10 @@ impl Camera {
             return false;
         }
 
+        if self.visibility_mask() != rhs.visibility_mask() {
+            return false;
+        }
+
         true
     }
 }
@@ -6,11 +6,14 @@
 
 mod atmosphere;
 mod brdf;
+mod bvh_stack;
 mod bvh_view;
 mod camera;
 mod gbuffer;
 mod hit;
 mod light;
+mod light_grid;
+mod light_tree;
 mod lights;
 mod material;
 mod materials;
@@ -18,6 +21,7 @@ mod noise;
 mod normal;
 mod passes;
 mod ray;
+mod ray_query;
 mod reprojection;
 mod reservoir;
 mod surface;
@@ -28,11 +32,14 @@ mod world;
 
 pub use self::atmosphere::*;
 pub use self::brdf::*;
+pub use self::bvh_stack::*;
 pub use self::bvh_view::*;
 pub use self::camera::*;
 pub use self::gbuffer::*;
 pub use self::hit::*;
 pub use self::light::*;
+pub use self::light_grid::*;
+pub use self::light_tree::*;
 pub use self::lights::*;
 pub use self::material::*;
 pub use self::materials::*;
@@ -40,6 +47,7 @@ pub use self::noise::*;
 pub use self::normal::*;
 pub use self::passes::*;
 pub use self::ray::*;
+pub use self::ray_query::*;
 pub use self::reprojection::*;
 pub use self::reservoir::*;
 pub use self::surface::*;
@@ -67,9 +75,16 @@ pub type BvhStack<'a> = &'a mut [u32; BVH_STACK_SIZE * 8 * 8];
 
 /// Maximum stack size per each workgroup-thread when traversing the BVH.
 ///
-/// Affects the maximum size of BVH tree (it must not grow larger than
-/// `2 ^ BVH_STACK_SIZE`).
-pub const BVH_STACK_SIZE: usize = 24;
+/// Traversal pushes one entry per each binary node and up to three entries per
+/// each wide node it descends through, plus up to two entries when going from
+/// the top-level into a bottom-level tree - so what's limited here is not the
+/// number of nodes, but the depth of the trees (the top-level and bottom-level
+/// trees combined).
+///
+/// `strolle` computes this worst-case depth when serializing trees and falls
+/// back to binary nodes for trees that wouldn't fit; pushes that don't fit
+/// anyway get dropped, see: [`WorkgroupBvhStack`].
+pub const BVH_STACK_SIZE: usize = 32;
 
 /// Golden angle, used for spatial filters.
 pub const GOLDEN_ANGLE: f32 = 2.39996;
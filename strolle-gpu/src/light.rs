@@ -1,50 +1,104 @@
 use core::f32::consts::PI;
 
 use bytemuck::{Pod, Zeroable};
-use glam::{vec2, vec4, Vec2, Vec3, Vec4, Vec4Swizzles};
+use glam::{vec2, vec3, vec4, Vec2, Vec3, Vec4, Vec4Swizzles};
 #[cfg(target_arch = "spirv")]
 use spirv_std::num_traits::Float;
 
-use crate::{DiffuseBrdf, F32Ext, Hit, Normal, Ray, WhiteNoise};
+use crate::{
+    DiffuseBrdf, F32Ext, Hit, Normal, Ray, RayKind, Vec3Ext, WhiteNoise,
+};
 
 #[repr(C)]
 #[derive(Clone, Copy, Pod, Zeroable)]
 #[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
 pub struct Light {
-    /// x - position x
-    /// y - position y
-    /// z - position z
-    /// w - radius
+    /// x - position x (or, if it's a directional light: direction x; if it's
+    ///     a triangle light: first vertex x)
+    /// y - position y (or, if it's a directional light: direction y; if it's
+    ///     a triangle light: first vertex y)
+    /// z - position z (or, if it's a directional light: direction z; if it's
+    ///     a triangle light: first vertex z)
+    /// w - radius (or, if it's a directional light: angular radius; if it's a
+    ///     rect light: radius of the sphere bounding the rect; if it's a
+    ///     triangle light: radius of the sphere around the first vertex that
+    ///     bounds the triangle)
     pub d0: Vec4,
 
     /// x - color r
     /// y - color g
     /// z - color b
-    /// w - range
+    /// w - range (or, if it's a triangle light: (as u32) visibility mask of
+    ///     the instance the triangle belongs to)
     pub d1: Vec4,
 
-    /// x - (as u32) light type: 0 - point light, 1 - spot light
-    /// y - if it's a spot light: direction
-    /// z - if it's a spot light: direction
-    /// w - if it's a spot light: angle
+    /// x - (as u32) light type: 0 - point light, 1 - spot light, 2 -
+    ///     directional light, 3 - rect light, 4 - disk light, 5 - triangle
+    ///     light
+    /// y - if it's a spot light: direction; if it's an area light: normal; if
+    ///     it's a triangle light: second vertex x
+    /// z - if it's a spot light: direction; if it's an area light: normal; if
+    ///     it's a triangle light: second vertex y
+    /// w - if it's a spot light: angle; if it's a rect light: half-height; if
+    ///     it's a triangle light: second vertex z
     pub d2: Vec4,
+
+    /// x - if it's a rect light: tangent x; if it's a triangle light: third
+    ///     vertex x
+    /// y - if it's a rect light: tangent y; if it's a triangle light: third
+    ///     vertex y
+    /// z - if it's a rect light: tangent z; if it's a triangle light: third
+    ///     vertex z
+    /// w - if it's a rect light: half-width; if it's a triangle light:
+    ///     cumulative probability of picking this or any of the preceding
+    ///     triangle lights
+    pub d3: Vec4,
 }
 
 impl Light {
     pub const TYPE_POINT: u32 = 0;
     pub const TYPE_SPOT: u32 = 1;
-
-    pub fn sun(position: Vec3, color: Vec3) -> Self {
+    pub const TYPE_DIRECTIONAL: u32 = 2;
+    pub const TYPE_RECT: u32 = 3;
+    pub const TYPE_DISK: u32 = 4;
+    pub const TYPE_TRIANGLE: u32 = 5;
+
+    /// Creates a directional light shining from given direction, with given
+    /// angular diameter (in radians).
+    pub fn sun(direction: Vec3, angular_size: f32, color: Vec3) -> Self {
         Self {
-            // TODO incorrect
-            d0: position.extend(25.0),
+            d0: direction.normalize().extend(0.5 * angular_size),
             d1: color.extend(f32::INFINITY),
             d2: vec4(
-                f32::from_bits(Self::TYPE_POINT),
+                f32::from_bits(Self::TYPE_DIRECTIONAL),
                 Default::default(),
                 Default::default(),
                 Default::default(),
             ),
+            d3: Default::default(),
+        }
+    }
+
+    /// Creates a light out of an emissive triangle (given in world-space);
+    /// `cdf` is the cumulative probability used to pick triangle lights, see:
+    /// [`crate::LightsView::sample_triangle()`].
+    ///
+    /// Triangle lights emit light only for cameras that see the instance the
+    /// triangle belongs to, see: [`Self::triangle_visibility_mask()`].
+    pub fn triangle(
+        positions: [Vec3; 3],
+        color: Vec3,
+        cdf: f32,
+        visibility_mask: u32,
+    ) -> Self {
+        let [v0, v1, v2] = positions;
+        let radius = v0.distance(v1).max(v0.distance(v2));
+
+        Self {
+            d0: v0.extend(radius),
+            d1: color.extend(f32::from_bits(visibility_mask)),
+            d2: vec4(f32::from_bits(Self::TYPE_TRIANGLE), v1.x, v1.y, v1.z),
+            d3: v2.extend(cdf),
         }
     }
 
@@ -68,6 +122,73 @@ impl Light {
         self.d2.x.to_bits() == Self::TYPE_POINT
     }
 
+    pub fn is_directional(&self) -> bool {
+        self.d2.x.to_bits() == Self::TYPE_DIRECTIONAL
+    }
+
+    /// Returns direction towards the light, assuming it's a directional one.
+    pub fn direction(&self) -> Vec3 {
+        self.d0.xyz()
+    }
+
+    /// Returns angular radius of the light, assuming it's a directional one.
+    pub fn angular_radius(&self) -> f32 {
+        self.d0.w
+    }
+
+    pub fn is_rect(&self) -> bool {
+        self.d2.x.to_bits() == Self::TYPE_RECT
+    }
+
+    pub fn is_disk(&self) -> bool {
+        self.d2.x.to_bits() == Self::TYPE_DISK
+    }
+
+    /// Returns normal of the light, assuming it's an area light; area lights
+    /// emit light only towards the side their normal points to.
+    pub fn area_normal(&self) -> Vec3 {
+        Normal::decode(self.d2.yz())
+    }
+
+    /// Returns direction of rect light's width, assuming it's a rect light.
+    pub fn rect_tangent(&self) -> Vec3 {
+        self.d3.xyz()
+    }
+
+    /// Returns direction of rect light's height, assuming it's a rect light.
+    pub fn rect_bitangent(&self) -> Vec3 {
+        self.area_normal().cross(self.rect_tangent())
+    }
+
+    pub fn rect_half_width(&self) -> f32 {
+        self.d3.w
+    }
+
+    pub fn rect_half_height(&self) -> f32 {
+        self.d2.w
+    }
+
+    pub fn is_triangle(&self) -> bool {
+        self.d2.x.to_bits() == Self::TYPE_TRIANGLE
+    }
+
+    /// Returns vertices of the light, assuming it's a triangle light.
+    pub fn triangle_positions(&self) -> [Vec3; 3] {
+        [self.d0.xyz(), self.d2.yzw(), self.d3.xyz()]
+    }
+
+    /// Returns cumulative probability of picking this light, assuming it's a
+    /// triangle light.
+    pub fn triangle_cdf(&self) -> f32 {
+        self.d3.w
+    }
+
+    /// Returns visibility mask of this light, assuming it's a triangle light;
+    /// see: [`crate::Ray::with_visibility_mask()`].
+    pub fn triangle_visibility_mask(&self) -> u32 {
+        self.d1.w.to_bits()
+    }
+
     pub fn spot_direction(&self) -> Vec3 {
         Normal::decode(self.d2.yz())
     }
@@ -76,7 +197,48 @@ impl Light {
         self.d2.w
     }
 
+    /// Estimates light's power, i.e. how much it can contribute towards a
+    /// point located at unit distance from it.
+    pub fn power(&self) -> f32 {
+        let area = if self.is_rect() {
+            4.0 * self.rect_half_width() * self.rect_half_height()
+        } else if self.is_disk() {
+            PI * self.radius().sqr()
+        } else {
+            1.0
+        };
+
+        self.color().luma() * area
+    }
+
     pub fn radiance(&self, hit: Hit) -> Vec3 {
+        if self.is_directional() {
+            let cosine_factor =
+                hit.gbuffer.normal.dot(self.direction()).saturate();
+
+            return self.color() * cosine_factor;
+        }
+
+        if self.is_rect() {
+            return self.color()
+                * self
+                    .rect_projected_solid_angle(hit.point, hit.gbuffer.normal);
+        }
+
+        if self.is_disk() {
+            return self.color()
+                * self
+                    .disk_projected_solid_angle(hit.point, hit.gbuffer.normal);
+        }
+
+        if self.is_triangle() {
+            return self.color()
+                * self.triangle_projected_solid_angle(
+                    hit.point,
+                    hit.gbuffer.normal,
+                );
+        }
+
         let l = self.center() - hit.point;
 
         let conical_factor = if self.is_point() {
@@ -121,14 +283,35 @@ impl Light {
     }
 
     pub fn ray_wnoise(&self, noise: &mut WhiteNoise, hit_point: Vec3) -> Ray {
+        if self.is_directional() {
+            return self.ray_directional(
+                vec2(noise.sample(), noise.sample()),
+                hit_point,
+            );
+        }
+
+        if self.is_rect() || self.is_disk() || self.is_triangle() {
+            return self
+                .ray_area(vec2(noise.sample(), noise.sample()), hit_point);
+        }
+
         let light_pos = self.center() + self.radius() * noise.sample_sphere();
         let light_to_hit = hit_point - light_pos;
 
         Ray::new(light_pos, light_to_hit.normalize())
             .with_length(light_to_hit.length())
+            .with_kind(RayKind::SHADOW)
     }
 
     pub fn ray_bnoise(&self, sample: Vec2, hit_point: Vec3) -> Ray {
+        if self.is_directional() {
+            return self.ray_directional(sample, hit_point);
+        }
+
+        if self.is_rect() || self.is_disk() || self.is_triangle() {
+            return self.ray_area(sample, hit_point);
+        }
+
         let to_light = self.center() - hit_point;
         let light_dir = to_light.normalize();
         let light_distance = to_light.length();
@@ -150,6 +333,346 @@ impl Light {
 
         Ray::new(hit_point + ray_dir * light_distance, -ray_dir)
             .with_length(light_distance)
+            .with_kind(RayKind::SHADOW)
+    }
+
+    /// Returns ray going from given point towards a directional light,
+    /// uniformly sampling the cone the light's disk subtends.
+    ///
+    /// Contrary to rays of other lights, this ray starts at the hit point -
+    /// directional lights are infinitely far away, so there's no point the ray
+    /// could start at.
+    fn ray_directional(&self, sample: Vec2, hit_point: Vec3) -> Ray {
+        let light_dir = self.direction();
+        let (light_tangent, light_bitangent) = light_dir.any_orthonormal_pair();
+
+        let disk_point = {
+            let angle = 2.0 * PI * sample.x;
+            let radius = sample.y.sqrt();
+
+            vec2(angle.sin(), angle.cos())
+                * radius
+                * self.angular_radius().tan()
+        };
+
+        let ray_dir = light_dir
+            + disk_point.x * light_tangent
+            + disk_point.y * light_bitangent;
+
+        Ray::new(hit_point, ray_dir.normalize()).with_kind(RayKind::SHADOW)
+    }
+
+    /// Returns ray going from a point sampled on an area light towards given
+    /// point.
+    fn ray_area(&self, sample: Vec2, hit_point: Vec3) -> Ray {
+        let light_pos = if self.is_rect() {
+            self.sample_rect(sample, hit_point)
+        } else if self.is_disk() {
+            self.sample_disk(sample, hit_point)
+        } else {
+            self.sample_triangle(sample)
+        };
+
+        let mut light_to_hit = hit_point - light_pos;
+
+        // Contrary to other lights, emissive triangles are a part of the
+        // scene's geometry - nudge the ray so that it doesn't get occluded by
+        // the very triangle it starts at
+        let light_pos = if self.is_triangle() {
+            let light_pos =
+                light_pos + light_to_hit.normalize() * Hit::NUDGE_OFFSET;
+
+            light_to_hit = hit_point - light_pos;
+            light_pos
+        } else {
+            light_pos
+        };
+
+        Ray::new(light_pos, light_to_hit.normalize())
+            .with_length(light_to_hit.length())
+            .with_kind(RayKind::SHADOW)
+    }
+
+    /// Samples a point on rect light, uniformly in the solid angle the light
+    /// subtends as seen from given point.
+    fn sample_rect(&self, sample: Vec2, point: Vec3) -> Vec3 {
+        Self::sample_spherical_rect(
+            sample,
+            point,
+            self.center(),
+            self.rect_tangent(),
+            self.rect_bitangent(),
+            vec2(self.rect_half_width(), self.rect_half_height()),
+        )
+    }
+
+    /// Samples a point on given rect, uniformly in the solid angle the rect
+    /// subtends as seen from given point; see:
+    ///
+    /// https://www.arnoldrenderer.com/research/egsr2013_spherical_rectangle.pdf
+    fn sample_spherical_rect(
+        sample: Vec2,
+        point: Vec3,
+        center: Vec3,
+        ex: Vec3,
+        ey: Vec3,
+        half_size: Vec2,
+    ) -> Vec3 {
+        let exl = 2.0 * half_size.x;
+        let eyl = 2.0 * half_size.y;
+        let corner = center - 0.5 * exl * ex - 0.5 * eyl * ey;
+
+        let d = corner - point;
+        let x0 = d.dot(ex);
+        let y0 = d.dot(ey);
+        let x1 = x0 + exl;
+        let y1 = y0 + eyl;
+
+        let mut ez = ex.cross(ey);
+        let mut z0 = d.dot(ez);
+
+        if z0 > 0.0 {
+            z0 = -z0;
+            ez = -ez;
+        }
+
+        let v00 = vec3(x0, y0, z0);
+        let v01 = vec3(x0, y1, z0);
+        let v10 = vec3(x1, y0, z0);
+        let v11 = vec3(x1, y1, z0);
+
+        let n0 = v00.cross(v10).normalize();
+        let n1 = v10.cross(v11).normalize();
+        let n2 = v11.cross(v01).normalize();
+        let n3 = v01.cross(v00).normalize();
+
+        let g0 = (-n0.dot(n1)).clamp(-1.0, 1.0).acos();
+        let g1 = (-n1.dot(n2)).clamp(-1.0, 1.0).acos();
+        let g2 = (-n2.dot(n3)).clamp(-1.0, 1.0).acos();
+        let g3 = (-n3.dot(n0)).clamp(-1.0, 1.0).acos();
+
+        let k = 2.0 * PI - g2 - g3;
+        let solid_angle = g0 + g1 - k;
+
+        // If the point lies (almost) on the light's plane, the rect's solid
+        // angle degenerates - fall back to sampling the area
+        if z0 > -0.0001 || solid_angle < 0.0001 {
+            return corner + sample.x * exl * ex + sample.y * eyl * ey;
+        }
+
+        let au = sample.x * solid_angle + k;
+        let fu = (au.cos() * n0.z - n2.z) / au.sin();
+
+        let cu = {
+            let cu = 1.0 / (fu * fu + n0.z * n0.z).sqrt();
+
+            if fu > 0.0 {
+                cu
+            } else {
+                -cu
+            }
+        };
+
+        let cu = cu.clamp(-1.0, 1.0);
+        let xu = -(cu * z0) / (1.0 - cu * cu).max(0.0001).sqrt();
+        let xu = xu.clamp(x0, x1);
+
+        let dd = (xu * xu + z0 * z0).sqrt();
+        let h0 = y0 / (dd * dd + y0 * y0).sqrt();
+        let h1 = y1 / (dd * dd + y1 * y1).sqrt();
+        let hv = h0 + sample.y * (h1 - h0);
+        let hv2 = hv * hv;
+
+        let yv = if hv2 < 1.0 - 0.0001 {
+            hv * dd / (1.0 - hv2).sqrt()
+        } else {
+            y1
+        };
+
+        point + xu * ex + yv * ey + z0 * ez
+    }
+
+    /// Samples a point on disk light, uniformly in the solid angle the light
+    /// subtends as seen from given point.
+    ///
+    /// Contrary to rects, disks don't have a closed-form solid-angle sampling
+    /// routine - so we sample the square circumscribed around the disk and
+    /// reject points that land outside of the disk, trying a few times (with
+    /// the sample shifted along the R2 sequence) before falling back to
+    /// sampling the disk's area.
+    fn sample_disk(&self, sample: Vec2, point: Vec3) -> Vec3 {
+        let (tangent, bitangent) = self.area_normal().any_orthonormal_pair();
+        let mut try_idx = 0;
+
+        while try_idx < 4 {
+            let sample = (sample
+                + (try_idx as f32) * vec2(0.754_877_7, 0.569_840_3))
+            .fract();
+
+            let light_pos = Self::sample_spherical_rect(
+                sample,
+                point,
+                self.center(),
+                tangent,
+                bitangent,
+                Vec2::splat(self.radius()),
+            );
+
+            if light_pos.distance_squared(self.center()) <= self.radius().sqr()
+            {
+                return light_pos;
+            }
+
+            try_idx += 1;
+        }
+
+        let angle = 2.0 * PI * sample.x;
+        let radius = sample.y.sqrt() * self.radius();
+
+        self.center()
+            + tangent * (angle.cos() * radius)
+            + bitangent * (angle.sin() * radius)
+    }
+
+    /// Samples a point on triangle light, uniformly in its area.
+    fn sample_triangle(&self, sample: Vec2) -> Vec3 {
+        let [v0, v1, v2] = self.triangle_positions();
+        let su = sample.x.sqrt();
+
+        v0 * (1.0 - su) + v1 * (su * (1.0 - sample.y)) + v2 * (su * sample.y)
+    }
+
+    /// Returns the projected solid angle a rect light subtends as seen from
+    /// given point with given normal, i.e. the light's irradiance divided by
+    /// its radiance.
+    ///
+    /// Uses Lambert's formula for polygonal lights, with the rect clipped
+    /// against the surface's horizon; see: [`HorizonClippedPolygon`].
+    fn rect_projected_solid_angle(&self, point: Vec3, normal: Vec3) -> f32 {
+        // Rect lights emit light only towards the side their normal points to
+        if (point - self.center()).dot(self.area_normal()) <= 0.0 {
+            return 0.0;
+        }
+
+        let ex = self.rect_half_width() * self.rect_tangent();
+        let ey = self.rect_half_height() * self.rect_bitangent();
+
+        let v0 = self.center() - ex - ey - point;
+        let v1 = self.center() + ex - ey - point;
+        let v2 = self.center() + ex + ey - point;
+        let v3 = self.center() - ex + ey - point;
+
+        let mut polygon = HorizonClippedPolygon::new(normal);
+
+        polygon.add_edge(v0, v1);
+        polygon.add_edge(v1, v2);
+        polygon.add_edge(v2, v3);
+        polygon.add_edge(v3, v0);
+        polygon.projected_solid_angle()
+    }
+
+    /// Returns the projected solid angle a triangle light subtends as seen
+    /// from given point with given normal; see:
+    /// [`Self::rect_projected_solid_angle()`].
+    ///
+    /// Emissive triangles emit light from both of their sides.
+    fn triangle_projected_solid_angle(&self, point: Vec3, normal: Vec3) -> f32 {
+        let [v0, v1, v2] = self.triangle_positions();
+        let v0 = v0 - point;
+        let v1 = v1 - point;
+        let v2 = v2 - point;
+
+        let mut polygon = HorizonClippedPolygon::new(normal);
+
+        polygon.add_edge(v0, v1);
+        polygon.add_edge(v1, v2);
+        polygon.add_edge(v2, v0);
+        polygon.projected_solid_angle()
+    }
+
+    /// Returns the (approximate) projected solid angle a disk light subtends
+    /// as seen from given point with given normal; see:
+    ///
+    /// https://seblagarde.files.wordpress.com/2015/07/course_notes_moving_frostbite_to_pbr_v32.pdf
+    fn disk_projected_solid_angle(&self, point: Vec3, normal: Vec3) -> f32 {
+        let l = self.center() - point;
+        let dir = l.normalize();
+        let cos_light = self.area_normal().dot(-dir).saturate();
+        let cos_surface = normal.dot(dir).saturate();
+
+        PI * cos_light * cos_surface * self.radius().sqr()
+            / (self.radius().sqr() + l.length_squared())
+    }
+}
+
+/// Convex polygon (given through its consecutive edges, with vertices relative
+/// to the shaded point) clipped against the horizon of the shaded surface.
+///
+/// Lambert's formula integrates the cosine over the polygon's entire solid
+/// angle, so parts of the polygon lying below the horizon would contribute
+/// negatively - that's why the polygon gets clipped first.
+///
+/// Since the polygon is convex, clipping it against a plane going through the
+/// shaded point leaves at most one edge that leaves the upper hemisphere and
+/// at most one edge that enters it - so instead of building the clipped
+/// polygon, we integrate the edges' visible parts as they come and close the
+/// polygon along the horizon at the end.
+struct HorizonClippedPolygon {
+    normal: Vec3,
+    sum: f32,
+    exit: Vec3,
+    entry: Vec3,
+    is_clipped: bool,
+}
+
+impl HorizonClippedPolygon {
+    fn new(normal: Vec3) -> Self {
+        Self {
+            normal,
+            sum: 0.0,
+            exit: Vec3::ZERO,
+            entry: Vec3::ZERO,
+            is_clipped: false,
+        }
+    }
+
+    fn add_edge(&mut self, v0: Vec3, v1: Vec3) {
+        let h0 = v0.dot(self.normal);
+        let h1 = v1.dot(self.normal);
+
+        if h0 >= 0.0 && h1 >= 0.0 {
+            self.sum += self.edge_integral(v0, v1);
+        } else if h0 >= 0.0 {
+            let exit = v0 + (v1 - v0) * (h0 / (h0 - h1));
+
+            self.sum += self.edge_integral(v0, exit);
+            self.exit = exit;
+            self.is_clipped = true;
+        } else if h1 >= 0.0 {
+            let entry = v0 + (v1 - v0) * (h0 / (h0 - h1));
+
+            self.sum += self.edge_integral(entry, v1);
+            self.entry = entry;
+            self.is_clipped = true;
+        }
+    }
+
+    fn projected_solid_angle(&self) -> f32 {
+        let mut sum = self.sum;
+
+        if self.is_clipped {
+            sum += self.edge_integral(self.exit, self.entry);
+        }
+
+        (0.5 * sum.abs()).min(PI)
+    }
+
+    fn edge_integral(&self, v0: Vec3, v1: Vec3) -> f32 {
+        let v0 = v0.normalize_or_zero();
+        let v1 = v1.normalize_or_zero();
+        let angle = v0.dot(v1).clamp(-1.0, 1.0).acos();
+
+        angle * v0.cross(v1).normalize_or_zero().dot(self.normal)
     }
 }
 
@@ -175,3 +698,143 @@ impl LightId {
         &mut self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(
+        center: Vec3,
+        normal: Vec3,
+        tangent: Vec3,
+        half_size: Vec2,
+    ) -> Light {
+        Light {
+            d0: center.extend(half_size.length()),
+            d1: Vec3::ONE.extend(f32::INFINITY),
+            d2: vec4(
+                f32::from_bits(Light::TYPE_RECT),
+                Normal::encode(normal).x,
+                Normal::encode(normal).y,
+                half_size.y,
+            ),
+            d3: tangent.extend(half_size.x),
+        }
+    }
+
+    fn disk(center: Vec3, normal: Vec3, radius: f32) -> Light {
+        Light {
+            d0: center.extend(radius),
+            d1: Vec3::ONE.extend(f32::INFINITY),
+            d2: vec4(
+                f32::from_bits(Light::TYPE_DISK),
+                Normal::encode(normal).x,
+                Normal::encode(normal).y,
+                0.0,
+            ),
+            d3: Default::default(),
+        }
+    }
+
+    #[test]
+    fn rect_projected_solid_angle() {
+        // Huge rect right above the point covers (almost) the entire
+        // hemisphere
+        let target = rect(Vec3::Y, -Vec3::Y, Vec3::X, Vec2::splat(1e4));
+
+        assert!(
+            (target.rect_projected_solid_angle(Vec3::ZERO, Vec3::Y) - PI).abs()
+                < 0.01
+        );
+
+        // Rect facing away from the point doesn't contribute at all
+        let target = rect(Vec3::Y, Vec3::Y, Vec3::X, Vec2::splat(1e4));
+
+        assert_eq!(0.0, target.rect_projected_solid_angle(Vec3::ZERO, Vec3::Y));
+
+        // Rect cut in half by the horizon contributes as much as its upper
+        // half alone
+        let full = rect(Vec3::Z, -Vec3::Z, Vec3::X, Vec2::ONE);
+        let half = rect(vec3(0.0, 0.5, 1.0), -Vec3::Z, Vec3::X, vec2(1.0, 0.5));
+
+        let full = full.rect_projected_solid_angle(Vec3::ZERO, Vec3::Y);
+        let half = half.rect_projected_solid_angle(Vec3::ZERO, Vec3::Y);
+
+        assert!(half > 0.1, "{half}");
+        assert!((full - half).abs() < 1e-4, "{full} vs {half}");
+
+        // Rect below the horizon doesn't contribute at all
+        let target = rect(-Vec3::Y, Vec3::Y, Vec3::X, Vec2::ONE);
+
+        assert_eq!(0.0, target.rect_projected_solid_angle(Vec3::ZERO, Vec3::Y));
+    }
+
+    #[test]
+    fn triangle_projected_solid_angle() {
+        // Triangle cut in half by the horizon contributes as much as its
+        // upper half alone
+        let full = Light::triangle(
+            [
+                vec3(-1.0, -1.0, 1.0),
+                vec3(1.0, -1.0, 1.0),
+                vec3(0.0, 1.0, 1.0),
+            ],
+            Vec3::ONE,
+            1.0,
+            u32::MAX,
+        );
+
+        let half = Light::triangle(
+            [
+                vec3(-0.5, 0.0, 1.0),
+                vec3(0.5, 0.0, 1.0),
+                vec3(0.0, 1.0, 1.0),
+            ],
+            Vec3::ONE,
+            1.0,
+            u32::MAX,
+        );
+
+        let full = full.triangle_projected_solid_angle(Vec3::ZERO, Vec3::Y);
+        let half = half.triangle_projected_solid_angle(Vec3::ZERO, Vec3::Y);
+
+        assert!(half > 0.01, "{half}");
+        assert!((full - half).abs() < 1e-4, "{full} vs {half}");
+    }
+
+    #[test]
+    fn sample_disk() {
+        // Point located on the disk's axis, close to it - as seen from there,
+        // the disk's center covers more of the solid angle than its edges do
+        let target = disk(Vec3::ZERO, Vec3::Y, 1.0);
+        let point = vec3(0.0, 0.5, 0.0);
+        let n = 64;
+        let mut inner = 0;
+
+        for x in 0..n {
+            for y in 0..n {
+                let sample = vec2(
+                    (x as f32 + 0.5) / (n as f32),
+                    (y as f32 + 0.5) / (n as f32),
+                );
+
+                let light_pos = target.sample_disk(sample, point);
+
+                assert!(light_pos.length() <= 1.0 + 1e-4, "{light_pos}");
+                assert!(light_pos.y.abs() < 1e-4, "{light_pos}");
+
+                if light_pos.length() < 0.5 {
+                    inner += 1;
+                }
+            }
+        }
+
+        // Solid angle subtended by a disk of radius `r` as seen from height
+        // `h` is `2 * PI * (1 - h / sqrt(h^2 + r^2))`
+        let solid_angle = |r: f32| 1.0 - 0.5 / (0.25 + r * r).sqrt();
+        let expected = solid_angle(0.5) / solid_angle(1.0);
+        let actual = (inner as f32) / ((n * n) as f32);
+
+        assert!((expected - actual).abs() < 0.03, "{expected} vs {actual}");
+    }
+}
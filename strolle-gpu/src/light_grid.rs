@@ -0,0 +1,177 @@
+use glam::{uvec3, vec3, vec4, UVec3, Vec3, Vec4};
+use spirv_std::arch::IndexUnchecked;
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
+
+use crate::{Camera, F32Ext, Light, LightId, Reservoir};
+
+/// World-space grid of light reservoirs centered around the camera - an
+/// alternative to the light tree, inspired by ReGIR.
+///
+/// Each cell contains [`Self::RESERVOIRS_PER_CELL`] reservoirs which are filled
+/// by the `di_grid` pass with lights that are important for that cell, so that
+/// `di_shading` can draw its candidates from the cell containing the hit point
+/// instead of going through all of the lights.
+///
+/// Similarly to the light tree, the grid contains all of the regular lights
+/// except for the sun.
+///
+/// Layout:
+///
+/// - each reservoir is `1 x Vec4` - `[light_id, w, m, _]`,
+///
+/// - reservoirs of each cell are stored next to each other, with cells
+///   following the x-y-z order.
+#[derive(Clone, Copy)]
+pub struct LightGrid {
+    origin: Vec3,
+}
+
+impl LightGrid {
+    /// Number of cells along each axis.
+    pub const SIZE: UVec3 = UVec3::new(32, 16, 32);
+
+    /// Length of each cell's side, in world units.
+    pub const CELL_SIZE: f32 = 2.0;
+
+    pub const RESERVOIRS_PER_CELL: u32 = 16;
+
+    pub const CELLS: u32 = Self::SIZE.x * Self::SIZE.y * Self::SIZE.z;
+
+    pub const RESERVOIRS: u32 = Self::CELLS * Self::RESERVOIRS_PER_CELL;
+
+    /// Creates a grid centered around given camera.
+    ///
+    /// Grid's origin is snapped to the cell size, so that moving the camera
+    /// doesn't shift cells' boundaries.
+    pub fn new(camera: &Camera) -> Self {
+        let origin = camera.approx_origin()
+            - 0.5 * Self::CELL_SIZE * Self::SIZE.as_vec3();
+
+        let origin = vec3(
+            (origin.x / Self::CELL_SIZE).floor(),
+            (origin.y / Self::CELL_SIZE).floor(),
+            (origin.z / Self::CELL_SIZE).floor(),
+        ) * Self::CELL_SIZE;
+
+        Self { origin }
+    }
+
+    /// Returns whether given point lies within the grid.
+    pub fn contains(&self, point: Vec3) -> bool {
+        let pos = (point - self.origin) / Self::CELL_SIZE;
+        let size = Self::SIZE.as_vec3();
+
+        pos.x >= 0.0
+            && pos.y >= 0.0
+            && pos.z >= 0.0
+            && pos.x < size.x
+            && pos.y < size.y
+            && pos.z < size.z
+    }
+
+    /// Returns index of the cell containing given point.
+    ///
+    /// Must be called only if the point lies within the grid.
+    pub fn cell_idx(&self, point: Vec3) -> u32 {
+        let pos = ((point - self.origin) / Self::CELL_SIZE)
+            .as_uvec3()
+            .min(Self::SIZE - 1);
+
+        pos.x + Self::SIZE.x * (pos.y + Self::SIZE.y * pos.z)
+    }
+
+    /// Returns the bounding box of given cell.
+    pub fn cell_bounds(&self, cell_idx: u32) -> (Vec3, Vec3) {
+        let pos = uvec3(
+            cell_idx % Self::SIZE.x,
+            (cell_idx / Self::SIZE.x) % Self::SIZE.y,
+            cell_idx / (Self::SIZE.x * Self::SIZE.y),
+        );
+
+        let min = self.origin + pos.as_vec3() * Self::CELL_SIZE;
+
+        (min, min + Self::CELL_SIZE)
+    }
+
+    /// Estimates how much given light contributes towards given cell.
+    ///
+    /// That's light's power attenuated by the distance to the closest point of
+    /// the cell, ignoring normals and occlusion - so that the estimate stays
+    /// conservative for all the points within the cell.
+    pub fn importance(light: Light, cell_min: Vec3, cell_max: Vec3) -> f32 {
+        let center = light.center();
+        let distance = center.distance(center.clamp(cell_min, cell_max));
+
+        if distance > light.range() + light.radius() {
+            return 0.0;
+        }
+
+        // Distance is clamped to cell's half-size so that lights located
+        // within the cell don't overshadow all of the others
+        light.power()
+            / distance
+                .max(light.radius())
+                .max(0.5 * Self::CELL_SIZE)
+                .sqr()
+    }
+
+    pub fn read(buffer: &[Vec4], id: u32) -> Reservoir<LightId> {
+        let d0 = unsafe { *buffer.index_unchecked(id as usize) };
+
+        Reservoir {
+            sample: LightId::new(d0.x.to_bits()),
+            w: d0.y,
+            m: d0.z,
+        }
+    }
+
+    pub fn write(buffer: &mut [Vec4], id: u32, res: &Reservoir<LightId>) {
+        let d0 = vec4(
+            f32::from_bits(res.sample.get()),
+            res.w,
+            res.m,
+            Default::default(),
+        );
+
+        unsafe {
+            *buffer.index_unchecked_mut(id as usize) = d0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Mat4;
+
+    use super::*;
+
+    #[test]
+    fn cells() {
+        let camera = Camera {
+            projection_view: Mat4::IDENTITY,
+            ndc_to_world: Mat4::IDENTITY,
+            origin: vec4(10.3, -4.2, 0.7, 0.0),
+            screen: Default::default(),
+            data: Default::default(),
+        };
+
+        let grid = LightGrid::new(&camera);
+
+        assert!(grid.contains(camera.approx_origin()));
+        assert!(!grid.contains(camera.approx_origin() + 100.0));
+
+        for point in [
+            vec3(10.3, -4.2, 0.7),
+            vec3(-5.0, 9.0, 30.0),
+            vec3(41.9, -19.9, -29.9),
+        ] {
+            assert!(grid.contains(point), "{point}");
+
+            let (min, max) = grid.cell_bounds(grid.cell_idx(point));
+
+            assert!(point.cmpge(min).all(), "{point}: {min} .. {max}");
+            assert!(point.cmplt(max).all(), "{point}: {min} .. {max}");
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use glam::{Vec3, Vec4, Vec4Swizzles};
+use spirv_std::arch::IndexUnchecked;
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
+
+use crate::{F32Ext, LightId};
+
+/// View into the light tree - a BVH built over lights, which allows to pick
+/// lights proportionally to their (estimated) contribution in logarithmic
+/// time, instead of going through all of them.
+///
+/// The tree contains all of the regular lights except for the sun, which is
+/// infinitely far away and so it always has to be considered separately.
+///
+/// Layout:
+///
+/// - each node is `2 x Vec4` - `[min, power]`, `[max, ptr]`, where `power` is
+///   the sum of (estimated) powers of all the lights within the node,
+///
+/// - for internal nodes `ptr` contains index of the right child, with the
+///   left child located right after the node,
+///
+/// - for leaf nodes `ptr` contains id of the light, with [`Self::LEAF_BIT`]
+///   set,
+///
+/// - root is located at index zero.
+#[derive(Clone, Copy)]
+pub struct LightTreeView<'a> {
+    buffer: &'a [Vec4],
+}
+
+impl<'a> LightTreeView<'a> {
+    pub const LEAF_BIT: u32 = 1 << 31;
+
+    pub fn new(buffer: &'a [Vec4]) -> Self {
+        Self { buffer }
+    }
+
+    /// Picks a light by traversing the tree stochastically, going into each
+    /// node with probability proportional to its importance for given point
+    /// and normal; returns the light and the probability of picking it.
+    ///
+    /// Must be called only if the tree is not empty.
+    pub fn sample(
+        &self,
+        point: Vec3,
+        normal: Vec3,
+        mut sample: f32,
+    ) -> (LightId, f32) {
+        let mut node_idx = 0;
+        let mut pdf = 1.0;
+
+        loop {
+            let ptr = self.get(2 * node_idx + 1).w.to_bits();
+
+            if ptr & Self::LEAF_BIT != 0 {
+                return (LightId::new(ptr & !Self::LEAF_BIT), pdf);
+            }
+
+            let left_idx = node_idx + 1;
+            let right_idx = ptr;
+
+            let left_importance = self.importance(left_idx, point, normal);
+            let right_importance = self.importance(right_idx, point, normal);
+            let total_importance = left_importance + right_importance;
+
+            let left_prob = if total_importance > 0.0 {
+                left_importance / total_importance
+            } else {
+                0.5
+            };
+
+            // Reusing the same sample for all levels of the tree, so that
+            // nearby points traverse the tree similarly
+            if sample < left_prob {
+                sample /= left_prob;
+                pdf *= left_prob;
+                node_idx = left_idx;
+            } else {
+                sample = (sample - left_prob) / (1.0 - left_prob);
+                pdf *= 1.0 - left_prob;
+                node_idx = right_idx;
+            }
+        }
+    }
+
+    /// Estimates how much given node contributes towards given point.
+    ///
+    /// That's node's power attenuated by the distance and by the smallest
+    /// angle between the normal and the node's bounding sphere - so nodes
+    /// that lie entirely below the point's horizon are ignored.
+    fn importance(&self, node_idx: u32, point: Vec3, normal: Vec3) -> f32 {
+        let d0 = self.get(2 * node_idx);
+        let d1 = self.get(2 * node_idx + 1);
+
+        let center = 0.5 * (d0.xyz() + d1.xyz());
+        let radius = 0.5 * d0.xyz().distance(d1.xyz());
+
+        let to_center = center - point;
+        let distance = to_center.length();
+
+        let cosine_factor = if distance <= radius {
+            1.0
+        } else {
+            let angle =
+                normal.dot(to_center / distance).clamp(-1.0, 1.0).acos();
+            let half_angle = (radius / distance).asin();
+
+            (angle - half_angle).max(0.0).cos().max(0.0)
+        };
+
+        d0.w * cosine_factor / distance.max(radius).max(0.01).sqr()
+    }
+
+    fn get(&self, idx: u32) -> Vec4 {
+        unsafe { *self.buffer.index_unchecked(idx as usize) }
+    }
+}
@@ -1,6 +1,6 @@
 use spirv_std::arch::IndexUnchecked;
 
-use crate::{Light, LightId};
+use crate::{Light, LightId, World};
 
 #[derive(Clone, Copy)]
 pub struct LightsView<'a> {
@@ -19,4 +19,83 @@ impl<'a> LightsView<'a> {
     pub fn len(&self) -> usize {
         self.items.len()
     }
+
+    /// Picks a triangle light proportionally to its power, returning its id
+    /// and the probability of picking it.
+    ///
+    /// Must be called only if there's at least one triangle light.
+    pub fn sample_triangle(
+        &self,
+        world: &World,
+        sample: f32,
+    ) -> (LightId, f32) {
+        // Triangle lights are sorted by their cumulative probabilities, so
+        // let's binary-search the first one that's larger than our sample
+        let mut min = world.light_count;
+        let mut max = world.light_count + world.triangle_light_count - 1;
+
+        while min < max {
+            let mid = (min + max) / 2;
+
+            if self.get(LightId::new(mid)).triangle_cdf() <= sample {
+                min = mid + 1;
+            } else {
+                max = mid;
+            }
+        }
+
+        let cdf = self.get(LightId::new(min)).triangle_cdf();
+
+        let prev_cdf = if min > world.light_count {
+            self.get(LightId::new(min - 1)).triangle_cdf()
+        } else {
+            0.0
+        };
+
+        (LightId::new(min), cdf - prev_cdf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+
+    #[test]
+    fn sample_triangle() {
+        let lights = [
+            Light::sun(Vec3::Y, 0.0, Vec3::ONE),
+            Light::triangle([Vec3::ZERO; 3], Vec3::ONE, 0.25, u32::MAX),
+            Light::triangle([Vec3::ZERO; 3], Vec3::ONE, 0.25, u32::MAX),
+            Light::triangle([Vec3::ZERO; 3], Vec3::ONE, 1.0, u32::MAX),
+        ];
+
+        let world = World {
+            light_count: 1,
+            triangle_light_count: 3,
+            ..Default::default()
+        };
+
+        let target = LightsView::new(&lights);
+
+        assert_eq!(
+            (LightId::new(1), 0.25),
+            target.sample_triangle(&world, 0.0)
+        );
+        assert_eq!(
+            (LightId::new(1), 0.25),
+            target.sample_triangle(&world, 0.2)
+        );
+
+        // Light with zero probability never gets picked
+        assert_eq!(
+            (LightId::new(3), 0.75),
+            target.sample_triangle(&world, 0.25)
+        );
+        assert_eq!(
+            (LightId::new(3), 0.75),
+            target.sample_triangle(&world, 0.9)
+        );
+    }
 }
@@ -40,6 +40,13 @@ impl Material {
         )
     }
 
+    /// Returns whether surfaces of this material are sampled as triangle
+    /// lights - that's the case for emissive materials without emissive
+    /// textures (since emission has to be known up-front, on the CPU).
+    pub fn is_triangle_light(&self) -> bool {
+        self.emissive.xyz() != Vec3::ZERO && self.emissive_texture == Vec4::ZERO
+    }
+
     pub fn emissive(
         &self,
         atlas_tex: Tex,
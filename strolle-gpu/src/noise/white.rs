@@ -14,7 +14,9 @@ pub struct WhiteNoise {
 impl WhiteNoise {
     pub fn new(seed: u32, id: UVec2) -> Self {
         Self {
-            state: seed ^ (48619 * id.x) ^ (95461 * id.y),
+            state: seed
+                ^ 48619u32.wrapping_mul(id.x)
+                ^ 95461u32.wrapping_mul(id.y),
         }
     }
 
@@ -25,10 +27,13 @@ impl WhiteNoise {
 
     /// Generates a uniform sample in range `<0, u32::MAX>`.
     pub fn sample_int(&mut self) -> u32 {
-        self.state = self.state * 747796405 + 2891336453;
+        // Using wrapping operations so that this works on the CPU as well,
+        // where overflowing in debug builds panics
+        self.state =
+            self.state.wrapping_mul(747796405).wrapping_add(2891336453);
 
-        let word =
-            ((self.state >> ((self.state >> 28) + 4)) ^ self.state) * 277803737;
+        let word = ((self.state >> ((self.state >> 28) + 4)) ^ self.state)
+            .wrapping_mul(277803737);
 
         (word >> 22) ^ word
     }
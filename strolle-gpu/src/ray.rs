@@ -1,14 +1,13 @@
 use core::mem;
 
-use glam::{Vec3, Vec4, Vec4Swizzles};
-use spirv_std::arch::IndexUnchecked;
+use glam::{uvec4, vec4, Affine3A, UVec4, Vec2, Vec3, Vec4, Vec4Swizzles};
 #[cfg(target_arch = "spirv")]
 use spirv_std::num_traits::Float;
 use spirv_std::Sampler;
 
 use crate::{
-    BvhStack, BvhView, Material, MaterialId, MaterialsView, Tex, Triangle,
-    TriangleHit, TriangleId, TrianglesView, BVH_STACK_SIZE,
+    BvhStack, BvhTraversalStack, BvhView, Material, MaterialId, MaterialsView,
+    Tex, Triangle, TriangleHit, TriangleId, TrianglesView, WorkgroupBvhStack,
 };
 
 #[derive(Clone, Copy, Default, PartialEq)]
@@ -17,6 +16,8 @@ pub struct Ray {
     direction: Vec3,
     inv_direction: Vec3,
     length: f32,
+    visibility_mask: u32,
+    kind: RayKind,
 }
 
 impl Ray {
@@ -26,14 +27,52 @@ impl Ray {
             direction,
             inv_direction: 1.0 / direction,
             length: f32::MAX,
+            visibility_mask: u32::MAX,
+            kind: RayKind::ANY,
         }
     }
 
+    /// Limits how far this ray reaches; infinite lengths are clamped to
+    /// `f32::MAX`, which is what missed bounding-boxes report as their
+    /// distance - that way rays never descend into nodes they've missed.
     pub fn with_length(mut self, length: f32) -> Self {
-        self.length = length;
+        self.length = length.min(f32::MAX);
         self
     }
 
+    /// Makes this ray see only instances whose visibility masks have at least
+    /// one bit in common with given mask; by default rays see everything.
+    pub fn with_visibility_mask(mut self, visibility_mask: u32) -> Self {
+        self.visibility_mask = visibility_mask;
+        self
+    }
+
+    /// Makes this ray see only instances visible to given kind of rays; by
+    /// default rays see everything.
+    pub fn with_kind(mut self, kind: RayKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn length(&self) -> f32 {
+        self.length
+    }
+
+    pub fn visibility_mask(&self) -> u32 {
+        self.visibility_mask
+    }
+
+    pub fn kind(&self) -> RayKind {
+        self.kind
+    }
+
+    /// Returns whether this ray can hit an instance with given visibility mask
+    /// and flags; see: `BvhView`.
+    pub fn sees_instance(&self, visibility_mask: u32, flags: u32) -> bool {
+        (visibility_mask & self.visibility_mask != 0)
+            & ((flags >> 2) & self.kind.get() != 0)
+    }
+
     pub fn origin(&self) -> Vec3 {
         self.origin
     }
@@ -61,19 +100,22 @@ impl Ray {
     ) -> (TriangleHit, usize) {
         let mut hit = TriangleHit::none();
 
-        let used_memory = self.traverse(
-            local_idx,
-            stack,
+        let traversal = self.traverse(
+            &mut WorkgroupBvhStack::new(stack, local_idx),
             triangles,
             bvh,
-            materials,
-            atlas_tex,
-            atlas_sampler,
             Tracing::ReturnClosest,
+            move |material_id, uv| {
+                materials
+                    .get(material_id)
+                    .base_color(atlas_tex, atlas_sampler, uv)
+                    .w
+                    >= 1.0
+            },
             &mut hit,
         );
 
-        (hit, used_memory)
+        (hit, traversal.used_memory)
     }
 
     /// Returns whether this ray intersects with anything in the world; used for
@@ -95,149 +137,261 @@ impl Ray {
         };
 
         self.traverse(
-            local_idx,
-            stack,
+            &mut WorkgroupBvhStack::new(stack, local_idx),
             triangles,
             bvh,
-            materials,
-            atlas_tex,
-            atlas_sampler,
             Tracing::ReturnFirst,
+            move |material_id, uv| {
+                materials
+                    .get(material_id)
+                    .base_color(atlas_tex, atlas_sampler, uv)
+                    .w
+                    >= 1.0
+            },
             &mut hit,
         );
 
         hit.distance < self.length
     }
 
-    #[allow(clippy::too_many_arguments)]
-    fn traverse(
+    /// Traverses the BVH, looking for intersections of this ray with the world
+    /// closer than `hit.distance`; found intersections get written into `hit`.
+    ///
+    /// This is the BVH traversal used both by the shaders (through
+    /// [`Self::trace()`] and [`Self::intersect()`]) and by the CPU tracer in
+    /// `strolle` - they differ only in where they keep the stack and in how
+    /// they evaluate materials, which is why those are provided by the caller.
+    ///
+    /// `is_opaque` gets called for hits on instances whose materials use alpha
+    /// blending and returns whether given material is opaque at given uv; hits
+    /// on transparent parts of triangles are ignored.
+    pub fn traverse<S, F>(
         self,
-        local_idx: u32,
-        stack: BvhStack,
+        stack: &mut S,
         triangles: TrianglesView,
         bvh: BvhView,
-        materials: MaterialsView,
-        atlas_tex: Tex,
-        atlas_sampler: &Sampler,
         tracing: Tracing,
+        is_opaque: F,
         hit: &mut TriangleHit,
-    ) -> usize {
-        // An estimation of the memory used when travelling the BVH; useful for
-        // debugging
-        let mut used_memory = 0;
+    ) -> BvhTraversal
+    where
+        S: BvhTraversalStack,
+        F: Fn(MaterialId, Vec2) -> bool,
+    {
+        let mut traversal = BvhTraversal {
+            used_memory: mem::size_of::<Vec4>(),
+            instance_ptr: BvhView::SENTINEL,
+            triangle_id: TriangleId::new(0),
+        };
 
         // Index into the `bvh` array; points at the currently processed node
-        let mut bvh_ptr = 0;
+        let mut bvh_ptr = bvh.root();
+
+        // Ray we're currently testing - when we're traversing a bottom-level
+        // BVH, this is the world-space ray transformed into instance's
+        // object-space.
+        //
+        // Note that we don't normalize the transformed direction, so that the
+        // distances remain expressed in the world-space units and can be
+        // compared with `hit.distance` as-is.
+        let mut ray = self;
+
+        // Properties of the instance whose bottom-level BVH we're currently
+        // traversing
+        let mut instance_ptr = BvhView::SENTINEL;
+        let mut instance_xform = Affine3A::IDENTITY;
+        let mut instance_material_id = MaterialId::new(0);
+        let mut instance_has_alpha_blending = false;
+
+        if bvh_ptr == BvhView::SENTINEL {
+            return traversal;
+        }
 
-        // Where this particular thread's stack starts at; see `BvhStack`
-        let stack_begins_at = (local_idx as usize) * BVH_STACK_SIZE;
+        loop {
+            if bvh_ptr == BvhView::SENTINEL {
+                // We've just finished traversing a bottom-level BVH, so let's
+                // go back to the world-space
+                ray = self;
+            } else {
+                traversal.used_memory += mem::size_of::<Vec4>();
 
-        // Index into the `stack` array; our stack spans from here up to +
-        // BVH_STACK_SIZE items
-        let mut stack_ptr = stack_begins_at;
+                let d0 = bvh.get(bvh_ptr);
+                let op = d0.w.to_bits();
 
-        loop {
-            used_memory += mem::size_of::<Vec4>();
+                if op == BvhView::OP_INTERNAL {
+                    traversal.used_memory += 3 * mem::size_of::<Vec4>();
 
-            let d0 = bvh.get(bvh_ptr);
-            let is_internal_node = d0.w.to_bits() == 0;
+                    let d1 = bvh.get(bvh_ptr + 1);
+                    let d2 = bvh.get(bvh_ptr + 2);
+                    let d3 = bvh.get(bvh_ptr + 3);
 
-            if is_internal_node {
-                used_memory += 3 * mem::size_of::<Vec4>();
+                    let mut near_ptr = bvh_ptr + 4;
+                    let mut far_ptr = d1.w.to_bits();
 
-                let d1 = bvh.get(bvh_ptr + 1);
-                let d2 = bvh.get(bvh_ptr + 2);
-                let d3 = bvh.get(bvh_ptr + 3);
+                    let mut near_distance =
+                        ray.intersect_box(d0.xyz(), d1.xyz());
 
-                let mut near_ptr = bvh_ptr + 4;
-                let mut far_ptr = d1.w.to_bits();
+                    let mut far_distance =
+                        ray.intersect_box(d2.xyz(), d3.xyz());
 
-                let mut near_distance = self.intersect_box(d0.xyz(), d1.xyz());
-                let mut far_distance = self.intersect_box(d2.xyz(), d3.xyz());
+                    if far_distance < near_distance {
+                        mem::swap(&mut near_ptr, &mut far_ptr);
+                        mem::swap(&mut near_distance, &mut far_distance);
+                    }
 
-                if far_distance < near_distance {
-                    mem::swap(&mut near_ptr, &mut far_ptr);
-                    mem::swap(&mut near_distance, &mut far_distance);
-                }
+                    // If the nearest child is closer than our current best
+                    // shot, let's check that child first; use stack to save
+                    // the other node for later.
+                    //
+                    // The reasoning here goes that the closer child is more
+                    // likely to contain a triangle we can hit; but if we don't
+                    // hit that triangle (kind of a "cache miss" kind of thing),
+                    // we still have to check the other node.
+                    if far_distance < hit.distance {
+                        stack.push(far_ptr);
+                    }
 
-                // If the nearest child is closer than our current best shot,
-                // let's check that child first; use stack to save the other
-                // node for later.
-                //
-                // The reasoning here goes that the closer child is more likely
-                // to contain a triangle we can hit; but if we don't hit that
-                // triangle (kind of a "cache miss" kind of thing), we still
-                // have to check the other node.
-                if far_distance < hit.distance {
-                    unsafe {
-                        *stack.index_unchecked_mut(stack_ptr) = far_ptr;
-                        stack_ptr += 1;
+                    if near_distance < hit.distance {
+                        bvh_ptr = near_ptr;
+                        continue;
+                    }
+                } else if BvhView::is_wide(op) {
+                    traversal.used_memory += ((BvhView::wide_node_size(op) - 1)
+                        as usize)
+                        * mem::size_of::<Vec4>();
+
+                    let (distances, ptrs) =
+                        ray.intersect_wide_node(bvh, bvh_ptr);
+
+                    // Same as above - push the farther children on the stack
+                    // (the farthest one first, so that it gets popped last)
+                    // and continue with the nearest one
+                    if distances.w < hit.distance {
+                        stack.push(ptrs.w);
                     }
-                }
 
-                if near_distance < hit.distance {
-                    bvh_ptr = near_ptr;
-                    continue;
-                }
-            } else {
-                used_memory += mem::size_of::<Triangle>();
-
-                let flags = d0.x.to_bits();
-
-                // Whether there are any more triangles directly following this
-                // triangle.
-                //
-                // This corresponds to a single BVH leaf node containing
-                // multiple triangles.
-                let got_more_triangles = flags & 1 == 1;
-
-                // Whether the triangle we're looking at supports alpha
-                // blending.
-                //
-                // If this is turned on, we have to load the triangle's material
-                // and compute albedo to make sure that the part of triangle we
-                // hit is actually opaque at that particular hit-point.
-                let has_alpha_blending = flags & 2 == 2;
-
-                let triangle_id = TriangleId::new(d0.y.to_bits());
-                let material_id = MaterialId::new(d0.z.to_bits());
-
-                let prev_uv = hit.uv;
-                let prev_normal = hit.normal;
-                let prev_distance = hit.distance;
-
-                let mut found_hit = triangles.get(triangle_id).hit(self, hit);
-
-                if found_hit && has_alpha_blending {
-                    used_memory += mem::size_of::<Material>();
-                    used_memory += mem::size_of::<Vec4>();
-
-                    let base_color = materials.get(material_id).base_color(
-                        atlas_tex,
-                        atlas_sampler,
-                        hit.uv,
+                    if distances.z < hit.distance {
+                        stack.push(ptrs.z);
+                    }
+
+                    if distances.y < hit.distance {
+                        stack.push(ptrs.y);
+                    }
+
+                    if distances.x < hit.distance {
+                        bvh_ptr = ptrs.x;
+                        continue;
+                    }
+                } else if op == BvhView::OP_INSTANCE {
+                    traversal.used_memory += mem::size_of::<Vec4>();
+
+                    let flags = d0.x.to_bits();
+
+                    // Whether there are any more instances directly following
+                    // this instance (i.e. whether we're in a leaf containing
+                    // multiple instances)
+                    let got_more_instances = flags & 1 == 1;
+
+                    let is_visible = self.sees_instance(
+                        bvh.instance_visibility_mask(bvh_ptr),
+                        flags,
                     );
 
-                    if base_color.w < 1.0 {
-                        found_hit = false;
+                    if is_visible {
+                        traversal.used_memory += 3 * mem::size_of::<Vec4>();
 
-                        hit.uv = prev_uv;
-                        hit.normal = prev_normal;
-                        hit.distance = prev_distance;
+                        // If there are more instances, let's remember to get
+                        // back to them after we're done with this instance
+                        if got_more_instances {
+                            stack.push(bvh_ptr + 5);
+                        }
+
+                        // Mark the end of this instance's bottom-level BVH, so
+                        // that we know when to go back to the world-space ray
+                        stack.push(BvhView::SENTINEL);
+
+                        instance_ptr = bvh_ptr;
+                        instance_xform = bvh.instance_xform(bvh_ptr);
+                        instance_material_id = MaterialId::new(d0.z.to_bits());
+                        instance_has_alpha_blending = flags & 2 == 2;
+
+                        ray = Ray::new(
+                            instance_xform.transform_point3(self.origin),
+                            instance_xform.transform_vector3(self.direction),
+                        );
+
+                        bvh_ptr = d0.y.to_bits();
+                        continue;
                     }
-                }
 
-                if found_hit {
-                    hit.material_id = material_id;
+                    // Instance is invisible to this ray (e.g. it's on a layer
+                    // the camera doesn't see or it doesn't cast shadows and
+                    // this is a shadow ray), so let's skip it altogether
+                    if got_more_instances {
+                        bvh_ptr += 5;
+                        continue;
+                    }
+                } else {
+                    traversal.used_memory += mem::size_of::<Triangle>();
+
+                    let flags = d0.x.to_bits();
+
+                    // Whether there are any more triangles directly following
+                    // this triangle.
+                    //
+                    // This corresponds to a single BVH leaf node containing
+                    // multiple triangles.
+                    let got_more_triangles = flags & 1 == 1;
+
+                    let triangle_id = TriangleId::new(d0.y.to_bits());
+
+                    let prev_uv = hit.uv;
+                    let prev_normal = hit.normal;
+                    let prev_distance = hit.distance;
+
+                    let mut found_hit =
+                        triangles.get(triangle_id).hit(ray, hit);
+
+                    // If instance's material supports alpha blending, we have
+                    // to load the material and compute albedo to make sure
+                    // that the part of triangle we hit is actually opaque at
+                    // that particular hit-point.
+                    if found_hit && instance_has_alpha_blending {
+                        traversal.used_memory += mem::size_of::<Material>();
+                        traversal.used_memory += mem::size_of::<Vec4>();
+
+                        if !is_opaque(instance_material_id, hit.uv) {
+                            found_hit = false;
+
+                            hit.uv = prev_uv;
+                            hit.normal = prev_normal;
+                            hit.distance = prev_distance;
+                        }
+                    }
+
+                    if found_hit {
+                        // Triangles live in the object-space, so their normals
+                        // have to be transformed back into the world-space;
+                        // since `instance_xform` is already inversed, all we
+                        // need is the transposition
+                        hit.normal = (instance_xform.matrix3.transpose()
+                            * hit.normal)
+                            .normalize();
 
-                    if let Tracing::ReturnFirst = tracing {
-                        break;
+                        hit.material_id = instance_material_id;
+
+                        traversal.instance_ptr = instance_ptr;
+                        traversal.triangle_id = triangle_id;
+
+                        if let Tracing::ReturnFirst = tracing {
+                            break;
+                        }
                     }
-                }
 
-                if got_more_triangles {
-                    bvh_ptr += 1;
-                    continue;
+                    if got_more_triangles {
+                        bvh_ptr += 1;
+                        continue;
+                    }
                 }
             }
 
@@ -247,21 +401,72 @@ impl Ray {
             // In any case, now it's the time to pop the next node from the
             // stack and investigate it; if the stack is empty, then we've
             // tested all nodes and we can safely bail out.
-            if stack_ptr > stack_begins_at {
-                unsafe {
-                    stack_ptr -= 1;
-                    bvh_ptr = *stack.index_unchecked(stack_ptr);
-                }
-            } else {
+            if stack.is_empty() {
                 break;
             }
+
+            bvh_ptr = stack.pop();
         }
 
         if hit.is_some() {
             hit.point = self.at(hit.distance);
         }
 
-        used_memory
+        traversal
+    }
+
+    /// Checks whether this ray hits children of the wide node located at given
+    /// pointer and returns their intersection distances and pointers, sorted
+    /// from the nearest child to the farthest one.
+    ///
+    /// Missed children have their distances set to `f32::MAX` and missing
+    /// children have their distances set to infinity, so that they never get
+    /// visited, regardless of the ray's length.
+    pub fn intersect_wide_node(self, bvh: BvhView, ptr: u32) -> (Vec4, UVec4) {
+        fn sort(a: &mut (f32, u32), b: &mut (f32, u32)) {
+            if b.0 < a.0 {
+                mem::swap(a, b);
+            }
+        }
+
+        let d0 = bvh.get(ptr);
+        let op = d0.w.to_bits();
+
+        let mut c0 = (0.0, ptr + BvhView::wide_node_size(op));
+        let mut c1 = (0.0, d0.x.to_bits());
+        let mut c2 = (0.0, d0.y.to_bits());
+        let mut c3 = (0.0, d0.z.to_bits());
+
+        c0.0 = self.intersect_wide_node_child(bvh, ptr, op, 0, c0.1);
+        c1.0 = self.intersect_wide_node_child(bvh, ptr, op, 1, c1.1);
+        c2.0 = self.intersect_wide_node_child(bvh, ptr, op, 2, c2.1);
+        c3.0 = self.intersect_wide_node_child(bvh, ptr, op, 3, c3.1);
+
+        // Sorting network for four elements
+        sort(&mut c0, &mut c1);
+        sort(&mut c2, &mut c3);
+        sort(&mut c0, &mut c2);
+        sort(&mut c1, &mut c3);
+        sort(&mut c1, &mut c2);
+
+        (vec4(c0.0, c1.0, c2.0, c3.0), uvec4(c0.1, c1.1, c2.1, c3.1))
+    }
+
+    fn intersect_wide_node_child(
+        self,
+        bvh: BvhView,
+        ptr: u32,
+        op: u32,
+        child_idx: u32,
+        child_ptr: u32,
+    ) -> f32 {
+        if child_ptr == BvhView::SENTINEL {
+            f32::INFINITY
+        } else {
+            let (min, max) = bvh.wide_node_child(ptr, op, child_idx);
+
+            self.intersect_box(min, max)
+        }
     }
 
     /// Checks whether this ray hits given bounding-box and returns their
@@ -320,8 +525,69 @@ impl Ray {
     }
 }
 
+/// Kind of ray, deciding which instances the ray can hit - e.g. shadow rays
+/// skip instances that don't cast shadows.
+///
+/// Values correspond to instances' flags (shifted by two bits); see:
+/// `BvhView`.
 #[derive(Clone, Copy, PartialEq, Eq)]
-enum Tracing {
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+pub struct RayKind(u32);
+
+impl RayKind {
+    /// Ray cast from the camera (i.e. a primary ray).
+    pub const CAMERA: Self = Self(1);
+
+    /// Ray cast towards a light to check whether it's occluded.
+    pub const SHADOW: Self = Self(2);
+
+    /// Ray cast to gather indirect diffuse lighting.
+    pub const INDIRECT: Self = Self(4);
+
+    /// Ray cast to gather indirect specular lighting.
+    pub const REFLECTION: Self = Self(8);
+
+    /// Ray that hits everything that's visible to at least one of the kinds
+    /// above; used e.g. for ray queries.
+    pub const ANY: Self = Self(15);
+
+    pub fn new(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub fn get(self) -> u32 {
+        self.0
+    }
+}
+
+impl Default for RayKind {
+    fn default() -> Self {
+        Self::ANY
+    }
+}
+
+/// Result of [`Ray::traverse()`].
+#[derive(Clone, Copy)]
+pub struct BvhTraversal {
+    /// An estimation of the memory used when travelling the BVH; useful for
+    /// debugging.
+    pub used_memory: usize,
+
+    /// Pointer to the instance whose triangle has been hit last or
+    /// [`BvhView::SENTINEL`] if nothing has been hit; see:
+    /// [`BvhView::instance_idx()`].
+    pub instance_ptr: u32,
+
+    /// Id of the triangle that has been hit last.
+    pub triangle_id: TriangleId,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Tracing {
+    /// Looks for the closest intersection.
     ReturnClosest,
+
+    /// Stops at the first intersection found; used for shadow rays, which
+    /// care only about whether there's any intersection at all.
     ReturnFirst,
 }
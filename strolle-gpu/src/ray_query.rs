@@ -0,0 +1,145 @@
+use glam::{Vec2, Vec3, Vec4, Vec4Swizzles};
+
+use crate::{MaterialId, Ray, TriangleId};
+
+/// Ray submitted through `Engine::trace_rays()`.
+///
+/// In the rays buffer each query occupies two `Vec4`s - see: [`Self::pack()`].
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug, PartialEq))]
+pub struct RayQuery {
+    pub origin: Vec3,
+    pub direction: Vec3,
+    pub max_distance: f32,
+}
+
+impl RayQuery {
+    pub fn ray(&self) -> Ray {
+        Ray::new(self.origin, self.direction).with_length(self.max_distance)
+    }
+
+    pub fn pack(&self) -> [Vec4; 2] {
+        let d0 = self.origin.extend(self.max_distance);
+        let d1 = self.direction.extend(0.0);
+
+        [d0, d1]
+    }
+
+    pub fn unpack([d0, d1]: [Vec4; 2]) -> Self {
+        Self {
+            origin: d0.xyz(),
+            direction: d1.xyz(),
+            max_distance: d0.w,
+        }
+    }
+}
+
+/// Result of tracing a [`RayQuery`].
+///
+/// In the hits buffer each hit occupies three `Vec4`s - see: [`Self::pack()`].
+#[derive(Clone, Copy)]
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug, PartialEq))]
+pub struct RayQueryHit {
+    /// Distance from the ray's origin to the hit point; `f32::MAX` if the ray
+    /// didn't hit anything.
+    pub distance: f32,
+
+    /// Interpolated normal at the hit point.
+    pub normal: Vec3,
+
+    /// Interpolated texture coordinates at the hit point.
+    pub uv: Vec2,
+
+    /// Material of the triangle that's been hit.
+    pub material_id: MaterialId,
+
+    /// Index of the instance that's been hit, within the top-level BVH;
+    /// `u32::MAX` if the ray didn't hit anything.
+    ///
+    /// Can be mapped back to the instance's handle through
+    /// `Engine::resolve_ray_query_hit()`.
+    pub instance_idx: u32,
+
+    /// Id of the triangle that's been hit, within the engine's triangle
+    /// buffer (i.e. shared by all instances of the same mesh).
+    pub triangle_id: TriangleId,
+}
+
+impl RayQueryHit {
+    pub fn none() -> Self {
+        Self {
+            distance: f32::MAX,
+            normal: Default::default(),
+            uv: Default::default(),
+            material_id: MaterialId::new(0),
+            instance_idx: u32::MAX,
+            triangle_id: TriangleId::new(0),
+        }
+    }
+
+    pub fn pack(&self) -> [Vec4; 3] {
+        let d0 = self.normal.extend(self.distance);
+
+        let d1 = self
+            .uv
+            .extend(f32::from_bits(self.material_id.get()))
+            .extend(0.0);
+
+        let d2 = Vec4::new(
+            f32::from_bits(self.instance_idx),
+            f32::from_bits(self.triangle_id.get()),
+            0.0,
+            0.0,
+        );
+
+        [d0, d1, d2]
+    }
+
+    pub fn unpack([d0, d1, d2]: [Vec4; 3]) -> Self {
+        Self {
+            distance: d0.w,
+            normal: d0.xyz(),
+            uv: d1.xy(),
+            material_id: MaterialId::new(d1.z.to_bits()),
+            instance_idx: d2.x.to_bits(),
+            triangle_id: TriangleId::new(d2.y.to_bits()),
+        }
+    }
+
+    pub fn is_some(&self) -> bool {
+        self.distance < f32::MAX
+    }
+
+    pub fn is_none(&self) -> bool {
+        !self.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec2, vec3};
+
+    use super::*;
+
+    #[test]
+    fn serialization() {
+        let query = RayQuery {
+            origin: vec3(1.0, 2.0, 3.0),
+            direction: vec3(0.0, -1.0, 0.0),
+            max_distance: 123.0,
+        };
+
+        assert_eq!(query, RayQuery::unpack(query.pack()));
+
+        let hit = RayQueryHit {
+            distance: 12.5,
+            normal: vec3(0.0, 1.0, 0.0),
+            uv: vec2(0.25, 0.75),
+            material_id: MaterialId::new(1234),
+            instance_idx: 56,
+            triangle_id: TriangleId::new(7890),
+        };
+
+        assert_eq!(hit, RayQueryHit::unpack(hit.pack()));
+    }
+}
@@ -6,7 +6,7 @@ use spirv_std::arch::IndexUnchecked;
 use spirv_std::num_traits::Float;
 
 use crate::utils::U32Ext;
-use crate::{Hit, LightId, LightsView, Ray, Reservoir, Vec3Ext};
+use crate::{Hit, LightId, LightsView, Ray, RayKind, Reservoir, Vec3Ext};
 
 #[derive(Clone, Copy, Default, PartialEq)]
 #[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
@@ -75,7 +75,11 @@ impl DerefMut for DiReservoir {
 #[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
 pub struct DiSample {
     pub light_id: LightId,
+
+    /// Sampled point on the light or, if it's a directional light, sampled
+    /// direction towards the light.
     pub light_point: Vec3,
+
     pub exists: bool,
 }
 
@@ -87,17 +91,31 @@ impl DiSample {
 
         let light = lights.get(self.light_id);
 
-        light.center().distance(self.light_point) <= light.radius()
+        if light.is_directional() {
+            // Small epsilon accounts for the precision lost while sampling the
+            // light's cone
+            light.direction().dot(self.light_point)
+                >= light.angular_radius().cos() - 0.0001
+        } else {
+            light.center().distance(self.light_point) <= light.radius()
+        }
     }
 
     pub fn pdf(&self, lights: LightsView, hit: Hit) -> f32 {
         lights.get(self.light_id).radiance(hit).perc_luma()
     }
 
-    pub fn ray(&self, hit: Hit) -> Ray {
+    pub fn ray(&self, lights: LightsView, hit: Hit) -> Ray {
+        if lights.get(self.light_id).is_directional() {
+            return Ray::new(hit.point, self.light_point)
+                .with_kind(RayKind::SHADOW);
+        }
+
         let dir = hit.point - self.light_point;
 
-        Ray::new(self.light_point, dir.normalize()).with_length(dir.length())
+        Ray::new(self.light_point, dir.normalize())
+            .with_length(dir.length())
+            .with_kind(RayKind::SHADOW)
     }
 }
 
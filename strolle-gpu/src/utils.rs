@@ -1,3 +1,4 @@
+mod affine3a_ext;
 mod bilinear_filter;
 mod f32_ext;
 mod u32_ext;
@@ -9,6 +10,7 @@ use core::ops;
 use glam::{uvec2, UVec2};
 use spirv_std::Image;
 
+pub use self::affine3a_ext::*;
 pub use self::bilinear_filter::*;
 pub use self::f32_ext::*;
 pub use self::u32_ext::*;
@@ -0,0 +1,51 @@
+use glam::{vec3a, vec4, Affine3A, Mat3A, Vec4};
+
+pub trait Affine3AExt
+where
+    Self: Sized,
+{
+    /// Encodes a 3D affine transformation as three Vec4s; we use this to
+    /// overcome padding issues when copying data from CPU into GPU.
+    fn encode(self) -> [Vec4; 3];
+
+    /// See: [`Self::encode()`].
+    fn decode(d: [Vec4; 3]) -> Self;
+}
+
+impl Affine3AExt for Affine3A {
+    fn encode(self) -> [Vec4; 3] {
+        let d0 = vec4(
+            self.matrix3.x_axis.x,
+            self.matrix3.x_axis.y,
+            self.matrix3.x_axis.z,
+            self.translation.x,
+        );
+
+        let d1 = vec4(
+            self.matrix3.y_axis.x,
+            self.matrix3.y_axis.y,
+            self.matrix3.y_axis.z,
+            self.translation.y,
+        );
+
+        let d2 = vec4(
+            self.matrix3.z_axis.x,
+            self.matrix3.z_axis.y,
+            self.matrix3.z_axis.z,
+            self.translation.z,
+        );
+
+        [d0, d1, d2]
+    }
+
+    fn decode([d0, d1, d2]: [Vec4; 3]) -> Self {
+        Self {
+            matrix3: Mat3A {
+                x_axis: vec3a(d0.x, d0.y, d0.z),
+                y_axis: vec3a(d1.x, d1.y, d1.z),
+                z_axis: vec3a(d2.x, d2.y, d2.z),
+            },
+            translation: vec3a(d0.w, d1.w, d2.w),
+        }
+    }
+}
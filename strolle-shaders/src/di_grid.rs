@@ -0,0 +1,71 @@
+//! This pass fills the light grid, i.e. for each of its cells it picks a few
+//! lights that are likely to be important for that cell; see: [`LightGrid`].
+
+use strolle_gpu::prelude::*;
+
+/// Number of lights considered for each reservoir of the grid.
+const CANDIDATES: u32 = 8;
+
+#[spirv(compute(threads(64)))]
+#[allow(clippy::too_many_arguments)]
+pub fn main(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(push_constant)] params: &PassParams,
+    #[spirv(descriptor_set = 0, binding = 0, storage_buffer)]
+    lights: &[Light],
+    #[spirv(descriptor_set = 0, binding = 1, uniform)] world: &World,
+    #[spirv(descriptor_set = 1, binding = 0, uniform)] camera: &Camera,
+    #[spirv(descriptor_set = 1, binding = 1, storage_buffer)]
+    light_grid: &mut [Vec4],
+) {
+    let res_id = global_id.x;
+    let mut wnoise = WhiteNoise::new(params.seed, global_id.xy());
+    let lights = LightsView::new(lights);
+    let grid = LightGrid::new(camera);
+
+    if res_id >= LightGrid::RESERVOIRS {
+        return;
+    }
+
+    // -------------------------------------------------------------------------
+
+    let (cell_min, cell_max) =
+        grid.cell_bounds(res_id / LightGrid::RESERVOIRS_PER_CELL);
+
+    let mut res = Reservoir::<LightId>::default();
+    let mut res_pdf = 0.0;
+
+    // Candidates are picked uniformly from all of the regular lights except
+    // for the sun (which is always considered separately) and then resampled
+    // according to their importance for this cell.
+    //
+    // Each cell has got many reservoirs, so that `di_shading` still gets a
+    // varied set of candidates out of them.
+    if world.light_count > 1 {
+        let light_count = world.light_count - 1;
+        let mut candidate_idx = 0;
+
+        while candidate_idx < CANDIDATES {
+            let light_id = LightId::new(
+                1 + ((wnoise.sample() * (light_count as f32)) as u32)
+                    .min(light_count - 1),
+            );
+
+            let light_pdf =
+                LightGrid::importance(lights.get(light_id), cell_min, cell_max);
+
+            let light_weight =
+                light_pdf * (light_count as f32) / (CANDIDATES as f32);
+
+            if res.update(&mut wnoise, light_id, light_weight) {
+                res_pdf = light_pdf;
+            }
+
+            candidate_idx += 1;
+        }
+    }
+
+    res.normalize_ex(res_pdf, 1.0, 1.0);
+
+    LightGrid::write(light_grid, res_id, &res);
+}
@@ -1,5 +1,15 @@
 use strolle_gpu::prelude::*;
 
+/// Number of lights picked from the light tree for each pixel.
+const LIGHT_TREE_CANDIDATES: u32 = 8;
+
+/// Number of lights picked from the light grid for each pixel, when the camera
+/// uses one.
+const LIGHT_GRID_CANDIDATES: u32 = 8;
+
+/// Number of triangle lights considered for each pixel.
+const TRIANGLE_LIGHT_CANDIDATES: u32 = 8;
+
 #[spirv(compute(threads(8, 8)))]
 #[allow(clippy::too_many_arguments)]
 pub fn main(
@@ -18,11 +28,15 @@ pub fn main(
     #[spirv(descriptor_set = 0, binding = 5)] atlas_tex: Tex,
     #[spirv(descriptor_set = 0, binding = 6)] atlas_sampler: &Sampler,
     #[spirv(descriptor_set = 0, binding = 7, uniform)] world: &World,
+    #[spirv(descriptor_set = 0, binding = 8, storage_buffer)]
+    light_tree: &[Vec4],
     #[spirv(descriptor_set = 1, binding = 0, uniform)] camera: &Camera,
     #[spirv(descriptor_set = 1, binding = 1)] prim_gbuffer_d0: TexRgba32,
     #[spirv(descriptor_set = 1, binding = 2)] prim_gbuffer_d1: TexRgba32,
     #[spirv(descriptor_set = 1, binding = 3, storage_buffer)]
     curr_reservoirs: &mut [Vec4],
+    #[spirv(descriptor_set = 1, binding = 4, storage_buffer)]
+    light_grid: &[Vec4],
 ) {
     let screen_pos = global_id.xy();
     let screen_idx = camera.screen_to_idx(screen_pos);
@@ -32,6 +46,8 @@ pub fn main(
     let bvh = BvhView::new(bvh);
     let materials = MaterialsView::new(materials);
     let lights = LightsView::new(lights);
+    let light_tree = LightTreeView::new(light_tree);
+    let grid = LightGrid::new(camera);
 
     if !camera.contains(screen_pos) {
         return;
@@ -56,11 +72,13 @@ pub fn main(
     let mut res = EphemeralReservoir::default();
     let mut res_pdf = 0.0;
 
-    let light_pdf = 1.0 / (world.light_count as f32);
-    let mut light_idx = 0;
-
-    while light_idx < world.light_count {
-        let light_id = LightId::new(light_idx);
+    // The sun is always considered, since it's infinitely far away and so it
+    // doesn't fit into the light tree.
+    //
+    // Each candidate's weight is divided by the number of candidates of its
+    // own kind and by the probability of picking it - here both are one.
+    {
+        let light_id = LightId::new(0);
         let light_radiance = lights.get(light_id).radiance(hit);
 
         let sample = EphemeralSample {
@@ -70,21 +88,137 @@ pub fn main(
 
         let sample_pdf = sample.pdf();
 
-        if res.update(&mut wnoise, sample, sample_pdf / light_pdf) {
+        if res.update(&mut wnoise, sample, sample_pdf) {
             res_pdf = sample_pdf;
         }
+    }
+
+    // Other lights might be numerous, so instead of going through all of them,
+    // let's pick a few candidates from the light grid's cell (if the camera
+    // uses one) or from the light tree, proportionally to their estimated
+    // contribution.
+    //
+    // Mixing different kinds of candidates within the same reservoir is fine,
+    // since a light can be picked only by one of them.
+    if world.light_count > 1
+        && camera.uses_light_grid()
+        && grid.contains(hit.point)
+    {
+        let cell_id = grid.cell_idx(hit.point);
+        let mut candidate_idx = 0;
+
+        while candidate_idx < LIGHT_GRID_CANDIDATES {
+            let res_id = cell_id * LightGrid::RESERVOIRS_PER_CELL
+                + ((wnoise.sample() * (LightGrid::RESERVOIRS_PER_CELL as f32))
+                    as u32)
+                    .min(LightGrid::RESERVOIRS_PER_CELL - 1);
+
+            let cell_res = LightGrid::read(light_grid, res_id);
+
+            // Grid's reservoirs have already been normalized, so their weight
+            // works as the inverse probability of picking the light
+            if cell_res.w > 0.0 {
+                let light_id = cell_res.sample;
+                let light_radiance = lights.get(light_id).radiance(hit);
+
+                let sample = EphemeralSample {
+                    light_id,
+                    light_radiance,
+                };
+
+                let sample_pdf = sample.pdf();
+
+                let sample_weight =
+                    sample_pdf * cell_res.w / (LIGHT_GRID_CANDIDATES as f32);
+
+                if res.update(&mut wnoise, sample, sample_weight) {
+                    res_pdf = sample_pdf;
+                }
+            }
+
+            candidate_idx += 1;
+        }
+    } else if world.light_count > 1 {
+        let mut candidate_idx = 0;
+
+        while candidate_idx < LIGHT_TREE_CANDIDATES {
+            let (light_id, light_pdf) = light_tree.sample(
+                hit.point,
+                hit.gbuffer.normal,
+                wnoise.sample(),
+            );
+
+            if light_pdf > 0.0 {
+                let light_radiance = lights.get(light_id).radiance(hit);
+
+                let sample = EphemeralSample {
+                    light_id,
+                    light_radiance,
+                };
 
-        light_idx += 1;
+                let sample_pdf = sample.pdf();
+
+                let sample_weight =
+                    sample_pdf / ((LIGHT_TREE_CANDIDATES as f32) * light_pdf);
+
+                if res.update(&mut wnoise, sample, sample_weight) {
+                    res_pdf = sample_pdf;
+                }
+            }
+
+            candidate_idx += 1;
+        }
     }
 
-    res.normalize(res_pdf);
+    // Same goes for emissive triangles, which are picked proportionally to
+    // their power.
+    if world.triangle_light_count > 0 {
+        let mut candidate_idx = 0;
+
+        while candidate_idx < TRIANGLE_LIGHT_CANDIDATES {
+            let (light_id, light_pdf) =
+                lights.sample_triangle(world, wnoise.sample());
+
+            let light = lights.get(light_id);
+
+            // Emissive triangles of instances the camera doesn't see don't
+            // light anything up for it either
+            let is_visible = light.triangle_visibility_mask()
+                & camera.visibility_mask()
+                != 0;
+
+            if light_pdf > 0.0 && is_visible {
+                let light_radiance = light.radiance(hit);
+
+                let sample = EphemeralSample {
+                    light_id,
+                    light_radiance,
+                };
+
+                let sample_pdf = sample.pdf();
+
+                let sample_weight = sample_pdf
+                    / ((TRIANGLE_LIGHT_CANDIDATES as f32) * light_pdf);
+
+                if res.update(&mut wnoise, sample, sample_weight) {
+                    res_pdf = sample_pdf;
+                }
+            }
+
+            candidate_idx += 1;
+        }
+    }
+
+    res.normalize_ex(res_pdf, 1.0, 1.0);
 
     // ---
 
     let res = if res.m > 0.0 {
-        let ray = lights
-            .get(res.sample.light_id)
-            .ray_bnoise(bnoise.first_sample(), hit.point);
+        let light = lights.get(res.sample.light_id);
+
+        let ray = light
+            .ray_bnoise(bnoise.first_sample(), hit.point)
+            .with_visibility_mask(camera.visibility_mask());
 
         let is_occluded = ray.intersect(
             local_idx,
@@ -104,7 +238,11 @@ pub fn main(
             reservoir: Reservoir {
                 sample: DiSample {
                     light_id: res.sample.light_id,
-                    light_point: ray.origin(),
+                    light_point: if light.is_directional() {
+                        ray.direction()
+                    } else {
+                        ray.origin()
+                    },
                     exists: true,
                 },
                 m: res.m,
@@ -118,15 +118,19 @@ pub fn main(
         }
 
         if found {
-            let is_occluded = sample.sample.ray(hit).intersect(
-                local_idx,
-                stack,
-                triangles,
-                bvh,
-                materials,
-                atlas_tex,
-                atlas_sampler,
-            );
+            let is_occluded = sample
+                .sample
+                .ray(lights, hit)
+                .with_visibility_mask(camera.visibility_mask())
+                .intersect(
+                    local_idx,
+                    stack,
+                    triangles,
+                    bvh,
+                    materials,
+                    atlas_tex,
+                    atlas_sampler,
+                );
 
             if is_occluded {
                 sample.m = 0.0;
@@ -109,7 +109,10 @@ pub fn main(
     let mut pi_sum = main_pdf * curr_m;
 
     if (prev_m > 0.0) & main.sample.exists {
-        let ray = main.sample.ray(hit);
+        let ray = main
+            .sample
+            .ray(lights, hit)
+            .with_visibility_mask(camera.visibility_mask());
         let mut is_occluded = false;
 
         if !is_occluded & (selected == 2) {
@@ -1,6 +1,16 @@
 use spirv_std::arch::IndexUnchecked;
 use strolle_gpu::prelude::*;
 
+/// Number of lights picked from the light tree for each indirect hit.
+const LIGHT_TREE_CANDIDATES: u32 = 4;
+
+/// Number of lights picked from the light grid for each indirect hit, when the
+/// camera uses one.
+const LIGHT_GRID_CANDIDATES: u32 = 4;
+
+/// Number of triangle lights considered for each indirect hit.
+const TRIANGLE_LIGHT_CANDIDATES: u32 = 4;
+
 #[spirv(compute(threads(8, 8)))]
 #[allow(clippy::too_many_arguments)]
 pub fn main(
@@ -18,6 +28,8 @@ pub fn main(
     #[spirv(descriptor_set = 0, binding = 4)] atlas_tex: Tex,
     #[spirv(descriptor_set = 0, binding = 5)] atlas_sampler: &Sampler,
     #[spirv(descriptor_set = 0, binding = 6, uniform)] world: &World,
+    #[spirv(descriptor_set = 0, binding = 7, storage_buffer)]
+    light_tree: &[Vec4],
     #[spirv(descriptor_set = 1, binding = 0, uniform)] camera: &Camera,
     #[spirv(descriptor_set = 1, binding = 1)]
     atmosphere_transmittance_lut_tex: Tex,
@@ -33,6 +45,8 @@ pub fn main(
     #[spirv(descriptor_set = 1, binding = 9)] gi_gbuffer_d1: TexRgba32,
     #[spirv(descriptor_set = 1, binding = 10, storage_buffer)]
     gi_samples: &mut [Vec4],
+    #[spirv(descriptor_set = 1, binding = 11, storage_buffer)]
+    light_grid: &[Vec4],
 ) {
     let global_id = global_id.xy();
     let screen_pos = checkerboard(global_id, params.frame);
@@ -41,6 +55,8 @@ pub fn main(
     let triangles = TrianglesView::new(triangles);
     let bvh = BvhView::new(bvh);
     let lights = LightsView::new(lights);
+    let light_tree = LightTreeView::new(light_tree);
+    let grid = LightGrid::new(camera);
     let materials = MaterialsView::new(materials);
     let atmosphere = Atmosphere::new(
         atmosphere_transmittance_lut_tex,
@@ -117,11 +133,12 @@ pub fn main(
                 atmosphere.sample(world.sun_direction(), light_dir, 32.0)
                     * gi_hit.gbuffer.normal.dot(light_dir);
         } else {
+            // Lights are picked the same way `di_shading` picks them, just
+            // with fewer candidates - see there for details
             let mut res = EphemeralReservoir::default();
-            let mut light_idx = 0;
 
-            while light_idx < world.light_count {
-                let light_id = LightId::new(light_idx);
+            {
+                let light_id = LightId::new(0);
                 let light_radiance = lights.get(light_id).radiance(gi_hit);
 
                 let sample = EphemeralSample {
@@ -130,9 +147,106 @@ pub fn main(
                 };
 
                 res.update(&mut wnoise, sample, sample.pdf());
-                light_idx += 1;
             }
 
+            if world.light_count > 1
+                && camera.uses_light_grid()
+                && grid.contains(gi_hit.point)
+            {
+                let cell_id = grid.cell_idx(gi_hit.point);
+                let mut candidate_idx = 0;
+
+                while candidate_idx < LIGHT_GRID_CANDIDATES {
+                    let res_id = cell_id * LightGrid::RESERVOIRS_PER_CELL
+                        + ((wnoise.sample()
+                            * (LightGrid::RESERVOIRS_PER_CELL as f32))
+                            as u32)
+                            .min(LightGrid::RESERVOIRS_PER_CELL - 1);
+
+                    let cell_res = LightGrid::read(light_grid, res_id);
+
+                    if cell_res.w > 0.0 {
+                        let light_id = cell_res.sample;
+
+                        let light_radiance =
+                            lights.get(light_id).radiance(gi_hit);
+
+                        let sample = EphemeralSample {
+                            light_id,
+                            light_radiance,
+                        };
+
+                        let sample_weight = sample.pdf() * cell_res.w
+                            / (LIGHT_GRID_CANDIDATES as f32);
+
+                        res.update(&mut wnoise, sample, sample_weight);
+                    }
+
+                    candidate_idx += 1;
+                }
+            } else if world.light_count > 1 {
+                let mut candidate_idx = 0;
+
+                while candidate_idx < LIGHT_TREE_CANDIDATES {
+                    let (light_id, light_pdf) = light_tree.sample(
+                        gi_hit.point,
+                        gi_hit.gbuffer.normal,
+                        wnoise.sample(),
+                    );
+
+                    if light_pdf > 0.0 {
+                        let light_radiance =
+                            lights.get(light_id).radiance(gi_hit);
+
+                        let sample = EphemeralSample {
+                            light_id,
+                            light_radiance,
+                        };
+
+                        let sample_weight = sample.pdf()
+                            / ((LIGHT_TREE_CANDIDATES as f32) * light_pdf);
+
+                        res.update(&mut wnoise, sample, sample_weight);
+                    }
+
+                    candidate_idx += 1;
+                }
+            }
+
+            if world.triangle_light_count > 0 {
+                let mut candidate_idx = 0;
+
+                while candidate_idx < TRIANGLE_LIGHT_CANDIDATES {
+                    let (light_id, light_pdf) =
+                        lights.sample_triangle(world, wnoise.sample());
+
+                    let light = lights.get(light_id);
+
+                    let is_visible = light.triangle_visibility_mask()
+                        & camera.visibility_mask()
+                        != 0;
+
+                    if light_pdf > 0.0 && is_visible {
+                        let light_radiance = light.radiance(gi_hit);
+
+                        let sample = EphemeralSample {
+                            light_id,
+                            light_radiance,
+                        };
+
+                        let sample_weight = sample.pdf()
+                            / ((TRIANGLE_LIGHT_CANDIDATES as f32) * light_pdf);
+
+                        res.update(&mut wnoise, sample, sample_weight);
+                    }
+
+                    candidate_idx += 1;
+                }
+            }
+
+            // Candidates' weights already account for the probabilities of
+            // picking them, so the sum of weights turns the reservoir into
+            // an estimate of the total contribution of all lights
             if res.w > 0.0 {
                 light_id = res.sample.light_id;
                 light_pdf = (res.sample.pdf() / res.w) * (1.0 - atmosphere_pdf);
@@ -150,11 +264,13 @@ pub fn main(
     let mut radiance = if light_pdf > 0.0 {
         let light_visibility = if gi_hit.is_some() {
             let ray = if light_id == LightId::sky() {
-                Ray::new(gi_hit.point, light_dir)
+                Ray::new(gi_hit.point, light_dir).with_kind(RayKind::SHADOW)
             } else {
                 lights.get(light_id).ray_wnoise(&mut wnoise, gi_hit.point)
             };
 
+            let ray = ray.with_visibility_mask(camera.visibility_mask());
+
             let is_occluded = ray.intersect(
                 local_idx,
                 stack,
@@ -84,10 +84,18 @@ pub fn main(
         }
     };
 
+    let ray_kind = if params.is_diff() {
+        RayKind::INDIRECT
+    } else {
+        RayKind::REFLECTION
+    };
+
     let ray = Ray::new(
         prim_hit.point + prim_hit.gbuffer.normal * 0.001,
         gi_ray_direction,
-    );
+    )
+    .with_visibility_mask(camera.visibility_mask())
+    .with_kind(ray_kind);
 
     let (gi_hit, _) = ray.trace(
         local_idx,
@@ -106,6 +114,18 @@ pub fn main(
 
         gi_material.regularize();
 
+        // Emission of triangle lights reaches the primary surface through
+        // direct lighting already (see: `di_shading`), so counting it here as
+        // well would make those surfaces twice as bright.
+        //
+        // (that's not the case for reflections, though, since direct lighting
+        // accounts for the diffuse lobe only.)
+        let emissive = if params.is_diff() && gi_material.is_triangle_light() {
+            Vec3::ZERO
+        } else {
+            gi_material.emissive(atlas_tex, atlas_sampler, gi_hit.uv)
+        };
+
         GBufferEntry {
             base_color: gi_material.base_color(
                 atlas_tex,
@@ -114,7 +134,7 @@ pub fn main(
             ),
             normal: gi_hit.normal,
             metallic: gi_material.metallic,
-            emissive: gi_material.emissive(atlas_tex, atlas_sampler, gi_hit.uv),
+            emissive,
             roughness: gi_material.roughness,
             reflectance: gi_material.reflectance,
             depth: prim_hit.point.distance(gi_hit.point),
@@ -2,6 +2,7 @@
 
 pub mod atmosphere;
 pub mod bvh_heatmap;
+pub mod di_grid;
 pub mod di_resolving;
 pub mod di_shading;
 pub mod di_spatial_resampling;
@@ -17,5 +18,6 @@ pub mod gi_spec_resampling;
 pub mod gi_spec_resolving;
 pub mod gi_tracing;
 pub mod prim_raster;
+pub mod ray_queries;
 pub mod ref_shading;
 pub mod ref_tracing;
@@ -12,6 +12,8 @@ pub fn vs(
     // Inputs
     vertex_d0: Vec4,
     vertex_d1: Vec4,
+    _vertex_d2: Vec4,
+    prev_vertex_d0: Vec4,
 
     // Outputs
     #[spirv(position)] out_vertex: &mut Vec4,
@@ -21,13 +23,21 @@ pub fn vs(
     out_normal: &mut Vec3,
     out_uv: &mut Vec2,
 ) {
-    let point = vertex_d0.xyz();
-
-    let prev_point = params
-        .prev_xform()
-        .transform_point3(params.curr_xform_inv().transform_point3(point));
-
-    let normal = vertex_d1.xyz();
+    // Vertices are stored in object-space (so that all instances of the same
+    // mesh can share them), so let's transform them into world-space
+    let curr_xform = params.curr_xform();
+    let point = curr_xform.transform_point3(vertex_d0.xyz());
+
+    // Deforming meshes (e.g. skinned ones) change their vertices from frame to
+    // frame, so for motion vectors to be correct we have to use the position
+    // the vertex had in the previous frame as well
+    let prev_point = params.prev_xform().transform_point3(prev_vertex_d0.xyz());
+
+    // Transforming normals requires inversing and transposing the matrix in
+    // order to get correct results under scaling, see:
+    //
+    // https://paroj.github.io/gltut/Illumination/Tut09%20Normal%20Transformation.html
+    let normal = curr_xform.matrix3.inverse().transpose() * vertex_d1.xyz();
     let uv = vec2(vertex_d0.w, vertex_d1.w);
 
     *out_vertex = camera.world_to_clip(point);
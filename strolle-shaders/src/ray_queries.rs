@@ -0,0 +1,74 @@
+use strolle_gpu::prelude::*;
+
+#[spirv(compute(threads(64)))]
+#[allow(clippy::too_many_arguments)]
+pub fn main(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(local_invocation_index)] local_idx: u32,
+    #[spirv(push_constant)] params: &RayQueriesPassParams,
+    #[spirv(workgroup)] stack: BvhStack,
+    #[spirv(descriptor_set = 0, binding = 0, storage_buffer)]
+    triangles: &[Triangle],
+    #[spirv(descriptor_set = 0, binding = 1, storage_buffer)] bvh: &[Vec4],
+    #[spirv(descriptor_set = 0, binding = 2, storage_buffer)]
+    materials: &[Material],
+    #[spirv(descriptor_set = 0, binding = 3)] atlas_tex: Tex,
+    #[spirv(descriptor_set = 0, binding = 4)] atlas_sampler: &Sampler,
+    #[spirv(descriptor_set = 1, binding = 0, storage_buffer)] rays: &[Vec4],
+    #[spirv(descriptor_set = 1, binding = 1, storage_buffer)]
+    hits: &mut [Vec4],
+) {
+    let query_idx = (params.offset + global_id.x) as usize;
+    let triangles = TrianglesView::new(triangles);
+    let bvh = BvhView::new(bvh);
+    let materials = MaterialsView::new(materials);
+
+    if query_idx >= params.count as usize {
+        return;
+    }
+
+    // -------------------------------------------------------------------------
+
+    let query =
+        RayQuery::unpack([rays[2 * query_idx], rays[2 * query_idx + 1]]);
+
+    // Same as `Ray::trace()`, but we need to know which instance and triangle
+    // have been hit as well
+    let mut hit = TriangleHit::none();
+
+    let traversal = query.ray().traverse(
+        &mut WorkgroupBvhStack::new(stack, local_idx),
+        triangles,
+        bvh,
+        Tracing::ReturnClosest,
+        move |material_id, uv| {
+            materials
+                .get(material_id)
+                .base_color(atlas_tex, atlas_sampler, uv)
+                .w
+                >= 1.0
+        },
+        &mut hit,
+    );
+
+    let hit = if hit.distance <= query.max_distance
+        && traversal.instance_ptr != BvhView::SENTINEL
+    {
+        RayQueryHit {
+            distance: hit.distance,
+            normal: hit.normal,
+            uv: hit.uv,
+            material_id: hit.material_id,
+            instance_idx: bvh.instance_idx(traversal.instance_ptr),
+            triangle_id: traversal.triangle_id,
+        }
+    } else {
+        RayQueryHit::none()
+    };
+
+    let [hit_d0, hit_d1, hit_d2] = hit.pack();
+
+    hits[3 * query_idx] = hit_d0;
+    hits[3 * query_idx + 1] = hit_d1;
+    hits[3 * query_idx + 2] = hit_d2;
+}
@@ -58,7 +58,7 @@ pub fn main(
             Default::default()
         };
 
-        let curr_color = rays[3 * screen_idx + 2].xyz();
+        let curr_color = rays[4 * screen_idx + 2].xyz();
 
         unsafe {
             colors.write(screen_pos, prev_color + curr_color.extend(1.0));
@@ -78,9 +78,9 @@ pub fn main(
         color = Vec3::ZERO;
         throughput = Vec3::ONE;
     } else {
-        let d0 = rays[3 * screen_idx];
-        let d1 = rays[3 * screen_idx + 1];
-        let d2 = rays[3 * screen_idx + 2];
+        let d0 = rays[4 * screen_idx];
+        let d1 = rays[4 * screen_idx + 1];
+        let d2 = rays[4 * screen_idx + 2];
 
         ray = Ray::new(d0.xyz(), d1.xyz());
         color = d2.xyz();
@@ -94,8 +94,8 @@ pub fn main(
         ]);
 
         if t_hit.is_none() {
-            rays[3 * screen_idx] = Default::default();
-            rays[3 * screen_idx + 1] = Default::default();
+            rays[4 * screen_idx] = Default::default();
+            rays[4 * screen_idx + 1] = Default::default();
 
             color += throughput
                 * atmosphere.sample(
@@ -104,7 +104,7 @@ pub fn main(
                     1.0,
                 );
 
-            rays[3 * screen_idx + 2] = color.extend(Default::default());
+            rays[4 * screen_idx + 2] = color.extend(Default::default());
 
             return;
         }
@@ -145,8 +145,10 @@ pub fn main(
 
         let light = lights.get(LightId::new(light_id));
 
-        let is_light_occluded =
-            light.ray_wnoise(&mut wnoise, hit.point).intersect(
+        let is_light_occluded = light
+            .ray_wnoise(&mut wnoise, hit.point)
+            .with_visibility_mask(camera.visibility_mask())
+            .intersect(
                 local_idx,
                 stack,
                 triangles,
@@ -166,8 +168,8 @@ pub fn main(
     let reflected_sample = LayeredBrdf::sample(&mut wnoise, hit);
 
     if reflected_sample.is_invalid() {
-        rays[3 * screen_idx] = Default::default();
-        rays[3 * screen_idx + 1] = Default::default();
+        rays[4 * screen_idx] = Default::default();
+        rays[4 * screen_idx + 1] = Default::default();
         return;
     }
 
@@ -178,9 +180,12 @@ pub fn main(
 
     // -------------------------------------------------------------------------
 
-    rays[3 * screen_idx] = reflected_ray.origin().extend(throughput.x);
+    rays[4 * screen_idx] = reflected_ray.origin().extend(throughput.x);
 
-    rays[3 * screen_idx + 1] = reflected_ray.direction().extend(throughput.y);
+    rays[4 * screen_idx + 1] = reflected_ray.direction().extend(throughput.y);
 
-    rays[3 * screen_idx + 2] = color.extend(throughput.z);
+    rays[4 * screen_idx + 2] = color.extend(throughput.z);
+
+    rays[4 * screen_idx + 3] =
+        vec4(f32::from_bits(reflected_sample.kind.get()), 0.0, 0.0, 0.0);
 }
@@ -34,14 +34,19 @@ pub fn main(
     let ray = if params.depth == 0 {
         camera.ray(screen_pos)
     } else {
-        let d0 = rays[3 * screen_idx];
-        let d1 = rays[3 * screen_idx + 1];
+        let d0 = rays[4 * screen_idx];
+        let d1 = rays[4 * screen_idx + 1];
+        let d3 = rays[4 * screen_idx + 3];
 
         if d1 == Default::default() {
             return;
         }
 
+        // Bounces off the diffuse lobe count as indirect rays, while bounces
+        // off the specular lobe count as reflections; see: `ref_shading`
         Ray::new(d0.xyz(), d1.xyz())
+            .with_visibility_mask(camera.visibility_mask())
+            .with_kind(RayKind::new(d3.x.to_bits()))
     };
 
     let (hit, _) = ray.trace(
@@ -1,73 +1,393 @@
+mod blas;
 mod builder;
+mod cache;
+mod config;
+mod exporter;
+mod instance;
 mod node;
 mod nodes;
 mod primitive;
 mod primitives;
 mod serializer;
+mod spatial_builder;
+mod stats;
+mod tree;
 
+use std::collections::BTreeMap;
 use std::fmt::Debug;
-use std::ops::Range;
+use std::{io, mem};
 
-use spirv_std::glam::Vec4;
+use log::warn;
+use spirv_std::glam::{vec4, Vec3, Vec4};
 
+pub use self::blas::*;
 pub use self::builder::*;
+pub use self::cache::*;
+pub use self::config::*;
+pub use self::instance::*;
 pub use self::node::*;
 pub use self::nodes::*;
 pub use self::primitive::*;
 pub use self::primitives::*;
-use crate::{
-    utils, Bindable, BufferFlushOutcome, MappedStorageBuffer, Materials, Params,
-};
+pub use self::stats::*;
+pub use self::tree::*;
+use crate::gpu::Affine3AExt;
+use crate::{gpu, utils, Bindable, BufferFlushOutcome, MappedStorageBuffer};
 
+/// Two-level acceleration structure: bottom-level BVHs built over triangles of
+/// each mesh and a top-level BVH built over instances.
+///
+/// When meshes get deformed or instances get moved, their BVHs are refitted
+/// instead of being rebuilt, until their quality degrades too much; see:
+/// [`BvhTree::refit()`].
+///
+/// See `BvhView` in `strolle-gpu` for the buffer's layout.
 #[derive(Debug)]
 pub struct Bvh {
     buffer: MappedStorageBuffer<Vec<Vec4>>,
-    nodes: BvhNodes,
-    primitives: BvhPrimitives,
+    blases: BTreeMap<BlasId, BvhTree>,
+    next_blas_id: u32,
+    any_blas_changed: bool,
+    tlas: BvhTree,
+
+    /// Whether the next refresh should rebuild the trees, even if they could
+    /// be refitted.
+    force_rebuild: bool,
+
+    config: BvhConfig,
+    cache: Option<BvhCache>,
+
+    /// Where the top-level BVH starts within the buffer (bottom-level BVHs
+    /// are located before it).
+    tlas_at: usize,
+
+    /// Where the modified part of the buffer starts, if anything has been
+    /// modified.
+    dirty_from: Option<usize>,
 }
 
 impl Bvh {
     pub fn new(device: &wgpu::Device) -> Self {
         Self {
-            buffer: MappedStorageBuffer::new_default(device, "bvh"),
-            nodes: Default::default(),
-            primitives: Default::default(),
+            buffer: MappedStorageBuffer::new(
+                device,
+                "bvh",
+                vec![Self::header(gpu::BvhView::SENTINEL)],
+            ),
+            blases: Default::default(),
+            next_blas_id: 0,
+            any_blas_changed: false,
+            tlas: Default::default(),
+            force_rebuild: false,
+            config: Default::default(),
+            cache: None,
+            tlas_at: 1,
+            dirty_from: Some(0),
+        }
+    }
+
+    pub fn set_config(&mut self, mut config: BvhConfig) {
+        if let Err(err) = config.validate() {
+            warn!("{err}; falling back to two bins");
+
+            config.bins = 2;
         }
+
+        self.config = config;
     }
 
-    pub fn add(&mut self, prim: BvhPrimitive) {
-        self.primitives.add(prim);
+    pub fn set_cache(&mut self, cache: Option<BvhCache>) {
+        self.cache = cache;
     }
 
-    pub fn update(
+    /// Builds a bottom-level BVH over given triangles, where the first triangle
+    /// has id `first_triangle_id`, the second one `first_triangle_id + 1` etc.
+    ///
+    /// If the cache is enabled, the BVH gets loaded from it (or, if it's not
+    /// there yet, stored in it after being built).
+    ///
+    /// The BVH doesn't get uploaded into the buffer until the next
+    /// [`Self::refresh()`].
+    pub fn create_blas(
         &mut self,
-        ids: Range<usize>,
-    ) -> impl Iterator<Item = &mut BvhPrimitive> {
-        self.primitives.update(ids)
+        first_triangle_id: usize,
+        triangles: &[gpu::Triangle],
+    ) -> BlasId {
+        let id = BlasId::new(self.next_blas_id);
+
+        self.next_blas_id += 1;
+
+        let cache_key = self
+            .cache
+            .is_some()
+            .then(|| BvhCache::key(triangles, self.config));
+
+        if let (Some(cache), Some(key)) = (&self.cache, cache_key) {
+            let blas = utils::measure("tick.bvh.cache.load", || {
+                cache.load(&key, first_triangle_id as u32)
+            });
+
+            if let Some(blas) = blas {
+                self.blases.insert(id, blas);
+                self.any_blas_changed = true;
+
+                return id;
+            }
+        }
+
+        self.blases.insert(id, BvhTree::default());
+        self.rebuild_blas(id, first_triangle_id, triangles);
+
+        if let (Some(cache), Some(key)) = (&self.cache, cache_key) {
+            let result = utils::measure("tick.bvh.cache.save", || {
+                cache.save(&key, &self.blases[&id], first_triangle_id as u32)
+            });
+
+            if let Err(err) = result {
+                warn!("Couldn't save BVH into cache: {err}");
+            }
+        }
+
+        id
     }
 
-    pub fn refresh<P>(&mut self, materials: &Materials<P>)
-    where
-        P: Params,
-    {
-        utils::measure("tick.bvh.begin", || {
-            self.primitives.begin_refresh();
-        });
+    /// Updates a bottom-level BVH after its triangles have been moved (but
+    /// not added or removed), refitting it if possible; see:
+    /// [`Self::create_blas()`].
+    pub fn update_blas(
+        &mut self,
+        id: BlasId,
+        first_triangle_id: usize,
+        triangles: &[gpu::Triangle],
+    ) {
+        let primitives =
+            Self::triangle_primitives(first_triangle_id, triangles);
+        let blas = self.blas_mut(id);
+
+        let refitted =
+            utils::measure("tick.bvh.blas", || blas.refit(&primitives));
 
-        utils::measure("tick.bvh.build", || {
-            builder::run(&mut self.nodes, &mut self.primitives);
+        if refitted {
+            self.any_blas_changed = true;
+        } else {
+            self.rebuild_blas(id, first_triangle_id, triangles);
+        }
+    }
+
+    /// Rebuilds a bottom-level BVH from scratch; see: [`Self::create_blas()`].
+    pub fn rebuild_blas(
+        &mut self,
+        id: BlasId,
+        first_triangle_id: usize,
+        triangles: &[gpu::Triangle],
+    ) {
+        let primitives =
+            Self::triangle_primitives(first_triangle_id, triangles);
+        let config = self.config;
+        let blas = self.blas_mut(id);
+
+        utils::measure("tick.bvh.blas", || {
+            if config.spatial_splits {
+                blas.build_spatial(primitives, triangles, config);
+            } else {
+                blas.build(primitives, config);
+            }
         });
 
-        utils::measure("tick.bvh.serialize", || {
-            serializer::run(
-                materials,
-                &self.nodes,
-                &self.primitives,
-                &mut self.buffer,
+        self.any_blas_changed = true;
+    }
+
+    pub fn remove_blas(&mut self, id: BlasId) {
+        self.any_blas_changed |= self.blases.remove(&id).is_some();
+    }
+
+    /// Makes the next [`Self::refresh()`] rebuild the top-level BVH from
+    /// scratch, instead of refitting it.
+    pub fn rebuild(&mut self) {
+        self.force_rebuild = true;
+    }
+
+    /// Updates the top-level BVH so that it reflects given instances (and, if
+    /// needed, re-serializes the bottom-level BVHs).
+    pub fn refresh(&mut self, instances: &[BvhInstance]) {
+        let force_rebuild = mem::take(&mut self.force_rebuild);
+
+        if mem::take(&mut self.any_blas_changed) {
+            utils::measure("tick.bvh.serialize_blases", || {
+                self.buffer.truncate(1);
+
+                for blas in self.blases.values_mut() {
+                    // Descending from the top-level BVH into a bottom-level one
+                    // pushes up to two entries on the stack, see: `Ray`
+                    Self::serialize_tree(
+                        blas,
+                        &mut self.buffer,
+                        self.config,
+                        gpu::BVH_STACK_SIZE - 2,
+                        &mut |buffer, primitive, got_more_entries| {
+                            buffer.push(vec4(
+                                f32::from_bits(got_more_entries as u32),
+                                f32::from_bits(primitive.item_id),
+                                Default::default(),
+                                f32::from_bits(gpu::BvhView::OP_TRIANGLE),
+                            ));
+
+                            0
+                        },
+                    );
+                }
+
+                self.tlas_at = self.buffer.len();
+                self.dirty_from = Some(0);
+            });
+        }
+
+        self.buffer.truncate(self.tlas_at);
+
+        let root = if instances.is_empty() {
+            // Since there are no primitives left, there's nothing that could
+            // be reused during the next build
+            self.tlas = Default::default();
+
+            gpu::BvhView::SENTINEL
+        } else {
+            let primitives = utils::measure("tick.bvh.begin", || {
+                instances
+                    .iter()
+                    .enumerate()
+                    .map(|(instance_idx, instance)| {
+                        let bounds = self.blases[&instance.blas_id]
+                            .bounds()
+                            .transform(instance.transform);
+
+                        BvhPrimitive {
+                            item_id: instance_idx as u32,
+                            center: bounds.center(),
+                            bounds,
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            });
+
+            // If the number of instances hasn't changed, the tree can be just
+            // refitted - in the worst case (e.g. when an instance got removed
+            // and another one got added in its place), the refitted tree will
+            // be of poor quality and we'll fall back to rebuilding it
+            let refitted = !force_rebuild
+                && self.tlas.primitives() == primitives.len()
+                && utils::measure("tick.bvh.refit", || {
+                    self.tlas.refit(&primitives)
+                });
+
+            if !refitted {
+                utils::measure("tick.bvh.build", || {
+                    self.tlas.build(primitives, self.config);
+                });
+            }
+
+            utils::measure("tick.bvh.serialize", || {
+                Self::serialize_tree(
+                    &mut self.tlas,
+                    &mut self.buffer,
+                    self.config,
+                    gpu::BVH_STACK_SIZE,
+                    &mut |buffer, primitive, got_more_entries| {
+                        let instance = &instances[primitive.item_id as usize];
+
+                        let blas = &self.blases[&instance.blas_id];
+
+                        let flags = (got_more_entries as u32)
+                            | ((instance.has_alpha_blending as u32) << 1)
+                            | (instance.ray_visibility.serialize() << 2);
+
+                        let [d1, d2, d3] = instance.transform_inverse.encode();
+
+                        buffer.push(vec4(
+                            f32::from_bits(flags),
+                            f32::from_bits(blas.ptr),
+                            f32::from_bits(instance.material_id.get()),
+                            f32::from_bits(gpu::BvhView::OP_INSTANCE),
+                        ));
+
+                        buffer.push(d1);
+                        buffer.push(d2);
+                        buffer.push(d3);
+
+                        buffer.push(vec4(
+                            f32::from_bits(primitive.item_id),
+                            f32::from_bits(instance.visibility_mask),
+                            Default::default(),
+                            Default::default(),
+                        ));
+
+                        // Entering an instance pushes the sentinel and, if
+                        // there are more instances in this leaf, pointer to
+                        // the next one
+                        (got_more_entries as usize) + 1 + blas.stack_depth
+                    },
+                );
+            });
+
+            self.tlas.ptr
+        };
+
+        self.buffer[0] = Self::header(root);
+
+        self.dirty_from =
+            Some(self.dirty_from.map_or(self.tlas_at, |dirty_from| {
+                dirty_from.min(self.tlas_at)
+            }));
+    }
+
+    /// Serializes given tree at the end of `buffer`, making sure its traversal
+    /// fits within `max_stack_depth` entries of the GPU's stack.
+    ///
+    /// Wide nodes push up to three entries on the stack, so trees that don't
+    /// fit get serialized again using binary nodes (which push just one); if
+    /// even that doesn't help, the tree gets serialized anyway, but some of its
+    /// nodes might get skipped during traversal.
+    fn serialize_tree(
+        tree: &mut BvhTree,
+        buffer: &mut Vec<Vec4>,
+        config: BvhConfig,
+        max_stack_depth: usize,
+        serialize_leaf: &mut impl FnMut(
+            &mut Vec<Vec4>,
+            &BvhPrimitive,
+            bool,
+        ) -> usize,
+    ) {
+        let ptr = buffer.len();
+
+        tree.serialize(buffer, config, serialize_leaf);
+
+        if tree.stack_depth > max_stack_depth && config.wide {
+            buffer.truncate(ptr);
+
+            tree.serialize(
+                buffer,
+                BvhConfig {
+                    wide: false,
+                    ..config
+                },
+                serialize_leaf,
             );
-        });
+        }
 
-        self.primitives.end_refresh();
+        if tree.stack_depth > max_stack_depth {
+            warn!(
+                "BVH is too deep to be traversed on the GPU (needs {} stack \
+                 entries, but only {} are available) - some of the geometry \
+                 might not be rendered",
+                tree.stack_depth, max_stack_depth,
+            );
+        }
+    }
+
+    /// Returns whether the buffer has been modified since the last
+    /// [`Self::flush()`].
+    pub fn is_dirty(&self) -> bool {
+        self.dirty_from.is_some()
     }
 
     pub fn flush(
@@ -75,14 +395,98 @@ impl Bvh {
         device: &wgpu::Device,
         queue: &wgpu::Queue,
     ) -> BufferFlushOutcome {
-        self.buffer.flush(device, queue)
+        let Some(dirty_from) = self.dirty_from.take() else {
+            return BufferFlushOutcome::default();
+        };
+
+        let reallocated = self.buffer.reallocate(device, queue);
+
+        if reallocated {
+            // Reallocating already flushes the entire buffer, so there's no
+            // need to flush it again
+        } else {
+            let stride = mem::size_of::<Vec4>();
+
+            // Header (which points at the top-level BVH) is always modified
+            // together with the top-level BVH, so let's make sure to send it
+            // as well
+            if dirty_from > 0 {
+                self.buffer.flush_part(queue, 0, stride);
+            }
+
+            self.buffer.flush_part(
+                queue,
+                dirty_from * stride,
+                (self.buffer.len() - dirty_from) * stride,
+            );
+        }
+
+        BufferFlushOutcome { reallocated }
+    }
+
+    pub fn stats(&self) -> BvhStats {
+        BvhStats {
+            tlas: self.tlas.stats(),
+            blases: self
+                .blases
+                .values()
+                .map(BvhTree::stats)
+                .fold(Default::default(), BvhTreeStats::merge),
+        }
+    }
+
+    /// Writes bounding boxes of all nodes as an OBJ wireframe; the top-level
+    /// BVH is exported in the world-space, while bottom-level BVHs are exported
+    /// in their meshes' object-spaces.
+    pub fn export_obj(&self, writer: impl io::Write) -> io::Result<()> {
+        let tlas = ("tlas".to_string(), &self.tlas);
+
+        let blases = self
+            .blases
+            .iter()
+            .map(|(id, blas)| (format!("blas_{}", id.get()), blas));
+
+        exporter::run(Some(tlas).into_iter().chain(blases), writer)
     }
 
     pub fn len(&self) -> usize {
-        self.nodes.nodes.len()
+        self.tlas.len() + self.blases.values().map(BvhTree::len).sum::<usize>()
+    }
+
+    pub fn as_slice(&self) -> &[Vec4] {
+        &self.buffer
     }
 
     pub fn bind_readable(&self) -> impl Bindable + '_ {
         self.buffer.bind_readable()
     }
+
+    fn blas_mut(&mut self, id: BlasId) -> &mut BvhTree {
+        self.blases
+            .get_mut(&id)
+            .unwrap_or_else(|| panic!("BLAS not known: {id:?}"))
+    }
+
+    fn triangle_primitives(
+        first_triangle_id: usize,
+        triangles: &[gpu::Triangle],
+    ) -> Vec<BvhPrimitive> {
+        triangles
+            .iter()
+            .enumerate()
+            .map(|(triangle_idx, triangle)| {
+                let positions = triangle.positions();
+
+                BvhPrimitive {
+                    item_id: (first_triangle_id + triangle_idx) as u32,
+                    center: positions.iter().sum::<Vec3>() / 3.0,
+                    bounds: positions.into_iter().collect(),
+                }
+            })
+            .collect()
+    }
+
+    fn header(root: u32) -> Vec4 {
+        vec4(f32::from_bits(root), 0.0, 0.0, 0.0)
+    }
 }
@@ -1,46 +1,249 @@
 use core::f32;
 use std::collections::VecDeque;
 use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
 
 use fxhash::FxHasher;
 use glam::UVec3;
 
 use super::{
-    BvhNode, BvhNodeHash, BvhNodeId, BvhNodes, BvhPrimitiveId, BvhPrimitives,
-    BvhPrimitivesRef,
+    BvhNode, BvhNodeHash, BvhNodeId, BvhNodes, BvhPrimitive, BvhPrimitiveId,
+    BvhPrimitives, BvhPrimitivesRef,
 };
 use crate::{Axis, BoundingBox};
 
-const BINS: usize = 12;
+/// Once the tree's been split into subtrees containing at most 1/N-th of all
+/// primitives, those subtrees get built in parallel.
+///
+/// Larger values give threads more opportunities for load balancing, at the
+/// cost of building more of the tree's top on a single thread.
+const TASKS: usize = 64;
 
-pub fn run(nodes: &mut BvhNodes, primitives: &mut BvhPrimitives) {
-    thread::scope(|s| {
-        s.spawn(|| {
-            let root = nodes.set_root(BvhNode::Leaf {
-                bounds: Default::default(),
-                primitives_ref: primitives.current_ref(),
-            });
+/// Subtrees containing fewer primitives than this are never built on separate
+/// threads, because the overhead would outweigh the gains.
+const MIN_TASK_SIZE: usize = 1024;
 
-            let root = BvhNodeRef::root(root);
-            let mut stack = VecDeque::from_iter([root]);
+pub fn run(nodes: &mut BvhNodes, primitives: &mut BvhPrimitives, bins: usize) {
+    let primitives_ref = primitives.current_ref();
 
-            while let Some(node) = stack.pop_front() {
-                match balance(nodes, primitives, node) {
-                    (Some(left), Some(right)) => {
-                        stack.push_back(left);
-                        stack.push_back(right);
-                    }
-                    (Some(node), None) | (None, Some(node)) => {
-                        stack.push_back(node);
-                    }
-                    (None, None) => {
-                        //
-                    }
-                }
+    let bounds = primitives
+        .current(primitives_ref)
+        .iter()
+        .map(|primitive| primitive.bounds)
+        .collect();
+
+    let root = nodes.set_root(BvhNode::Leaf {
+        bounds,
+        primitives_ref,
+    });
+
+    let max_task_size = if primitives_ref.len() > MIN_TASK_SIZE {
+        Some((primitives_ref.len() / TASKS).max(MIN_TASK_SIZE))
+    } else {
+        None
+    };
+
+    let tasks = build(
+        nodes,
+        primitives,
+        BvhNodeRef::root(root),
+        bins,
+        max_task_size,
+    );
+
+    if !tasks.is_empty() {
+        build_parallel(nodes, primitives, tasks, bins);
+    }
+}
+
+/// Builds the tree starting at given node, returning nodes that have been
+/// deferred (see: [`build_parallel()`]).
+///
+/// Only nodes that don't reuse anything from the previous build (i.e. whose
+/// ghosts are empty) and contain at most `max_task_size` primitives are
+/// deferred; if `max_task_size` is `None`, the entire tree gets built here.
+fn build(
+    nodes: &mut BvhNodes,
+    primitives: &mut BvhPrimitives,
+    root: BvhNodeRef,
+    bins: usize,
+    max_task_size: Option<usize>,
+) -> Vec<BvhNodeId> {
+    let mut stack = VecDeque::from_iter([root]);
+    let mut tasks = Vec::new();
+
+    while let Some(node) = stack.pop_front() {
+        if let Some(max_task_size) = max_task_size {
+            if node.ghost.is_none()
+                && nodes[node.id].primitives_ref().len() <= max_task_size
+            {
+                tasks.push(node.id);
+                continue;
             }
-        });
+        }
+
+        match balance(nodes, primitives, node, bins) {
+            (Some(left), Some(right)) => {
+                stack.push_back(left);
+                stack.push_back(right);
+            }
+            (Some(node), None) | (None, Some(node)) => {
+                stack.push_back(node);
+            }
+            (None, None) => {
+                //
+            }
+        }
+    }
+
+    tasks
+}
+
+/// Builds subtrees starting at given nodes, spreading them across all of the
+/// available threads.
+///
+/// Each subtree gets built into its own arena, which is then grafted into
+/// `nodes` in the same order `tasks` are given in - so the output is the same
+/// no matter how many threads there are or how the work got scheduled.
+fn build_parallel(
+    nodes: &mut BvhNodes,
+    primitives: &mut BvhPrimitives,
+    tasks: Vec<BvhNodeId>,
+    bins: usize,
+) {
+    let threads = thread::available_parallelism()
+        .map_or(1, |threads| threads.get())
+        .min(tasks.len());
+
+    let next_task = AtomicUsize::new(0);
+
+    let subtrees: Vec<_> = thread::scope(|s| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut subtrees = Vec::new();
+
+                    loop {
+                        let task_idx =
+                            next_task.fetch_add(1, Ordering::Relaxed);
+
+                        let Some(&id) = tasks.get(task_idx) else {
+                            break;
+                        };
+
+                        let subtree_primitives =
+                            primitives.current(nodes[id].primitives_ref());
+
+                        subtrees.push((
+                            task_idx,
+                            build_subtree(subtree_primitives, bins),
+                        ));
+                    }
+
+                    subtrees
+                })
+            })
+            .collect();
+
+        let mut subtrees: Vec<_> = workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect();
+
+        subtrees.sort_by_key(|(task_idx, _)| *task_idx);
+        subtrees
+    });
+
+    for (id, (_, (subtree_nodes, subtree_primitives))) in
+        tasks.into_iter().zip(subtrees)
+    {
+        let primitives_ref = nodes[id].primitives_ref();
+
+        primitives
+            .current_mut(primitives_ref)
+            .copy_from_slice(&subtree_primitives);
+
+        graft(
+            nodes,
+            &subtree_nodes,
+            id,
+            primitives_ref.start().get() as i32,
+        );
+    }
+}
+
+/// Builds a standalone tree over given primitives, returning its nodes and
+/// primitives (reordered so that they match the nodes).
+fn build_subtree(
+    primitives: &[BvhPrimitive],
+    bins: usize,
+) -> (BvhNodes, Vec<BvhPrimitive>) {
+    let mut subtree_nodes = BvhNodes::default();
+    let mut subtree_primitives = BvhPrimitives::default();
+
+    subtree_primitives.replace(primitives.iter().copied());
+    subtree_primitives.begin_refresh();
+
+    let primitives_ref = subtree_primitives.current_ref();
+
+    subtree_nodes.set_root(BvhNode::Leaf {
+        bounds: primitives
+            .iter()
+            .map(|primitive| primitive.bounds)
+            .collect(),
+        primitives_ref,
     });
+
+    build(
+        &mut subtree_nodes,
+        &mut subtree_primitives,
+        BvhNodeRef::root(None),
+        bins,
+        None,
+    );
+
+    (subtree_nodes, subtree_primitives.current_all().to_vec())
+}
+
+/// Copies given subtree into `nodes`, in place of the node with given id.
+fn graft(
+    nodes: &mut BvhNodes,
+    subtree_nodes: &BvhNodes,
+    id: BvhNodeId,
+    primitives_offset: i32,
+) {
+    let mut stack = vec![(BvhNodeId::root(), id)];
+
+    while let Some((subtree_id, id)) = stack.pop() {
+        let mut node = subtree_nodes[subtree_id];
+
+        match &mut node {
+            BvhNode::Internal {
+                primitives_ref,
+                left_id,
+                right_id,
+                ..
+            } => {
+                primitives_ref.offset(primitives_offset);
+
+                let subtree_left_id = *left_id;
+                let subtree_right_id = *right_id;
+
+                *left_id = nodes.add(Default::default());
+                *right_id = nodes.add(Default::default());
+
+                stack.push((subtree_left_id, *left_id));
+                stack.push((subtree_right_id, *right_id));
+            }
+
+            BvhNode::Leaf { primitives_ref, .. } => {
+                primitives_ref.offset(primitives_offset);
+            }
+        }
+
+        nodes[id] = node;
+    }
 }
 
 #[inline(always)]
@@ -48,8 +251,15 @@ fn balance(
     nodes: &mut BvhNodes,
     primitives: &mut BvhPrimitives,
     node_ref: BvhNodeRef,
+    bins: usize,
 ) -> (Option<BvhNodeRef>, Option<BvhNodeRef>) {
-    if let Some(plane) = find_splitting_plane(nodes, primitives, node_ref.id) {
+    let BvhNode::Leaf { primitives_ref, .. } = nodes[node_ref.id] else {
+        unreachable!();
+    };
+
+    if let Some(plane) =
+        find_splitting_plane(primitives.current(primitives_ref), bins)
+    {
         if plane.split_cost < nodes[node_ref.id].sah_cost() {
             return split(nodes, primitives, node_ref, plane);
         }
@@ -66,22 +276,15 @@ fn balance(
     (None, None)
 }
 
-#[inline(always)]
-fn find_splitting_plane(
-    nodes: &BvhNodes,
-    primitives: &BvhPrimitives,
-    node_id: BvhNodeId,
+/// Finds the best object-split of given primitives, using binned SAH.
+pub fn find_splitting_plane(
+    primitives: &[BvhPrimitive],
+    bins: usize,
 ) -> Option<SplittingPlane> {
-    let BvhNode::Leaf { primitives_ref, .. } = nodes[node_id] else {
-        unreachable!();
-    };
-
-    if primitives_ref.len() <= 1 {
+    if primitives.len() <= 1 {
         return None;
     }
 
-    let primitives = primitives.current(primitives_ref);
-
     // ---
 
     let centroid_bb: BoundingBox = primitives
@@ -89,75 +292,42 @@ fn find_splitting_plane(
         .map(|primitive| primitive.center)
         .collect();
 
-    let mut bins = [[Bin::default(); BINS]; 3];
-    let scale = (BINS as f32) / centroid_bb.extent();
+    let mut axis_bins = [(); 3].map(|_| vec![Bin::default(); bins]);
+
+    let scale = (bins as f32) / centroid_bb.extent();
 
     for primitive in primitives {
         let bin_id = scale * (primitive.center - centroid_bb.min());
-        let bin_id = bin_id.as_uvec3().min(UVec3::splat((BINS as u32) - 1));
+        let bin_id = bin_id.as_uvec3().min(UVec3::splat((bins as u32) - 1));
         let bin_idx = bin_id.x as usize;
         let bin_idy = bin_id.y as usize;
         let bin_idz = bin_id.z as usize;
 
-        bins[0][bin_idx].count += 1;
-        bins[0][bin_idx].bounds += primitive.bounds;
+        axis_bins[0][bin_idx].count += 1;
+        axis_bins[0][bin_idx].bounds += primitive.bounds;
 
-        bins[1][bin_idy].count += 1;
-        bins[1][bin_idy].bounds += primitive.bounds;
+        axis_bins[1][bin_idy].count += 1;
+        axis_bins[1][bin_idy].bounds += primitive.bounds;
 
-        bins[2][bin_idz].count += 1;
-        bins[2][bin_idz].bounds += primitive.bounds;
+        axis_bins[2][bin_idz].count += 1;
+        axis_bins[2][bin_idz].bounds += primitive.bounds;
     }
 
     // ---
 
-    let mut left_areas = [[0.0; BINS - 1]; 3];
-    let mut right_areas = [[0.0; BINS - 1]; 3];
-    let mut left_counts = [[0; BINS - 1]; 3];
-    let mut right_counts = [[0; BINS - 1]; 3];
-    let mut left_bb = [BoundingBox::default(); 3];
-    let mut right_bb = [BoundingBox::default(); 3];
-    let mut left_count = [0; 3];
-    let mut right_count = [0; 3];
-
-    for axis in 0..3 {
-        for i in 0..(BINS - 1) {
-            let left_bin = bins[axis][i];
-
-            left_count[axis] += left_bin.count;
-            left_counts[axis][i] = left_count[axis];
-
-            if left_bin.bounds.is_set() {
-                left_bb[axis] += left_bin.bounds;
-            }
-
-            left_areas[axis][i] = left_bb[axis].half_area();
-
-            // ---
-
-            let right_bin = bins[axis][BINS - 1 - i];
-
-            right_count[axis] += right_bin.count;
-            right_counts[axis][BINS - 2 - i] = right_count[axis];
-
-            if right_bin.bounds.is_set() {
-                right_bb[axis] += right_bin.bounds;
-            }
-
-            right_areas[axis][BINS - 2 - i] = right_bb[axis].half_area();
-        }
-    }
+    let mut best: Option<SplittingPlane> = None;
+    let scale = centroid_bb.extent() / (bins as f32);
 
-    // ---
+    for (axis, bins) in axis_bins.iter().enumerate() {
+        let (left_counts, left_areas) = sweep(bins.iter());
+        let (mut right_counts, mut right_areas) = sweep(bins.iter().rev());
 
-    let mut best: Option<SplittingPlane> = None;
-    let scale = centroid_bb.extent() / (BINS as f32);
+        right_counts.reverse();
+        right_areas.reverse();
 
-    for axis in 0..3 {
-        for i in 0..(BINS - 1) {
-            let split_cost = (left_counts[axis][i] as f32)
-                * left_areas[axis][i]
-                + (right_counts[axis][i] as f32) * right_areas[axis][i];
+        for i in 0..(bins.len() - 1) {
+            let split_cost = (left_counts[i] as f32) * left_areas[i]
+                + (right_counts[i + 1] as f32) * right_areas[i + 1];
 
             let is_current_bin_better =
                 best.map_or(true, |best| split_cost <= best.split_cost);
@@ -180,6 +350,32 @@ fn find_splitting_plane(
     best
 }
 
+/// Returns the number of primitives and the area of their bounding box for
+/// each prefix of given bins.
+fn sweep<'a>(bins: impl Iterator<Item = &'a Bin>) -> (Vec<u32>, Vec<f32>) {
+    let mut count = 0;
+    let mut bounds = BoundingBox::default();
+    let mut counts = Vec::new();
+    let mut areas = Vec::new();
+
+    for bin in bins {
+        count += bin.count;
+
+        if bin.bounds.is_set() {
+            bounds += bin.bounds;
+        }
+
+        counts.push(count);
+        areas.push(if bounds.is_set() {
+            bounds.half_area()
+        } else {
+            0.0
+        });
+    }
+
+    (counts, areas)
+}
+
 fn split(
     nodes: &mut BvhNodes,
     primitives: &mut BvhPrimitives,
@@ -354,10 +550,10 @@ fn offset_primitives(nodes: &mut BvhNodes, offset: i32, id: BvhNodeId) {
 }
 
 #[derive(Clone, Copy, Debug)]
-struct SplittingPlane {
-    split_by: Axis,
-    split_at: f32,
-    split_cost: f32,
+pub struct SplittingPlane {
+    pub split_by: Axis,
+    pub split_at: f32,
+    pub split_cost: f32,
 }
 
 #[derive(Clone, Copy, Default, Debug)]
@@ -380,3 +576,87 @@ impl BvhNodeRef {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec3, Vec3, Vec4};
+
+    use super::*;
+    use crate::bvh::serializer;
+    use crate::BvhConfig;
+
+    #[test]
+    fn parallel() {
+        // Pseudo-random primitives, enough of them to spawn a few tasks
+        let all_primitives = (0..(8 * MIN_TASK_SIZE as u32)).map(|item_id| {
+            let hash = item_id.wrapping_mul(2654435761);
+
+            let center = vec3(
+                (hash % 1000) as f32,
+                ((hash >> 10) % 1000) as f32,
+                ((hash >> 20) % 1000) as f32,
+            );
+
+            BvhPrimitive {
+                item_id,
+                center,
+                bounds: BoundingBox::new(
+                    center - Vec3::ONE,
+                    center + Vec3::ONE,
+                ),
+            }
+        });
+
+        let build = |parallel: bool| {
+            let mut nodes = BvhNodes::default();
+            let mut primitives = BvhPrimitives::default();
+
+            primitives.replace(all_primitives.clone());
+            primitives.begin_refresh();
+
+            if parallel {
+                run(&mut nodes, &mut primitives, 12);
+            } else {
+                let primitives_ref = primitives.current_ref();
+
+                nodes.set_root(BvhNode::Leaf {
+                    bounds: primitives
+                        .current_all()
+                        .iter()
+                        .map(|primitive| primitive.bounds)
+                        .collect(),
+                    primitives_ref,
+                });
+
+                build(
+                    &mut nodes,
+                    &mut primitives,
+                    BvhNodeRef::root(None),
+                    12,
+                    None,
+                );
+            }
+
+            let mut buffer = Vec::new();
+
+            serializer::run(
+                &nodes,
+                &primitives,
+                &mut buffer,
+                BvhConfig::default(),
+                &mut |buffer, primitive, _| {
+                    buffer.push(Vec4::splat(f32::from_bits(primitive.item_id)));
+
+                    0
+                },
+            );
+
+            buffer
+        };
+
+        let expected = build(false);
+
+        assert_eq!(expected, build(true));
+        assert_eq!(expected, build(true));
+    }
+}
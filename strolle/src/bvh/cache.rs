@@ -0,0 +1,256 @@
+use std::path::PathBuf;
+use std::{fs, io, process};
+
+use super::{BvhConfig, BvhTree};
+use crate::gpu;
+
+/// On-disk cache of bottom-level BVHs, so that meshes of static levels don't
+/// have to be rebuilt at every startup; see:
+/// [`crate::Engine::set_bvh_cache_dir()`].
+///
+/// Trees are keyed by a digest of their meshes' triangles and the builder's
+/// configuration, so there's no need to invalidate the cache manually - when
+/// a mesh changes, its new tree simply gets stored under a different key.
+///
+/// The key is stored in the file as well and verified when loading, so that
+/// a mismatched (e.g. renamed) file doesn't get used for a wrong mesh.
+///
+/// Note that only bottom-level BVHs are cached - the top-level BVH is built
+/// over instances, so building it doesn't depend on the number of triangles.
+#[derive(Clone, Debug)]
+pub struct BvhCache {
+    dir: PathBuf,
+}
+
+impl BvhCache {
+    /// Identifies cache files; bump the last byte when the encoding of trees
+    /// changes in an incompatible way.
+    const MAGIC: u32 = u32::from_le_bytes(*b"SBV\x02");
+
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Returns the key under which the tree for given triangles is stored.
+    pub fn key(triangles: &[gpu::Triangle], config: BvhConfig) -> BvhCacheKey {
+        let mut hasher = blake3::Hasher::new();
+
+        hasher.update(&Self::MAGIC.to_le_bytes());
+        hasher.update(&(config.bins as u64).to_le_bytes());
+        hasher.update(&[config.spatial_splits as u8]);
+        hasher.update(&(triangles.len() as u64).to_le_bytes());
+
+        for triangle in triangles {
+            for position in triangle.positions() {
+                for coord in position.to_array() {
+                    hasher.update(&coord.to_le_bytes());
+                }
+            }
+        }
+
+        BvhCacheKey {
+            digest: *hasher.finalize().as_bytes(),
+            triangles: triangles.len() as u32,
+        }
+    }
+
+    /// Loads tree stored under given key, if it exists (and is valid); see:
+    /// [`BvhTree::decode()`].
+    pub fn load(
+        &self,
+        key: &BvhCacheKey,
+        first_item_id: u32,
+    ) -> Option<BvhTree> {
+        let bytes = fs::read(self.path(key)).ok()?;
+
+        let words: Vec<_> = bytes
+            .chunks_exact(4)
+            .map(|word| {
+                u32::from_le_bytes([word[0], word[1], word[2], word[3]])
+            })
+            .collect();
+
+        let header = Self::header(key);
+
+        if !words.starts_with(&header) {
+            return None;
+        }
+
+        BvhTree::decode(&words[header.len()..], first_item_id, key.triangles)
+    }
+
+    /// Stores given tree under given key.
+    pub fn save(
+        &self,
+        key: &BvhCacheKey,
+        tree: &BvhTree,
+        first_item_id: u32,
+    ) -> io::Result<()> {
+        let mut words = Self::header(key);
+
+        words.extend(tree.encode(first_item_id));
+
+        fs::create_dir_all(&self.dir)?;
+
+        // Writing into a temporary file first, so that a crash in the middle
+        // of writing doesn't leave a truncated file behind; the name is unique
+        // so that processes sharing the cache don't write into the same file
+        let path = self.path(key);
+
+        let tmp_path = path.with_extension(format!(
+            "{}.{:08x}.tmp",
+            process::id(),
+            rand::random::<u32>(),
+        ));
+
+        let bytes: Vec<_> =
+            words.into_iter().flat_map(u32::to_le_bytes).collect();
+
+        fs::write(&tmp_path, bytes)?;
+        fs::rename(tmp_path, path)
+    }
+
+    /// Returns words stored at the beginning of the file, before the tree.
+    fn header(key: &BvhCacheKey) -> Vec<u32> {
+        let mut words = vec![Self::MAGIC, key.triangles];
+
+        words.extend(key.digest.chunks_exact(4).map(|word| {
+            u32::from_le_bytes([word[0], word[1], word[2], word[3]])
+        }));
+
+        words
+    }
+
+    fn path(&self, key: &BvhCacheKey) -> PathBuf {
+        self.dir.join(format!("{}.bvh", key.name()))
+    }
+}
+
+/// Identifies a tree stored in [`BvhCache`]; see: [`BvhCache::key()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BvhCacheKey {
+    /// BLAKE3 digest of the triangles and the builder's configuration.
+    digest: [u8; 32],
+
+    /// Number of triangles the tree has been built for.
+    triangles: u32,
+}
+
+impl BvhCacheKey {
+    /// Returns the name of the file in which the tree is stored.
+    fn name(&self) -> String {
+        blake3::Hash::from(self.digest).to_hex().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use glam::vec3;
+
+    use super::*;
+    use crate::{BoundingBox, BvhPrimitive, Triangle};
+
+    #[test]
+    fn roundtrip() {
+        let dir = std::env::temp_dir()
+            .join(format!("strolle-bvh-cache-{}", process::id()));
+
+        let target = BvhCache::new(dir.clone());
+
+        let triangles: Vec<_> = (0..64)
+            .map(|idx| {
+                let offset = vec3(idx as f32, (idx % 8) as f32, 0.0);
+
+                Triangle {
+                    positions: [
+                        offset,
+                        offset + vec3(1.0, 0.0, 0.0),
+                        offset + vec3(0.0, 1.0, 0.0),
+                    ],
+                    normals: Default::default(),
+                    uvs: Default::default(),
+                    tangents: Default::default(),
+                }
+                .serialize()
+            })
+            .collect();
+
+        let mut tree = BvhTree::default();
+
+        tree.build(
+            triangles.iter().enumerate().map(|(idx, triangle)| {
+                let bounds: BoundingBox =
+                    triangle.positions().into_iter().collect();
+
+                BvhPrimitive {
+                    item_id: 10 + idx as u32,
+                    center: bounds.center(),
+                    bounds,
+                }
+            }),
+            BvhConfig::default(),
+        );
+
+        let key = BvhCache::key(&triangles, BvhConfig::default());
+        let other_key = BvhCache::key(&triangles[1..], BvhConfig::default());
+
+        assert_ne!(key, other_key);
+
+        assert_ne!(
+            key,
+            BvhCache::key(
+                &triangles,
+                BvhConfig {
+                    bins: 16,
+                    ..Default::default()
+                }
+            ),
+        );
+
+        assert!(target.load(&key, 100).is_none());
+
+        target.save(&key, &tree, 10).unwrap();
+
+        // Loaded tree should be the same as the original one, just pointing
+        // at a different range of triangles
+        let loaded = target.load(&key, 100).unwrap();
+
+        assert_eq!(tree.encode(10), loaded.encode(100));
+        assert_eq!(tree.bounds(), loaded.bounds());
+
+        // No temporary files should be left behind
+        assert_eq!(1, fs::read_dir(&dir).unwrap().count());
+
+        // Tree stored for a different number of triangles is rejected
+        let path = dir.join(format!("{}.bvh", key.name()));
+
+        assert!(target
+            .load(
+                &BvhCacheKey {
+                    triangles: 32,
+                    ..key
+                },
+                100
+            )
+            .is_none());
+
+        // ... and so is a tree stored for different triangles (e.g. when the
+        // file got renamed)
+        let other_path = dir.join(format!("{}.bvh", other_key.name()));
+
+        fs::copy(&path, other_path).unwrap();
+
+        assert!(target.load(&other_key, 100).is_none());
+
+        // ... and so is a corrupted one
+        let bytes = fs::read(&path).unwrap();
+
+        fs::write(&path, &bytes[..(bytes.len() / 2)]).unwrap();
+
+        assert!(target.load(&key, 100).is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,71 @@
+use crate::StrolleError;
+
+/// Configuration of the BVH builder; see: [`crate::Engine::set_bvh_config()`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BvhConfig {
+    /// Number of bins used when looking for the best splitting planes.
+    ///
+    /// More bins yield better trees (and so faster ray-tracing), at the cost of
+    /// longer build times; must be at least 2.
+    pub bins: usize,
+
+    /// Whether meshes' BVHs should be built using spatial-splits (SBVH), i.e.
+    /// whether triangles can be split across nodes.
+    ///
+    /// This considerably improves the ray-tracing performance for meshes with
+    /// large or elongated triangles (such as walls in architectural scenes),
+    /// but makes building slower and uses more memory - so it's best suited
+    /// for static meshes.
+    pub spatial_splits: bool,
+
+    /// Whether the BVH should be uploaded as a 4-ary tree (instead of a binary
+    /// one).
+    ///
+    /// Wide trees are shallower, so traversing them needs fewer memory
+    /// fetches, which speeds up ray-tracing - especially on GPUs without
+    /// hardware ray-tracing support.
+    ///
+    /// Since wide nodes need more stack space, trees that would overflow the
+    /// GPU's stack get uploaded as binary ones anyway.
+    pub wide: bool,
+
+    /// Whether bounds stored in wide nodes should be quantized (relative to
+    /// their parent's bounds) instead of being stored as `f32`s.
+    ///
+    /// Quantized nodes take about half as much memory, at the cost of slightly
+    /// looser bounds; has no effect unless [`Self::wide`] is enabled.
+    pub quantization: Option<BvhQuantization>,
+}
+
+impl BvhConfig {
+    /// Checks whether this configuration can be used to build BVHs; see:
+    /// [`crate::Engine::try_set_bvh_config()`].
+    pub(crate) fn validate(&self) -> Result<(), StrolleError> {
+        if self.bins < 2 {
+            return Err(StrolleError::InvalidBvhConfig(format!(
+                "BVH needs at least two bins, got {}",
+                self.bins
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for BvhConfig {
+    fn default() -> Self {
+        Self {
+            bins: 12,
+            spatial_splits: false,
+            wide: false,
+            quantization: None,
+        }
+    }
+}
+
+/// Precision of quantized bounds; see: [`BvhConfig::quantization`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BvhQuantization {
+    Bits8,
+    Bits16,
+}
@@ -0,0 +1,117 @@
+use std::io::{self, Write};
+
+use super::BvhTree;
+
+/// Writes bounding boxes of given trees' nodes as an OBJ wireframe, so that
+/// they can be inspected in external tools (e.g. Blender).
+///
+/// Each tree becomes a separate object and each of the tree's levels becomes a
+/// separate group (`depth_0`, `depth_1` etc.), so that particular levels can be
+/// easily shown or hidden.
+pub fn run<'a>(
+    trees: impl IntoIterator<Item = (String, &'a BvhTree)>,
+    mut writer: impl Write,
+) -> io::Result<()> {
+    // OBJ indices are global and one-based
+    let mut next_vertex = 1;
+
+    for (name, tree) in trees {
+        let mut nodes = Vec::new();
+
+        tree.visit(|node, depth| {
+            nodes.push((node.bounds(), depth));
+        });
+
+        nodes.sort_by_key(|(_, depth)| *depth);
+
+        writeln!(writer, "o {name}")?;
+
+        let mut curr_depth = None;
+
+        for (bounds, depth) in nodes {
+            if curr_depth != Some(depth) {
+                writeln!(writer, "g depth_{depth}")?;
+                curr_depth = Some(depth);
+            }
+
+            let [min, max] = [bounds.min(), bounds.max()];
+
+            for corner in 0..8 {
+                let x = if corner & 1 == 0 { min.x } else { max.x };
+                let y = if corner & 2 == 0 { min.y } else { max.y };
+                let z = if corner & 4 == 0 { min.z } else { max.z };
+
+                writeln!(writer, "v {x} {y} {z}")?;
+            }
+
+            // Corners differing by exactly one bit are connected by an edge
+            for corner in 0..8 {
+                for bit in [1, 2, 4] {
+                    if corner & bit == 0 {
+                        writeln!(
+                            writer,
+                            "l {} {}",
+                            next_vertex + corner,
+                            next_vertex + (corner | bit),
+                        )?;
+                    }
+                }
+            }
+
+            next_vertex += 8;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec3, Vec3};
+
+    use super::*;
+    use crate::{BoundingBox, BvhConfig, BvhPrimitive};
+
+    #[test]
+    fn export() {
+        let mut tree = BvhTree::default();
+
+        tree.build(
+            (0..4).map(|item_id| {
+                let center = vec3(item_id as f32, 0.0, 0.0);
+
+                BvhPrimitive {
+                    item_id,
+                    center,
+                    bounds: BoundingBox::new(
+                        center - Vec3::splat(0.25),
+                        center + Vec3::splat(0.25),
+                    ),
+                }
+            }),
+            BvhConfig {
+                bins: 4,
+                ..Default::default()
+            },
+        );
+
+        let mut obj = Vec::new();
+
+        run([("tree".to_string(), &tree)], &mut obj).unwrap();
+
+        let obj = String::from_utf8(obj).unwrap();
+        let stats = tree.stats();
+        let nodes = stats.internal_nodes + stats.leaf_nodes;
+
+        let count = |prefix| {
+            obj.lines().filter(|line| line.starts_with(prefix)).count()
+        };
+
+        assert_eq!(1, count("o "));
+        assert_eq!(stats.max_depth + 1, count("g "));
+        assert_eq!(8 * nodes, count("v "));
+        assert_eq!(12 * nodes, count("l "));
+        assert!(obj.lines().any(|line| line == "v -0.25 -0.25 -0.25"));
+        assert!(obj.lines().any(|line| line == "v 3.25 0.25 0.25"));
+    }
+}
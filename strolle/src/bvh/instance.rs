@@ -0,0 +1,19 @@
+use glam::Affine3A;
+
+use super::BlasId;
+use crate::{gpu, RayVisibility};
+
+/// Instance, as seen by the top-level BVH.
+#[derive(Clone, Copy, Debug)]
+pub struct BvhInstance {
+    pub blas_id: BlasId,
+    pub material_id: gpu::MaterialId,
+    pub has_alpha_blending: bool,
+    pub transform: Affine3A,
+    pub transform_inverse: Affine3A,
+
+    /// See: [`crate::Instance::with_visibility_mask()`].
+    pub visibility_mask: u32,
+
+    pub ray_visibility: RayVisibility,
+}
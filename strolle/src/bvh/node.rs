@@ -79,4 +79,8 @@ impl BvhNodeHash {
     pub fn new(hash: u64) -> Self {
         Self(hash)
     }
+
+    pub fn get(&self) -> u64 {
+        self.0
+    }
 }
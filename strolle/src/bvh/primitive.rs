@@ -3,27 +3,17 @@ use std::ops::Range;
 
 use glam::Vec3;
 
-use crate::gpu;
 use crate::utils::BoundingBox;
 
 #[derive(Clone, Copy, Debug)]
 pub struct BvhPrimitive {
-    pub triangle_id: gpu::TriangleId,
-    pub material_id: gpu::MaterialId,
+    /// Triangle id (for bottom-level BVHs) or index into the instances (for
+    /// the top-level BVH).
+    pub item_id: u32,
     pub center: Vec3,
     pub bounds: BoundingBox,
 }
 
-impl BvhPrimitive {
-    pub fn kill(&mut self) {
-        self.center = Vec3::MAX;
-    }
-
-    pub fn is_alive(&self) -> bool {
-        self.center.x != f32::MAX
-    }
-}
-
 impl Hash for BvhPrimitive {
     fn hash<H>(&self, state: &mut H)
     where
@@ -32,6 +22,13 @@ impl Hash for BvhPrimitive {
         self.center.x.to_bits().hash(state);
         self.center.y.to_bits().hash(state);
         self.center.z.to_bits().hash(state);
+
+        // Top-level primitives can change their bounds (e.g. when instance
+        // gets rotated) or their item (e.g. when another instance gets removed)
+        // without moving their center, so those have to be hashed as well
+        self.bounds.min().to_array().map(f32::to_bits).hash(state);
+        self.bounds.max().to_array().map(f32::to_bits).hash(state);
+        self.item_id.hash(state);
     }
 }
 
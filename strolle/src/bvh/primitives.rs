@@ -1,6 +1,3 @@
-use std::mem;
-use std::ops::Range;
-
 use super::{BvhPrimitive, BvhPrimitiveId, BvhPrimitivesRef};
 
 #[derive(Debug, Default)]
@@ -11,15 +8,9 @@ pub struct BvhPrimitives {
 }
 
 impl BvhPrimitives {
-    pub fn add(&mut self, prim: BvhPrimitive) {
-        self.all.push(prim);
-    }
-
-    pub fn update(
-        &mut self,
-        ids: Range<usize>,
-    ) -> impl Iterator<Item = &mut BvhPrimitive> {
-        self.all[ids].iter_mut()
+    pub fn replace(&mut self, prims: impl IntoIterator<Item = BvhPrimitive>) {
+        self.all.clear();
+        self.all.extend(prims);
     }
 
     pub fn current_ref(&self) -> BvhPrimitivesRef {
@@ -29,6 +20,10 @@ impl BvhPrimitives {
         )
     }
 
+    pub fn current_all(&self) -> &[BvhPrimitive] {
+        &self.current
+    }
+
     pub fn current(&self, range: BvhPrimitivesRef) -> &[BvhPrimitive] {
         let start = range.start().get() as usize;
         let end = range.end().get() as usize;
@@ -56,11 +51,20 @@ impl BvhPrimitives {
     }
 
     pub fn begin_refresh(&mut self) {
-        self.current =
-            self.all.iter().filter(|p| p.is_alive()).copied().collect();
+        self.current = self.all.clone();
     }
 
     pub fn end_refresh(&mut self) {
-        self.previous = mem::take(&mut self.current);
+        self.previous = self.current.clone();
+    }
+
+    /// Replaces current primitives with given ones, matching them by their
+    /// ids; `primitives` must be ordered by their ids.
+    pub fn refit(&mut self, primitives: &[BvhPrimitive]) {
+        let first_item_id = primitives.first().map_or(0, |prim| prim.item_id);
+
+        for prim in &mut self.current {
+            *prim = primitives[(prim.item_id - first_item_id) as usize];
+        }
     }
 }
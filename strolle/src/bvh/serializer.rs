@@ -1,110 +1,320 @@
-use glam::Vec4;
+use glam::{Vec3, Vec4};
 use spirv_std::glam::vec4;
 
-use super::{BvhNodeId, BvhNodes, BvhPrimitives};
-use crate::{AlphaMode, BvhNode, Materials, Params};
+use super::{
+    BvhConfig, BvhNodeId, BvhNodes, BvhPrimitive, BvhPrimitives,
+    BvhQuantization,
+};
+use crate::{gpu, BoundingBox, BvhNode};
 
-pub fn run<P>(
-    materials: &Materials<P>,
+/// Serializes given tree at the end of `buffer`, returning pointer to its root
+/// together with the tree's worst-case stack depth, i.e. the maximum number of
+/// entries the GPU traversal might have to push on its stack while walking
+/// this tree; see: [`gpu::BVH_STACK_SIZE`].
+///
+/// Leaves are serialized through `serialize_leaf`, which gets called for each
+/// primitive together with a flag saying whether there are any more primitives
+/// in this leaf - and which returns stack depth needed by that primitive (e.g.
+/// zero for triangles, but more for instances, whose bottom-level BVHs get
+/// traversed on top of the top-level one).
+///
+/// If [`BvhConfig::wide`] is enabled, the binary tree gets collapsed into a
+/// 4-ary one (with bounds optionally quantized); see `BvhView` in `strolle-gpu`
+/// for all of the layouts.
+pub fn run(
     nodes: &BvhNodes,
     primitives: &BvhPrimitives,
     buffer: &mut Vec<Vec4>,
-) where
-    P: Params,
-{
-    buffer.clear();
+    config: BvhConfig,
+    serialize_leaf: &mut impl FnMut(&mut Vec<Vec4>, &BvhPrimitive, bool) -> usize,
+) -> (u32, usize) {
+    let mut serializer = Serializer {
+        nodes,
+        primitives,
+        buffer,
+        config,
+        serialize_leaf,
+    };
 
-    serialize(materials, nodes, primitives, buffer, BvhNodeId::root());
+    serializer.serialize(BvhNodeId::root())
 }
 
-fn serialize<P>(
-    materials: &Materials<P>,
-    nodes: &BvhNodes,
-    primitives: &BvhPrimitives,
-    buffer: &mut Vec<Vec4>,
-    id: BvhNodeId,
-) -> u32
+struct Serializer<'a, F> {
+    nodes: &'a BvhNodes,
+    primitives: &'a BvhPrimitives,
+    buffer: &'a mut Vec<Vec4>,
+    config: BvhConfig,
+    serialize_leaf: &'a mut F,
+}
+
+impl<F> Serializer<'_, F>
 where
-    P: Params,
+    F: FnMut(&mut Vec<Vec4>, &BvhPrimitive, bool) -> usize,
 {
-    const OP_INTERNAL: u32 = 0;
-    const OP_LEAF: u32 = 1;
-
-    let ptr = buffer.len();
-
-    match nodes[id] {
-        BvhNode::Internal {
-            left_id, right_id, ..
-        } => {
-            buffer.push(Default::default());
-            buffer.push(Default::default());
-            buffer.push(Default::default());
-            buffer.push(Default::default());
-
-            let left_bb = nodes[left_id].bounds();
-            let right_bb = nodes[right_id].bounds();
-
-            let _left_ptr =
-                serialize(materials, nodes, primitives, buffer, left_id);
-
-            let right_ptr =
-                serialize(materials, nodes, primitives, buffer, right_id);
-
-            buffer[ptr] = vec4(
-                left_bb.min().x,
-                left_bb.min().y,
-                left_bb.min().z,
-                f32::from_bits(OP_INTERNAL),
-            );
-
-            buffer[ptr + 1] = vec4(
-                left_bb.max().x,
-                left_bb.max().y,
-                left_bb.max().z,
-                f32::from_bits(right_ptr),
-            );
-
-            buffer[ptr + 2] = vec4(
-                right_bb.min().x,
-                right_bb.min().y,
-                right_bb.min().z,
-                Default::default(),
-            );
-
-            buffer[ptr + 3] = vec4(
-                right_bb.max().x,
-                right_bb.max().y,
-                right_bb.max().z,
-                Default::default(),
-            );
-        }
+    /// Serializes given node, returning pointer to it and its stack depth.
+    fn serialize(&mut self, id: BvhNodeId) -> (u32, usize) {
+        let ptr = self.buffer.len();
+
+        let stack_depth = match self.nodes[id] {
+            BvhNode::Internal {
+                left_id, right_id, ..
+            } => {
+                if self.config.wide {
+                    self.serialize_wide(left_id, right_id)
+                } else {
+                    self.serialize_binary(left_id, right_id)
+                }
+            }
 
-        BvhNode::Leaf { primitives_ref, .. } => {
-            for (primitive_idx, primitive) in
-                primitives.current(primitives_ref).iter().enumerate()
-            {
-                let material = &materials[primitive.material_id];
+            BvhNode::Leaf { primitives_ref, .. } => {
+                let mut stack_depth = 0;
 
-                let flags = {
+                for (primitive_idx, primitive) in
+                    self.primitives.current(primitives_ref).iter().enumerate()
+                {
                     let got_more_entries =
                         primitive_idx + 1 < primitives_ref.len();
 
-                    let has_alpha_blending =
-                        matches!(material.alpha_mode, AlphaMode::Blend);
+                    stack_depth = stack_depth.max((self.serialize_leaf)(
+                        self.buffer,
+                        primitive,
+                        got_more_entries,
+                    ));
+                }
+
+                stack_depth
+            }
+        };
+
+        (ptr as u32, stack_depth)
+    }
+
+    fn serialize_binary(
+        &mut self,
+        left_id: BvhNodeId,
+        right_id: BvhNodeId,
+    ) -> usize {
+        let ptr = self.buffer.len();
+
+        self.buffer.push(Default::default());
+        self.buffer.push(Default::default());
+        self.buffer.push(Default::default());
+        self.buffer.push(Default::default());
+
+        let left_bb = self.nodes[left_id].bounds();
+        let right_bb = self.nodes[right_id].bounds();
+        let (_, left_depth) = self.serialize(left_id);
+        let (right_ptr, right_depth) = self.serialize(right_id);
+
+        self.buffer[ptr] = vec4(
+            left_bb.min().x,
+            left_bb.min().y,
+            left_bb.min().z,
+            f32::from_bits(gpu::BvhView::OP_INTERNAL),
+        );
+
+        self.buffer[ptr + 1] = vec4(
+            left_bb.max().x,
+            left_bb.max().y,
+            left_bb.max().z,
+            f32::from_bits(right_ptr),
+        );
+
+        self.buffer[ptr + 2] = vec4(
+            right_bb.min().x,
+            right_bb.min().y,
+            right_bb.min().z,
+            Default::default(),
+        );
+
+        self.buffer[ptr + 3] = vec4(
+            right_bb.max().x,
+            right_bb.max().y,
+            right_bb.max().z,
+            Default::default(),
+        );
+
+        // Traversing a binary node pushes the farther child on the stack and
+        // continues with the nearer one - and since we don't know which one's
+        // going to be nearer, we have to assume the worst
+        1 + left_depth.max(right_depth)
+    }
+
+    fn serialize_wide(
+        &mut self,
+        left_id: BvhNodeId,
+        right_id: BvhNodeId,
+    ) -> usize {
+        let ptr = self.buffer.len();
+        let children = self.collapse(left_id, right_id);
+
+        let op = match self.config.quantization {
+            None => gpu::BvhView::OP_INTERNAL_WIDE,
+            Some(BvhQuantization::Bits8) => gpu::BvhView::OP_INTERNAL_WIDE_Q8,
+            Some(BvhQuantization::Bits16) => gpu::BvhView::OP_INTERNAL_WIDE_Q16,
+        };
+
+        for _ in 0..gpu::BvhView::wide_node_size(op) {
+            self.buffer.push(Default::default());
+        }
+
+        let mut ptrs = [gpu::BvhView::SENTINEL; 4];
+        let mut bounds = [None; 4];
+        let mut children_depth = 0;
+
+        for (child_idx, &child_id) in children.iter().enumerate() {
+            let (child_ptr, child_depth) = self.serialize(child_id);
+
+            ptrs[child_idx] = child_ptr;
+            bounds[child_idx] = Some(self.nodes[child_id].bounds());
+            children_depth = children_depth.max(child_depth);
+        }
+
+        // The first child always directly follows its parent, so there's no
+        // need to store its pointer
+        self.buffer[ptr] = vec4(
+            f32::from_bits(ptrs[1]),
+            f32::from_bits(ptrs[2]),
+            f32::from_bits(ptrs[3]),
+            f32::from_bits(op),
+        );
 
-                    (got_more_entries as u32)
-                        | ((has_alpha_blending as u32) << 1)
+        match self.config.quantization {
+            None => {
+                self.encode(ptr, bounds);
+            }
+            Some(quantization) => {
+                self.encode_quantized(ptr, bounds, quantization);
+            }
+        }
+
+        // Same as for binary nodes - all children except for the nearest one
+        // get pushed on the stack
+        children.len() - 1 + children_depth
+    }
+
+    fn encode(&mut self, ptr: usize, bounds: [Option<BoundingBox>; 4]) {
+        // Missing children are encoded as boxes located at infinity, which no
+        // ray can hit
+        let mut rows = [Vec4::INFINITY; 6];
+
+        for (child_idx, bounds) in bounds.into_iter().enumerate() {
+            let Some(bounds) = bounds else {
+                continue;
+            };
+
+            for axis in 0..3 {
+                rows[axis][child_idx] = bounds.min()[axis];
+                rows[axis + 3][child_idx] = bounds.max()[axis];
+            }
+        }
+
+        self.buffer[(ptr + 1)..(ptr + 7)].copy_from_slice(&rows);
+    }
+
+    fn encode_quantized(
+        &mut self,
+        ptr: usize,
+        bounds: [Option<BoundingBox>; 4],
+        quantization: BvhQuantization,
+    ) {
+        let bits = match quantization {
+            BvhQuantization::Bits8 => 8,
+            BvhQuantization::Bits16 => 16,
+        };
+
+        let max_value = ((1u32 << bits) - 1) as f32;
+        let parent: BoundingBox = bounds.into_iter().flatten().collect();
+        let origin = parent.min();
+
+        // Scales are powers of two, so that decoding doesn't introduce any
+        // (well, almost any) floating-point errors; we're looking for the
+        // smallest scale that can represent the parent's extent
+        let exponents =
+            (parent.extent() / max_value).to_array().map(|extent| {
+                (extent.log2().ceil().clamp(-126.0, 127.0) as i32 + 127) as u32
+            });
+
+        let scale = Vec3::from_array(
+            exponents.map(|exponent| f32::from_bits(exponent << 23)),
+        );
+
+        let mut words = [0u32; 12];
+
+        for (child_idx, bounds) in bounds.into_iter().enumerate() {
+            // Missing children are skipped on the GPU through their pointers,
+            // so their bounds don't matter
+            let Some(bounds) = bounds else {
+                continue;
+            };
+
+            // Rounding outwards, so that the quantized box is never smaller
+            // than the original one
+            let min = ((bounds.min() - origin) / scale).floor();
+            let max = ((bounds.max() - origin) / scale).ceil();
+
+            let rows = [min.x, min.y, min.z, max.x, max.y, max.z];
+
+            for (row_idx, value) in rows.into_iter().enumerate() {
+                let value = value.clamp(0.0, max_value) as u32;
+
+                let word_idx = match quantization {
+                    BvhQuantization::Bits8 => row_idx,
+                    BvhQuantization::Bits16 => 2 * row_idx + child_idx / 2,
                 };
 
-                buffer.push(vec4(
-                    f32::from_bits(flags),
-                    f32::from_bits(primitive.triangle_id.get()),
-                    f32::from_bits(primitive.material_id.get()),
-                    f32::from_bits(OP_LEAF),
-                ));
+                words[word_idx] |= value << ((child_idx * bits) % 32);
             }
         }
+
+        self.buffer[ptr + 1] = origin.extend(f32::from_bits(
+            exponents[0] | (exponents[1] << 8) | (exponents[2] << 16),
+        ));
+
+        for (word_idx, word) in words[..(6 * bits / 8)].iter().enumerate() {
+            self.buffer[ptr + 2 + word_idx / 4][word_idx % 4] =
+                f32::from_bits(*word);
+        }
     }
 
-    ptr as u32
+    /// Collapses given children of a binary node into (at most) four children
+    /// by repeatedly pulling up the largest internal child's children - the
+    /// larger the node, the more rays visit it, so that's where skipping a
+    /// level helps the most.
+    fn collapse(
+        &self,
+        left_id: BvhNodeId,
+        right_id: BvhNodeId,
+    ) -> Vec<BvhNodeId> {
+        let mut children = vec![left_id, right_id];
+
+        while children.len() < 4 {
+            let largest_child = children
+                .iter()
+                .enumerate()
+                .filter_map(|(child_idx, &child_id)| {
+                    if let BvhNode::Internal {
+                        bounds,
+                        left_id,
+                        right_id,
+                        ..
+                    } = self.nodes[child_id]
+                    {
+                        Some((child_idx, bounds.half_area(), left_id, right_id))
+                    } else {
+                        None
+                    }
+                })
+                .max_by(|(_, a, _, _), (_, b, _, _)| a.total_cmp(b));
+
+            let Some((child_idx, _, left_id, right_id)) = largest_child else {
+                break;
+            };
+
+            children[child_idx] = left_id;
+            children.insert(child_idx + 1, right_id);
+        }
+
+        children
+    }
 }
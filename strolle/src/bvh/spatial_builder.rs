@@ -0,0 +1,344 @@
+//! Builder that, apart from object-splits, considers spatial-splits - i.e.
+//! splitting triangles' references across nodes; see:
+//!
+//! https://www.nvidia.in/docs/IO/77714/sbvh.pdf
+//!
+//! Contrary to the object-split builder, this one always builds the tree from
+//! scratch (because nodes don't map one-to-one to primitives anymore, there's
+//! nothing that could be reused between builds).
+
+use glam::Vec3;
+
+use super::{
+    builder, BvhNode, BvhNodeHash, BvhNodeId, BvhNodes, BvhPrimitive,
+    BvhPrimitiveId, BvhPrimitives, BvhPrimitivesRef,
+};
+use crate::{gpu, Axis, BoundingBox};
+
+/// Spatial-splits are considered only when children of the best object-split
+/// overlap by more than this fraction of the root's area; this limits the
+/// number of duplicated references (and so the memory usage).
+const OVERLAP_THRESHOLD: f32 = 1e-5;
+
+/// Nodes deeper than this are not split anymore, so that spatial-splits (which
+/// can keep splitting the same triangles over and over) eventually stop.
+const MAX_DEPTH: usize = 24;
+
+/// Builds the tree over given primitives, where each primitive is a triangle;
+/// `triangles` must be ordered by their ids (same as `primitives`' items).
+pub fn run(
+    nodes: &mut BvhNodes,
+    primitives: &mut BvhPrimitives,
+    triangles: &[gpu::Triangle],
+    bins: usize,
+) {
+    let refs = primitives.current_all().to_vec();
+    let first_item_id = refs.first().map_or(0, |prim| prim.item_id);
+    let bounds: BoundingBox = refs.iter().map(|prim| prim.bounds).collect();
+    let root_area = bounds.half_area();
+    let mut leaves = Vec::with_capacity(refs.len());
+
+    *nodes = Default::default();
+
+    nodes.set_root(BvhNode::Leaf {
+        bounds,
+        primitives_ref: Default::default(),
+    });
+
+    let mut stack = vec![(BvhNodeId::root(), refs, bounds, 0)];
+
+    while let Some((id, refs, bounds, depth)) = stack.pop() {
+        let split = if depth < MAX_DEPTH {
+            find_split(&refs, bounds, root_area, triangles, first_item_id, bins)
+        } else {
+            None
+        };
+
+        let Some((left, right)) = split else {
+            let start = BvhPrimitiveId::new(leaves.len() as u32);
+
+            leaves.extend(refs);
+
+            let end = BvhPrimitiveId::new(leaves.len() as u32);
+
+            nodes[id] = BvhNode::Leaf {
+                bounds,
+                primitives_ref: BvhPrimitivesRef::new(start, end),
+            };
+
+            continue;
+        };
+
+        let left_bounds = left.iter().map(|prim| prim.bounds).collect();
+        let right_bounds = right.iter().map(|prim| prim.bounds).collect();
+        let left_id = nodes.add(Default::default());
+        let right_id = nodes.add(Default::default());
+
+        // Hashes are used only for reusing nodes between builds, which - as
+        // mentioned above - we don't do here
+        nodes[id] = BvhNode::Internal {
+            bounds,
+            primitives_ref: Default::default(),
+            left_id,
+            left_hash: BvhNodeHash::new(0),
+            right_id,
+            right_hash: BvhNodeHash::new(0),
+        };
+
+        stack.push((left_id, left, left_bounds, depth + 1));
+        stack.push((right_id, right, right_bounds, depth + 1));
+    }
+
+    primitives.replace(leaves);
+    primitives.begin_refresh();
+}
+
+/// Returns the cheapest split of given references, if splitting is cheaper
+/// than creating a leaf.
+fn find_split(
+    refs: &[BvhPrimitive],
+    bounds: BoundingBox,
+    root_area: f32,
+    triangles: &[gpu::Triangle],
+    first_item_id: u32,
+    bins: usize,
+) -> Option<(Vec<BvhPrimitive>, Vec<BvhPrimitive>)> {
+    let leaf_cost = (refs.len() as f32) * bounds.half_area();
+    let object_split = builder::find_splitting_plane(refs, bins)?;
+
+    let (left, right): (Vec<_>, Vec<_>) =
+        refs.iter().copied().partition(|prim| {
+            prim.center[object_split.split_by] < object_split.split_at
+        });
+
+    let overlap = {
+        let left = left.iter().map(|prim| prim.bounds).collect::<BoundingBox>();
+
+        let right = right
+            .iter()
+            .map(|prim| prim.bounds)
+            .collect::<BoundingBox>();
+
+        left.intersection(&right)
+    };
+
+    let spatial_split = if overlap.is_valid()
+        && overlap.half_area() > OVERLAP_THRESHOLD * root_area
+    {
+        find_spatial_split(refs, bounds, triangles, first_item_id, bins)
+    } else {
+        None
+    };
+
+    match spatial_split {
+        Some(spatial_split)
+            if spatial_split.split_cost < object_split.split_cost =>
+        {
+            if spatial_split.split_cost >= leaf_cost {
+                return None;
+            }
+
+            let mut left = Vec::new();
+            let mut right = Vec::new();
+
+            for prim in refs {
+                let axis = spatial_split.split_by;
+                let split_at = spatial_split.split_at;
+
+                if prim.bounds.max()[axis] <= split_at {
+                    left.push(*prim);
+                } else if prim.bounds.min()[axis] >= split_at {
+                    right.push(*prim);
+                } else {
+                    let positions = positions(triangles, first_item_id, prim);
+
+                    let left_bounds =
+                        clip(positions, axis, f32::MIN, split_at, prim.bounds);
+
+                    let right_bounds =
+                        clip(positions, axis, split_at, f32::MAX, prim.bounds);
+
+                    if left_bounds.is_valid() {
+                        left.push(BvhPrimitive {
+                            center: left_bounds.center(),
+                            bounds: left_bounds,
+                            ..*prim
+                        });
+                    }
+
+                    if right_bounds.is_valid() {
+                        right.push(BvhPrimitive {
+                            center: right_bounds.center(),
+                            bounds: right_bounds,
+                            ..*prim
+                        });
+                    }
+                }
+            }
+
+            (!left.is_empty() && !right.is_empty()).then_some((left, right))
+        }
+
+        _ => {
+            if object_split.split_cost >= leaf_cost
+                || left.is_empty()
+                || right.is_empty()
+            {
+                return None;
+            }
+
+            Some((left, right))
+        }
+    }
+}
+
+/// Finds the best spatial-split of given references by distributing them into
+/// spatial bins (clipping triangles to bins they span).
+fn find_spatial_split(
+    refs: &[BvhPrimitive],
+    bounds: BoundingBox,
+    triangles: &[gpu::Triangle],
+    first_item_id: u32,
+    bins: usize,
+) -> Option<builder::SplittingPlane> {
+    let mut best: Option<builder::SplittingPlane> = None;
+
+    for axis in Axis::all() {
+        let min = bounds.min()[axis];
+        let extent = bounds.extent()[axis];
+
+        if extent <= 0.0 {
+            continue;
+        }
+
+        let bin_size = extent / (bins as f32);
+        let mut bin_bounds = vec![BoundingBox::default(); bins];
+        let mut entries = vec![0u32; bins];
+        let mut exits = vec![0u32; bins];
+
+        let bin_of =
+            |value: f32| (((value - min) / bin_size) as usize).min(bins - 1);
+
+        for prim in refs {
+            let first_bin = bin_of(prim.bounds.min()[axis]);
+            let last_bin = bin_of(prim.bounds.max()[axis]);
+            let positions = positions(triangles, first_item_id, prim);
+
+            for (bin_idx, bin_bounds) in bin_bounds
+                .iter_mut()
+                .enumerate()
+                .take(last_bin + 1)
+                .skip(first_bin)
+            {
+                let bin_min = min + bin_size * (bin_idx as f32);
+                let bin_max = bin_min + bin_size;
+                let bounds =
+                    clip(positions, axis, bin_min, bin_max, prim.bounds);
+
+                if bounds.is_valid() {
+                    *bin_bounds += bounds;
+                }
+            }
+
+            entries[first_bin] += 1;
+            exits[last_bin] += 1;
+        }
+
+        let mut left_bounds = BoundingBox::default();
+        let mut left_count = 0;
+        let mut left_costs = Vec::with_capacity(bins);
+
+        for bin_idx in 0..(bins - 1) {
+            left_count += entries[bin_idx];
+
+            if bin_bounds[bin_idx].is_set() {
+                left_bounds += bin_bounds[bin_idx];
+            }
+
+            left_costs.push(if left_bounds.is_set() {
+                (left_count as f32) * left_bounds.half_area()
+            } else {
+                0.0
+            });
+        }
+
+        let mut right_bounds = BoundingBox::default();
+        let mut right_count = 0;
+
+        for bin_idx in (1..bins).rev() {
+            right_count += exits[bin_idx];
+
+            if bin_bounds[bin_idx].is_set() {
+                right_bounds += bin_bounds[bin_idx];
+            }
+
+            let right_cost = if right_bounds.is_set() {
+                (right_count as f32) * right_bounds.half_area()
+            } else {
+                0.0
+            };
+
+            let split_cost = left_costs[bin_idx - 1] + right_cost;
+
+            if best.map_or(true, |best| split_cost < best.split_cost) {
+                best = Some(builder::SplittingPlane {
+                    split_by: axis,
+                    split_at: min + bin_size * (bin_idx as f32),
+                    split_cost,
+                });
+            }
+        }
+    }
+
+    best
+}
+
+fn positions(
+    triangles: &[gpu::Triangle],
+    first_item_id: u32,
+    prim: &BvhPrimitive,
+) -> [Vec3; 3] {
+    triangles[(prim.item_id - first_item_id) as usize].positions()
+}
+
+/// Returns bounding box of the part of triangle that lies within given slab
+/// (along given axis), limited to given bounds.
+fn clip(
+    positions: [Vec3; 3],
+    axis: Axis,
+    slab_min: f32,
+    slab_max: f32,
+    bounds: BoundingBox,
+) -> BoundingBox {
+    let mut clipped = BoundingBox::default();
+
+    for i in 0..3 {
+        let v0 = positions[i];
+        let v1 = positions[(i + 1) % 3];
+
+        if (slab_min..=slab_max).contains(&v0[axis]) {
+            clipped += v0;
+        }
+
+        for plane in [slab_min, slab_max] {
+            let crosses = (v0[axis] < plane && v1[axis] > plane)
+                || (v0[axis] > plane && v1[axis] < plane);
+
+            if crosses {
+                let t = (plane - v0[axis]) / (v1[axis] - v0[axis]);
+                let mut point = v0.lerp(v1, t);
+
+                // Make sure the point lies exactly on the plane, despite any
+                // floating-point inaccuracies
+                point[axis] = plane;
+                clipped += point;
+            }
+        }
+    }
+
+    if clipped.is_set() {
+        clipped.intersection(&bounds)
+    } else {
+        clipped
+    }
+}
@@ -0,0 +1,133 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Quality statistics of the acceleration structure; see:
+/// [`crate::Engine::bvh_stats()`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BvhStats {
+    /// Statistics of the top-level BVH, built over instances.
+    pub tlas: BvhTreeStats,
+
+    /// Statistics of all bottom-level BVHs (built over meshes' triangles)
+    /// combined.
+    pub blases: BvhTreeStats,
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "tlas: {} || blases: {}", self.tlas, self.blases)
+    }
+}
+
+/// Quality statistics of a single BVH (or a group of BVHs).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BvhTreeStats {
+    /// Number of trees these statistics have been gathered from.
+    pub trees: usize,
+
+    pub internal_nodes: usize,
+    pub leaf_nodes: usize,
+
+    /// Number of primitives stored in leaves (for trees built with
+    /// spatial-splits, that includes the duplicated references).
+    pub primitives: usize,
+
+    /// SAH cost, normalized by the root's area (summed over all trees).
+    ///
+    /// Roughly speaking, that's the expected number of nodes and primitives a
+    /// random ray passing through the tree has to test - the lower, the better.
+    pub sah_cost: f32,
+
+    pub max_depth: usize,
+
+    /// Average depth of leaf nodes.
+    pub avg_depth: f32,
+
+    /// Number of leaves of given size, i.e. `leaf_sizes[n]` says how many
+    /// leaves contain `n` primitives.
+    pub leaf_sizes: Vec<usize>,
+
+    /// Average overlap of internal nodes' children, expressed as the ratio of
+    /// the area of their intersection to the area of the node itself.
+    ///
+    /// Zero means that children never overlap, which is the ideal case - the
+    /// more they overlap, the more nodes rays have to visit.
+    pub avg_overlap: f32,
+
+    /// How long the last build (or refit) took (summed over all trees).
+    pub build_time: Duration,
+}
+
+impl BvhTreeStats {
+    /// Combines statistics of two groups of trees.
+    pub fn merge(self, other: Self) -> Self {
+        fn avg(a: f32, a_weight: usize, b: f32, b_weight: usize) -> f32 {
+            if a_weight + b_weight == 0 {
+                0.0
+            } else {
+                (a * (a_weight as f32) + b * (b_weight as f32))
+                    / ((a_weight + b_weight) as f32)
+            }
+        }
+
+        let mut leaf_sizes = self.leaf_sizes;
+
+        if leaf_sizes.len() < other.leaf_sizes.len() {
+            leaf_sizes.resize(other.leaf_sizes.len(), 0);
+        }
+
+        for (size, count) in other.leaf_sizes.into_iter().enumerate() {
+            leaf_sizes[size] += count;
+        }
+
+        Self {
+            trees: self.trees + other.trees,
+            internal_nodes: self.internal_nodes + other.internal_nodes,
+            leaf_nodes: self.leaf_nodes + other.leaf_nodes,
+            primitives: self.primitives + other.primitives,
+            sah_cost: self.sah_cost + other.sah_cost,
+            max_depth: self.max_depth.max(other.max_depth),
+            avg_depth: avg(
+                self.avg_depth,
+                self.leaf_nodes,
+                other.avg_depth,
+                other.leaf_nodes,
+            ),
+            leaf_sizes,
+            avg_overlap: avg(
+                self.avg_overlap,
+                self.internal_nodes,
+                other.avg_overlap,
+                other.internal_nodes,
+            ),
+            build_time: self.build_time + other.build_time,
+        }
+    }
+}
+
+impl fmt::Display for BvhTreeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "trees={} | nodes={}+{} | primitives={} | sah={:.2} | \
+             depth={:.1}/{} | overlap={:.3} | build_time={:?} | leaf_sizes=",
+            self.trees,
+            self.internal_nodes,
+            self.leaf_nodes,
+            self.primitives,
+            self.sah_cost,
+            self.avg_depth,
+            self.max_depth,
+            self.avg_overlap,
+            self.build_time,
+        )?;
+
+        for (size, count) in self.leaf_sizes.iter().enumerate() {
+            if *count > 0 {
+                write!(f, "[{size}: {count}]")?;
+            }
+        }
+
+        Ok(())
+    }
+}
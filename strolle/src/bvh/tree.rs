@@ -0,0 +1,689 @@
+use std::slice;
+use std::time::{Duration, Instant};
+
+use glam::{Vec3, Vec4};
+
+use super::{
+    builder, serializer, spatial_builder, BvhConfig, BvhNode, BvhNodeHash,
+    BvhNodeId, BvhNodes, BvhPrimitive, BvhPrimitiveId, BvhPrimitives,
+    BvhPrimitivesRef, BvhTreeStats,
+};
+use crate::{gpu, BoundingBox};
+
+/// How much the SAH cost of a refitted tree can grow (compared to the cost the
+/// tree had right after it was built) before [`BvhTree::refit()`] asks for a
+/// rebuild.
+const REFIT_THRESHOLD: f32 = 1.5;
+
+/// Single BVH - either a bottom-level one, built over triangles of a mesh, or
+/// the top-level one, built over instances.
+#[derive(Debug, Default)]
+pub struct BvhTree {
+    nodes: BvhNodes,
+    primitives: BvhPrimitives,
+
+    /// SAH cost of this tree right after the last build.
+    built_cost: f32,
+
+    /// Whether nodes of this tree can be reused during the next build - that's
+    /// not the case after the tree's been refitted (since hashes of its nodes
+    /// don't match their primitives anymore) or built with spatial-splits.
+    reusable: bool,
+
+    /// How long the last build (or refit) took.
+    build_time: Duration,
+
+    /// Pointer to this tree's root within the BVH buffer; set when the tree
+    /// gets serialized.
+    pub ptr: u32,
+
+    /// Worst-case stack depth of traversing this tree; set when the tree gets
+    /// serialized, see: [`serializer::run()`].
+    pub stack_depth: usize,
+}
+
+impl BvhTree {
+    /// Builds the tree from scratch, reusing nodes from the previous build
+    /// wherever possible.
+    pub fn build(
+        &mut self,
+        primitives: impl IntoIterator<Item = BvhPrimitive>,
+        config: BvhConfig,
+    ) {
+        let tt = Instant::now();
+
+        if !self.reusable {
+            self.nodes = Default::default();
+        }
+
+        self.primitives.replace(primitives);
+        self.primitives.begin_refresh();
+
+        builder::run(&mut self.nodes, &mut self.primitives, config.bins);
+
+        self.primitives.end_refresh();
+        self.built_cost = self.sah_cost();
+        self.reusable = true;
+        self.build_time = tt.elapsed();
+    }
+
+    /// Builds the tree from scratch, using spatial-splits; `triangles` must be
+    /// ordered by their ids (same as `primitives`' items).
+    pub fn build_spatial(
+        &mut self,
+        primitives: impl IntoIterator<Item = BvhPrimitive>,
+        triangles: &[gpu::Triangle],
+        config: BvhConfig,
+    ) {
+        let tt = Instant::now();
+
+        self.primitives.replace(primitives);
+        self.primitives.begin_refresh();
+
+        spatial_builder::run(
+            &mut self.nodes,
+            &mut self.primitives,
+            triangles,
+            config.bins,
+        );
+
+        self.primitives.end_refresh();
+        self.built_cost = self.sah_cost();
+        self.reusable = false;
+        self.build_time = tt.elapsed();
+    }
+
+    /// Updates bounds of primitives and nodes (bottom-up), without
+    /// re-partitioning the tree.
+    ///
+    /// `primitives` must contain the same items the tree's been built with,
+    /// ordered by their ids.
+    ///
+    /// Returns `false` if the tree's quality got degraded so much that it
+    /// should be rebuilt.
+    pub fn refit(&mut self, primitives: &[BvhPrimitive]) -> bool {
+        let tt = Instant::now();
+
+        self.primitives.refit(primitives);
+
+        refit(&mut self.nodes, &self.primitives, BvhNodeId::root());
+
+        self.reusable = false;
+        self.build_time = tt.elapsed();
+        self.sah_cost() <= REFIT_THRESHOLD * self.built_cost
+    }
+
+    /// Returns the number of primitives this tree's been built with (for trees
+    /// built with spatial-splits, that includes the duplicated references).
+    pub fn primitives(&self) -> usize {
+        self.primitives.current_all().len()
+    }
+
+    pub fn bounds(&self) -> BoundingBox {
+        self.nodes[BvhNodeId::root()].bounds()
+    }
+
+    /// Returns the number of nodes (including the unused ones).
+    pub fn len(&self) -> usize {
+        self.nodes.nodes.len()
+    }
+
+    pub fn serialize(
+        &mut self,
+        buffer: &mut Vec<Vec4>,
+        config: BvhConfig,
+        serialize_leaf: &mut impl FnMut(
+            &mut Vec<Vec4>,
+            &BvhPrimitive,
+            bool,
+        ) -> usize,
+    ) {
+        (self.ptr, self.stack_depth) = serializer::run(
+            &self.nodes,
+            &self.primitives,
+            buffer,
+            config,
+            serialize_leaf,
+        );
+    }
+
+    /// Encodes this tree into a flat list of words, so that it can be stored
+    /// in [`BvhCache`].
+    ///
+    /// Items are encoded relative to `first_item_id`, so that the tree can be
+    /// later decoded for a different range of items (e.g. when the same mesh
+    /// gets uploaded in a different order).
+    pub fn encode(&self, first_item_id: u32) -> Vec<u32> {
+        let nodes = &self.nodes.nodes;
+        let free_nodes = &self.nodes.free_nodes;
+        let primitives = self.primitives.current_all();
+
+        let mut words = vec![
+            self.built_cost.to_bits(),
+            nodes.len() as u32,
+            free_nodes.len() as u32,
+            primitives.len() as u32,
+        ];
+
+        let encode_bounds = |words: &mut Vec<u32>, bounds: BoundingBox| {
+            words.extend(bounds.min().to_array().map(f32::to_bits));
+            words.extend(bounds.max().to_array().map(f32::to_bits));
+        };
+
+        for node in nodes {
+            let primitives_ref = node.primitives_ref();
+
+            encode_bounds(&mut words, node.bounds());
+            words.push(primitives_ref.start().get());
+            words.push(primitives_ref.end().get());
+
+            match *node {
+                BvhNode::Internal {
+                    left_id,
+                    left_hash,
+                    right_id,
+                    right_hash,
+                    ..
+                } => {
+                    words.extend([
+                        1,
+                        left_id.get(),
+                        left_hash.get() as u32,
+                        (left_hash.get() >> 32) as u32,
+                        right_id.get(),
+                        right_hash.get() as u32,
+                        (right_hash.get() >> 32) as u32,
+                    ]);
+                }
+
+                BvhNode::Leaf { .. } => {
+                    words.extend([0; 7]);
+                }
+            }
+        }
+
+        words.extend(free_nodes.iter().map(BvhNodeId::get));
+
+        for primitive in primitives {
+            words.push(primitive.item_id - first_item_id);
+            words.extend(primitive.center.to_array().map(f32::to_bits));
+            encode_bounds(&mut words, primitive.bounds);
+        }
+
+        words
+    }
+
+    /// Decodes tree encoded with [`Self::encode()`], for items in range
+    /// `first_item_id..(first_item_id + items)`; returns `None` if the words are
+    /// malformed.
+    pub fn decode(
+        words: &[u32],
+        first_item_id: u32,
+        items: u32,
+    ) -> Option<Self> {
+        let mut words = WordReader(words.iter());
+
+        let built_cost = words.f32()?;
+        let nodes_len = words.u32()? as usize;
+        let free_nodes_len = words.u32()? as usize;
+        let primitives_len = words.u32()? as usize;
+        let mut nodes = BvhNodes::default();
+        let mut primitives = Vec::new();
+
+        for _ in 0..nodes_len {
+            let bounds = words.bounds()?;
+
+            let primitives_ref = BvhPrimitivesRef::new(
+                BvhPrimitiveId::new(words.u32()?),
+                BvhPrimitiveId::new(words.u32()?),
+            );
+
+            let is_internal = words.u32()? == 1;
+            let left_id = BvhNodeId::new(words.u32()?);
+            let left_hash = words.u64()?;
+            let right_id = BvhNodeId::new(words.u32()?);
+            let right_hash = words.u64()?;
+
+            nodes.nodes.push(if is_internal {
+                BvhNode::Internal {
+                    bounds,
+                    primitives_ref,
+                    left_id,
+                    left_hash: BvhNodeHash::new(left_hash),
+                    right_id,
+                    right_hash: BvhNodeHash::new(right_hash),
+                }
+            } else {
+                BvhNode::Leaf {
+                    bounds,
+                    primitives_ref,
+                }
+            });
+        }
+
+        for _ in 0..free_nodes_len {
+            nodes.free_nodes.push(BvhNodeId::new(words.u32()?));
+        }
+
+        for _ in 0..primitives_len {
+            let item_id = words.u32()?;
+
+            if item_id >= items {
+                return None;
+            }
+
+            let item_id = item_id + first_item_id;
+            let center = words.vec3()?;
+            let bounds = words.bounds()?;
+
+            primitives.push(BvhPrimitive {
+                item_id,
+                center,
+                bounds,
+            });
+        }
+
+        // Make sure that the tree is consistent, so that a corrupted file
+        // doesn't cause us to panic later
+        let is_consistent = nodes.nodes.iter().all(|node| {
+            let primitives_ref = node.primitives_ref();
+
+            let children_ok =
+                if let BvhNode::Internal {
+                    left_id, right_id, ..
+                } = node
+                {
+                    (left_id.get() as usize) < nodes_len
+                        && (right_id.get() as usize) < nodes_len
+                } else {
+                    true
+                };
+
+            children_ok
+                && primitives_ref.start().get() <= primitives_ref.end().get()
+                && (primitives_ref.end().get() as usize) <= primitives_len
+        });
+
+        if words.u32().is_some() || nodes_len == 0 || !is_consistent {
+            return None;
+        }
+
+        let mut tree = Self {
+            nodes,
+            built_cost,
+            ..Default::default()
+        };
+
+        // Since hashes of the nodes cover items, which we've just offset, the
+        // nodes can't be reused during the next build
+        tree.primitives.replace(primitives);
+        tree.primitives.begin_refresh();
+        tree.primitives.end_refresh();
+
+        Some(tree)
+    }
+
+    /// Returns quality statistics of this tree.
+    pub fn stats(&self) -> BvhTreeStats {
+        if self.nodes.nodes.is_empty() {
+            return Default::default();
+        }
+
+        let mut stats = BvhTreeStats {
+            trees: 1,
+            build_time: self.build_time,
+            ..Default::default()
+        };
+
+        let mut depth_sum = 0;
+        let mut overlap_sum = 0.0;
+
+        self.visit(|node, depth| match *node {
+            BvhNode::Internal {
+                bounds,
+                left_id,
+                right_id,
+                ..
+            } => {
+                let overlap = self.nodes[left_id]
+                    .bounds()
+                    .intersection(&self.nodes[right_id].bounds());
+
+                if overlap.is_valid() && bounds.half_area() > 0.0 {
+                    overlap_sum += overlap.half_area() / bounds.half_area();
+                }
+
+                stats.internal_nodes += 1;
+            }
+
+            BvhNode::Leaf { primitives_ref, .. } => {
+                let size = primitives_ref.len();
+
+                if stats.leaf_sizes.len() <= size {
+                    stats.leaf_sizes.resize(size + 1, 0);
+                }
+
+                stats.leaf_sizes[size] += 1;
+                stats.leaf_nodes += 1;
+                stats.primitives += size;
+                stats.max_depth = stats.max_depth.max(depth);
+
+                depth_sum += depth;
+            }
+        });
+
+        stats.sah_cost = self.sah_cost();
+        stats.avg_depth = (depth_sum as f32) / (stats.leaf_nodes as f32);
+
+        if stats.internal_nodes > 0 {
+            stats.avg_overlap = overlap_sum / (stats.internal_nodes as f32);
+        }
+
+        stats
+    }
+
+    /// Calls given function for each node reachable from the root (i.e.
+    /// skipping the unused ones), together with the node's depth.
+    pub fn visit(&self, mut f: impl FnMut(&BvhNode, usize)) {
+        if self.nodes.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = vec![(BvhNodeId::root(), 0)];
+
+        while let Some((id, depth)) = stack.pop() {
+            let node = &self.nodes[id];
+
+            if let BvhNode::Internal {
+                left_id, right_id, ..
+            } = node
+            {
+                stack.push((*right_id, depth + 1));
+                stack.push((*left_id, depth + 1));
+            }
+
+            f(node, depth);
+        }
+    }
+
+    /// Returns the SAH cost of this tree, normalized by its root's area.
+    fn sah_cost(&self) -> f32 {
+        let root_area = self.bounds().half_area();
+
+        if root_area > 0.0 {
+            sah_cost(&self.nodes, BvhNodeId::root()) / root_area
+        } else {
+            0.0
+        }
+    }
+}
+
+fn refit(
+    nodes: &mut BvhNodes,
+    primitives: &BvhPrimitives,
+    id: BvhNodeId,
+) -> BoundingBox {
+    let new_bounds = match nodes[id] {
+        BvhNode::Internal {
+            left_id, right_id, ..
+        } => {
+            refit(nodes, primitives, left_id)
+                + refit(nodes, primitives, right_id)
+        }
+
+        BvhNode::Leaf { primitives_ref, .. } => primitives
+            .current(primitives_ref)
+            .iter()
+            .map(|primitive| primitive.bounds)
+            .collect(),
+    };
+
+    match &mut nodes[id] {
+        BvhNode::Internal { bounds, .. } | BvhNode::Leaf { bounds, .. } => {
+            *bounds = new_bounds;
+        }
+    }
+
+    new_bounds
+}
+
+struct WordReader<'a>(slice::Iter<'a, u32>);
+
+impl WordReader<'_> {
+    fn u32(&mut self) -> Option<u32> {
+        self.0.next().copied()
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some((self.u32()? as u64) | ((self.u32()? as u64) << 32))
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        self.u32().map(f32::from_bits)
+    }
+
+    fn vec3(&mut self) -> Option<Vec3> {
+        Some(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn bounds(&mut self) -> Option<BoundingBox> {
+        Some(BoundingBox::new(self.vec3()?, self.vec3()?))
+    }
+}
+
+fn sah_cost(nodes: &BvhNodes, id: BvhNodeId) -> f32 {
+    let node = &nodes[id];
+
+    match *node {
+        BvhNode::Internal {
+            bounds,
+            left_id,
+            right_id,
+            ..
+        } => {
+            bounds.half_area()
+                + sah_cost(nodes, left_id)
+                + sah_cost(nodes, right_id)
+        }
+
+        BvhNode::Leaf { .. } => node.sah_cost(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec3, Vec3};
+
+    use super::*;
+    use crate::Triangle;
+
+    fn primitives(center: impl Fn(u32) -> Vec3) -> Vec<BvhPrimitive> {
+        (0..16)
+            .map(|item_id| {
+                let center = center(item_id);
+
+                BvhPrimitive {
+                    item_id,
+                    center,
+                    bounds: BoundingBox::new(
+                        center - Vec3::splat(0.25),
+                        center + Vec3::splat(0.25),
+                    ),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn refit() {
+        let mut target = BvhTree::default();
+
+        target.build(
+            primitives(|item_id| vec3(item_id as f32, 0.0, 0.0)),
+            BvhConfig::default(),
+        );
+
+        // Moving everything together doesn't affect the tree's quality
+        assert!(target
+            .refit(&primitives(|item_id| { vec3(item_id as f32, 10.0, 0.0) })));
+
+        assert_eq!(
+            BoundingBox::new(
+                vec3(-0.25, 9.75, -0.25),
+                vec3(15.25, 10.25, 0.25)
+            ),
+            target.bounds()
+        );
+
+        // ... but shuffling primitives around does
+        assert!(!target.refit(&primitives(|item_id| {
+            vec3(((item_id * 7) % 16) as f32, 10.0, 0.0)
+        })));
+    }
+
+    #[test]
+    fn stats() {
+        let mut target = BvhTree::default();
+
+        assert_eq!(BvhTreeStats::default(), target.stats());
+
+        target.build(
+            primitives(|item_id| vec3(item_id as f32, 0.0, 0.0)),
+            BvhConfig::default(),
+        );
+
+        let stats = target.stats();
+
+        assert_eq!(1, stats.trees);
+        assert_eq!(stats.leaf_nodes, stats.internal_nodes + 1);
+        assert_eq!(16, stats.primitives);
+        assert_eq!(0.0, stats.avg_overlap);
+        assert!(stats.max_depth > 0);
+        assert!(stats.avg_depth <= stats.max_depth as f32);
+
+        assert_eq!(stats.leaf_nodes, stats.leaf_sizes.iter().sum::<usize>(),);
+
+        assert_eq!(
+            stats.primitives,
+            stats
+                .leaf_sizes
+                .iter()
+                .enumerate()
+                .map(|(size, count)| size * count)
+                .sum::<usize>(),
+        );
+
+        let merged = stats.clone().merge(stats.clone());
+
+        assert_eq!(2, merged.trees);
+        assert_eq!(32, merged.primitives);
+        assert_eq!(stats.avg_depth, merged.avg_depth);
+        assert_eq!(2.0 * stats.sah_cost, merged.sah_cost);
+    }
+
+    #[test]
+    fn stack_depth() {
+        let mut target = BvhTree::default();
+
+        target.build(
+            primitives(|item_id| vec3(item_id as f32, 0.0, 0.0)),
+            BvhConfig::default(),
+        );
+
+        let max_depth = target.stats().max_depth;
+
+        let mut serialize = |wide, leaf_depth| {
+            target.serialize(
+                &mut Vec::new(),
+                BvhConfig {
+                    wide,
+                    ..Default::default()
+                },
+                &mut |buffer, _, _| {
+                    buffer.push(Vec4::ZERO);
+                    leaf_depth
+                },
+            );
+
+            target.stack_depth
+        };
+
+        // Binary nodes push one entry per level
+        assert_eq!(max_depth, serialize(false, 0));
+        assert_eq!(max_depth + 2, serialize(false, 2));
+
+        // Wide nodes push up to three entries per (collapsed) level
+        let wide_depth = serialize(true, 0);
+
+        assert!(wide_depth <= 3 * max_depth, "{wide_depth} vs {max_depth}");
+        assert_eq!(wide_depth + 2, serialize(true, 2));
+    }
+
+    #[test]
+    fn spatial_splits() {
+        // Long, diagonal triangles - a worst-case scenario for object-splits
+        let triangles: Vec<_> = (0..32)
+            .map(|idx| {
+                let offset = vec3(idx as f32, 0.0, 0.0);
+
+                Triangle {
+                    positions: [
+                        offset,
+                        offset + vec3(0.1, 0.0, 0.0),
+                        offset + vec3(16.0, 16.0, 0.0),
+                    ],
+                    normals: Default::default(),
+                    uvs: Default::default(),
+                    tangents: Default::default(),
+                }
+                .serialize()
+            })
+            .collect();
+
+        let primitives: Vec<_> = triangles
+            .iter()
+            .enumerate()
+            .map(|(item_id, triangle)| {
+                let bounds: BoundingBox =
+                    triangle.positions().into_iter().collect();
+
+                BvhPrimitive {
+                    item_id: item_id as u32,
+                    center: bounds.center(),
+                    bounds,
+                }
+            })
+            .collect();
+
+        let mut object_tree = BvhTree::default();
+        let mut spatial_tree = BvhTree::default();
+
+        object_tree.build(primitives.clone(), BvhConfig::default());
+
+        spatial_tree.build_spatial(
+            primitives.clone(),
+            &triangles,
+            BvhConfig::default(),
+        );
+
+        assert!(spatial_tree.built_cost < object_tree.built_cost);
+        assert_eq!(object_tree.bounds(), spatial_tree.bounds());
+
+        // Each triangle must be still reachable, though possibly through many
+        // (clipped) references
+        for prim in &primitives {
+            let refs: Vec<_> = spatial_tree
+                .primitives
+                .current_all()
+                .iter()
+                .filter(|r| r.item_id == prim.item_id)
+                .collect();
+
+            assert!(!refs.is_empty());
+
+            let bounds: BoundingBox = refs.iter().map(|r| r.bounds).collect();
+
+            assert_eq!(prim.bounds, bounds);
+        }
+    }
+}
@@ -6,12 +6,38 @@ use spirv_std::glam::{uvec2, Mat4, UVec2, Vec3};
 
 use crate::gpu;
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 pub struct Camera {
     pub mode: CameraMode,
     pub viewport: CameraViewport,
     pub transform: Mat4,
     pub projection: Mat4,
+
+    /// Which instances this camera sees - an instance is visible only if its
+    /// visibility mask has at least one bit in common with this mask; see:
+    /// [`crate::Instance::with_visibility_mask()`].
+    ///
+    /// Note that the mask applies to all rays traced for this camera, so
+    /// invisible instances don't cast shadows or show up in reflections
+    /// either.
+    pub visibility_mask: u32,
+
+    /// How direct lighting picks its candidates among the lights; see:
+    /// [`LightSampling`].
+    pub light_sampling: LightSampling,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            mode: Default::default(),
+            viewport: Default::default(),
+            transform: Default::default(),
+            projection: Default::default(),
+            visibility_mask: u32::MAX,
+            light_sampling: Default::default(),
+        }
+    }
 }
 
 impl Camera {
@@ -36,6 +62,16 @@ impl Camera {
             return true;
         }
 
+        if self.light_sampling != older.light_sampling {
+            info!(
+                "Camera `{}` invalidated: light sampling has been changed \
+                 ({:?} -> {:?})",
+                older, older.light_sampling, self.light_sampling,
+            );
+
+            return true;
+        }
+
         false
     }
 
@@ -63,8 +99,8 @@ impl Camera {
             data: vec4(
                 f32::from_bits(self.mode.serialize()),
                 t,
-                Default::default(),
-                Default::default(),
+                f32::from_bits(self.visibility_mask),
+                f32::from_bits(self.light_sampling.serialize()),
             ),
         }
     }
@@ -131,6 +167,31 @@ impl CameraMode {
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LightSampling {
+    /// Picks lights through the light tree, built over all of the lights;
+    /// works best for scenes with a moderate number of lights
+    #[default]
+    Tree,
+
+    /// Picks lights through a world-space grid centered around the camera,
+    /// where each cell keeps track of lights that are important for it; makes
+    /// scenes with many local lights (think dungeons) much cheaper to render
+    ///
+    /// Surfaces outside of the grid's range still pick their lights through
+    /// the tree.
+    Grid,
+}
+
+impl LightSampling {
+    pub(crate) fn serialize(&self) -> u32 {
+        match self {
+            LightSampling::Tree => 0,
+            LightSampling::Grid => 1,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct CameraViewport {
     pub format: wgpu::TextureFormat,
@@ -3,6 +3,7 @@ mod pass;
 mod passes;
 
 use std::ops::DerefMut;
+use std::sync::mpsc;
 
 use log::{debug, info};
 use rand::Rng;
@@ -10,7 +11,10 @@ use rand::Rng;
 pub use self::buffers::*;
 pub use self::pass::*;
 pub use self::passes::*;
-use crate::{gpu, Camera, CameraMode, Engine, Params};
+use crate::{
+    gpu, Camera, CameraImage, CameraMode, Engine, LightSampling, Params,
+    StrolleError,
+};
 
 #[derive(Debug)]
 pub struct CameraController {
@@ -119,6 +123,16 @@ impl CameraController {
                 if has_any_objects {
                     self.passes.frame_reprojection.run(self, encoder);
 
+                    // Light grid is used by both direct and indirect
+                    // lighting
+                    if self.camera.light_sampling == LightSampling::Grid
+                        && (self.camera.mode.needs_di()
+                            || self.camera.mode.needs_gi_diff()
+                            || self.camera.mode.needs_gi_spec())
+                    {
+                        self.passes.di_grid.run(self, encoder);
+                    }
+
                     if self.camera.mode.needs_di() {
                         self.passes.di_shading.run(self, encoder);
                         self.passes.di_temporal_resampling.run(self, encoder);
@@ -176,6 +190,128 @@ impl CameraController {
         }
     }
 
+    pub fn render_to_image<P>(
+        &self,
+        engine: &Engine<P>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<CameraImage, StrolleError>
+    where
+        P: Params,
+    {
+        let viewport = &self.camera.viewport;
+        let bytes_per_pixel = CameraImage::bytes_per_pixel(viewport.format)?;
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("strolle_camera_image"),
+            size: wgpu::Extent3d {
+                width: viewport.position.x + viewport.size.x,
+                height: viewport.position.y + viewport.size.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: viewport.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        // Rows copied into a buffer must be aligned, so we have to allocate a
+        // bit more memory and then skip the padding when reading the texels
+        let unpadded_bytes_per_row = viewport.size.x * bytes_per_pixel;
+
+        let padded_bytes_per_row = {
+            let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+            (unpadded_bytes_per_row + align - 1) / align * align
+        };
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("strolle_camera_image"),
+            size: (padded_bytes_per_row * viewport.size.y) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("strolle_camera_image"),
+            });
+
+        self.render(
+            engine,
+            &mut encoder,
+            &texture.create_view(&Default::default()),
+        );
+
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: viewport.position.x,
+                    y: viewport.position.y,
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(viewport.size.y),
+                },
+            },
+            wgpu::Extent3d {
+                width: viewport.size.x,
+                height: viewport.size.y,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        queue.submit(Some(encoder.finish()));
+
+        // ---
+
+        let slice = buffer.slice(..);
+        let (tx, rx) = mpsc::channel();
+
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            _ = tx.send(result);
+        });
+
+        device.poll(wgpu::Maintain::Wait);
+
+        rx.recv()
+            .map_err(|_| {
+                StrolleError::ImageReadbackFailed(
+                    "buffer mapping has been cancelled".into(),
+                )
+            })?
+            .map_err(|err| {
+                StrolleError::ImageReadbackFailed(format!(
+                    "couldn't map buffer: {err}"
+                ))
+            })?;
+
+        let data = {
+            let texels = slice.get_mapped_range();
+
+            texels
+                .chunks_exact(padded_bytes_per_row as usize)
+                .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+                .copied()
+                .collect()
+        };
+
+        buffer.unmap();
+
+        CameraImage::from_raw(viewport.format, viewport.size, data)
+    }
+
     pub fn invalidate<P>(&mut self, engine: &Engine<P>, device: &wgpu::Device)
     where
         P: Params,
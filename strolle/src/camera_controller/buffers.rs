@@ -1,7 +1,8 @@
 use log::debug;
 
 use crate::{
-    gpu, Camera, DoubleBuffered, MappedUniformBuffer, StorageBuffer, Texture,
+    gpu, Camera, DoubleBuffered, LightSampling, MappedUniformBuffer,
+    StorageBuffer, Texture,
 };
 
 #[derive(Debug)]
@@ -24,6 +25,7 @@ pub struct CameraBuffers {
     pub di_prev_reservoirs: StorageBuffer,
     pub di_curr_reservoirs: StorageBuffer,
     pub di_next_reservoirs: StorageBuffer,
+    pub di_light_grid: StorageBuffer,
 
     pub di_diff_samples: Texture,
     pub di_diff_prev_colors: Texture,
@@ -166,6 +168,18 @@ impl CameraBuffers {
             viewport_buffer_size(2 * 4 * 4),
         );
 
+        // Light grid takes a couple of megabytes, so it's allocated only when
+        // it's actually going to be used
+        let di_light_grid = StorageBuffer::new(
+            device,
+            "di_light_grid",
+            if camera.light_sampling == LightSampling::Grid {
+                (gpu::LightGrid::RESERVOIRS as usize) * 4 * 4
+            } else {
+                4 * 4
+            },
+        );
+
         // ---
 
         let di_diff_samples = Texture::builder("di_diff_samples")
@@ -298,7 +312,7 @@ impl CameraBuffers {
         let ref_rays = StorageBuffer::new(
             device,
             "ref_rays",
-            viewport_buffer_size(3 * 4 * 4),
+            viewport_buffer_size(4 * 4 * 4),
         );
 
         // TODO initialize lazily
@@ -336,6 +350,7 @@ impl CameraBuffers {
             di_prev_reservoirs,
             di_curr_reservoirs,
             di_next_reservoirs,
+            di_light_grid,
 
             di_diff_samples,
             di_diff_prev_colors,
@@ -35,6 +35,7 @@ macro_rules! passes {
 passes!([
     atmosphere => AtmospherePass,
     bvh_heatmap => BvhHeatmapPass,
+    di_grid => DiGridPass,
     di_resolving => DiResolvingPass,
     di_shading => DiShadingPass,
     di_spatial_resampling => DiSpatialResamplingPass,
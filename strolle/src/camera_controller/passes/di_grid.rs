@@ -0,0 +1,48 @@
+use glam::uvec2;
+
+use crate::{
+    gpu, Camera, CameraBuffers, CameraComputePass, CameraController, Engine,
+    Params,
+};
+
+#[derive(Debug)]
+pub struct DiGridPass {
+    pass: CameraComputePass,
+}
+
+impl DiGridPass {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<P>(
+        engine: &Engine<P>,
+        device: &wgpu::Device,
+        _: &Camera,
+        buffers: &CameraBuffers,
+    ) -> Self
+    where
+        P: Params,
+    {
+        let pass = CameraComputePass::builder("di_grid")
+            .bind([
+                &engine.lights.bind_readable(),
+                &engine.world.bind_readable(),
+            ])
+            .bind([
+                &buffers.camera.bind_readable(),
+                &buffers.di_light_grid.bind_writable(),
+            ])
+            .build(device, &engine.shaders.di_grid);
+
+        Self { pass }
+    }
+
+    pub fn run(
+        &self,
+        camera: &CameraController,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        // This pass uses 64-thread warps, one thread per each reservoir:
+        let size = uvec2((gpu::LightGrid::RESERVOIRS + 63) / 64, 1);
+
+        self.pass.run(camera, encoder, size, camera.pass_params());
+    }
+}
@@ -27,12 +27,14 @@ impl DiShadingPass {
                 &engine.lights.bind_readable(),
                 &engine.images.bind_atlas(),
                 &engine.world.bind_readable(),
+                &engine.lights.bind_tree(),
             ])
             .bind([
                 &buffers.camera.bind_readable(),
                 &buffers.prim_gbuffer_d0.bind_readable(),
                 &buffers.prim_gbuffer_d1.bind_readable(),
                 &buffers.di_curr_reservoirs.bind_writable(),
+                &buffers.di_light_grid.bind_readable(),
             ])
             .build(device, &engine.shaders.di_shading);
 
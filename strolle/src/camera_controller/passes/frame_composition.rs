@@ -66,7 +66,10 @@ impl FrameCompositionPass {
                     entry_point: engine.shaders.frame_composition_fs.1,
                     targets: &[Some(wgpu::ColorTargetState {
                         format: camera.viewport.format,
-                        blend: Some(wgpu::BlendState::REPLACE),
+                        // Not using `BlendState::REPLACE` here, since it's
+                        // not supported for non-blendable formats such as
+                        // `Rgba32Float` (and it's a no-op anyway)
+                        blend: None,
                         write_mask: wgpu::ColorWrites::ALL,
                     })],
                 }),
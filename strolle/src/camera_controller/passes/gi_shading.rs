@@ -30,6 +30,7 @@ impl GiShadingPass {
                 &engine.materials.bind_readable(),
                 &engine.images.bind_atlas(),
                 &engine.world.bind_readable(),
+                &engine.lights.bind_tree(),
             ])
             .bind([
                 &buffers.camera.bind_readable(),
@@ -41,6 +42,7 @@ impl GiShadingPass {
                 &buffers.gi_gbuffer_d0.bind_readable(),
                 &buffers.gi_gbuffer_d1.bind_readable(),
                 &buffers.gi_samples.bind_writable(),
+                &buffers.di_light_grid.bind_readable(),
             ])
             .build(device, &engine.shaders.gi_shading);
 
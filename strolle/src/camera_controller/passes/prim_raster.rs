@@ -4,6 +4,7 @@ use std::ops::Range;
 use glam::vec4;
 use log::debug;
 
+use crate::gpu::Affine3AExt;
 use crate::{
     gpu, BindGroup, Camera, CameraBuffers, CameraController, Engine, Params,
 };
@@ -57,30 +58,44 @@ impl PrimRasterPass {
                 vertex: wgpu::VertexState {
                     module: &engine.shaders.prim_raster_vs.0,
                     entry_point: engine.shaders.prim_raster_vs.1,
-                    buffers: &[wgpu::VertexBufferLayout {
-                        array_stride: (3 * 4 * mem::size_of::<f32>()) as _,
-                        step_mode: wgpu::VertexStepMode::Vertex,
-                        attributes: &[
-                            // position (xyz) + uv (x)
-                            wgpu::VertexAttribute {
-                                offset: 0,
-                                shader_location: 0,
-                                format: wgpu::VertexFormat::Float32x4,
-                            },
-                            // normal (xyz) + uv (y)
-                            wgpu::VertexAttribute {
-                                offset: (4 * mem::size_of::<f32>()) as _,
-                                shader_location: 1,
-                                format: wgpu::VertexFormat::Float32x4,
-                            },
-                            // tangent (xyzw)
-                            wgpu::VertexAttribute {
-                                offset: (8 * mem::size_of::<f32>()) as _,
-                                shader_location: 2,
-                                format: wgpu::VertexFormat::Float32x4,
-                            },
-                        ],
-                    }],
+                    buffers: &[
+                        wgpu::VertexBufferLayout {
+                            array_stride: (3 * 4 * mem::size_of::<f32>()) as _,
+                            step_mode: wgpu::VertexStepMode::Vertex,
+                            attributes: &[
+                                // position (xyz) + uv (x)
+                                wgpu::VertexAttribute {
+                                    offset: 0,
+                                    shader_location: 0,
+                                    format: wgpu::VertexFormat::Float32x4,
+                                },
+                                // normal (xyz) + uv (y)
+                                wgpu::VertexAttribute {
+                                    offset: (4 * mem::size_of::<f32>()) as _,
+                                    shader_location: 1,
+                                    format: wgpu::VertexFormat::Float32x4,
+                                },
+                                // tangent (xyzw)
+                                wgpu::VertexAttribute {
+                                    offset: (8 * mem::size_of::<f32>()) as _,
+                                    shader_location: 2,
+                                    format: wgpu::VertexFormat::Float32x4,
+                                },
+                            ],
+                        },
+                        wgpu::VertexBufferLayout {
+                            array_stride: (4 * mem::size_of::<f32>()) as _,
+                            step_mode: wgpu::VertexStepMode::Vertex,
+                            attributes: &[
+                                // previous position (xyz)
+                                wgpu::VertexAttribute {
+                                    offset: 0,
+                                    shader_location: 3,
+                                    format: wgpu::VertexFormat::Float32x4,
+                                },
+                            ],
+                        },
+                    ],
                 },
                 primitive: wgpu::PrimitiveState {
                     topology: wgpu::PrimitiveTopology::TriangleList,
@@ -193,9 +208,15 @@ impl PrimRasterPass {
         pass.set_bind_group(0, self.bg0.get(alternate), &[]);
         pass.set_bind_group(1, self.bg1.get(alternate), &[]);
 
-        for (instance_handle, instance_entry) in engine.instances.iter() {
+        for (_, instance_entry) in engine.instances.iter() {
             let instance = &instance_entry.instance;
 
+            if instance.visibility_mask & camera.camera.visibility_mask == 0
+                || !instance.ray_visibility.visible_to_camera
+            {
+                continue;
+            }
+
             let Some(material_id) =
                 engine.materials.lookup(&instance.material_handle)
             else {
@@ -203,13 +224,8 @@ impl PrimRasterPass {
             };
 
             let params = {
-                let curr_xform_inv = gpu::PrimRasterPassParams::encode_affine(
-                    instance.transform_inverse,
-                );
-
-                let prev_xform = gpu::PrimRasterPassParams::encode_affine(
-                    instance_entry.prev_transform,
-                );
+                let curr_xform = instance.transform.encode();
+                let prev_xform = instance_entry.prev_transform.encode();
 
                 gpu::PrimRasterPassParams {
                     payload: vec4(
@@ -218,9 +234,9 @@ impl PrimRasterPass {
                         Default::default(),
                         Default::default(),
                     ),
-                    curr_xform_inv_d0: curr_xform_inv[0],
-                    curr_xform_inv_d1: curr_xform_inv[1],
-                    curr_xform_inv_d2: curr_xform_inv[2],
+                    curr_xform_d0: curr_xform[0],
+                    curr_xform_d1: curr_xform[1],
+                    curr_xform_d2: curr_xform[2],
                     prev_xform_d0: prev_xform[0],
                     prev_xform_d1: prev_xform[1],
                     prev_xform_d2: prev_xform[2],
@@ -228,12 +244,19 @@ impl PrimRasterPass {
             };
 
             let Some((vertices, vertex_buffer)) =
-                engine.triangles.as_vertex_buffer(instance_handle)
+                engine.triangles.as_vertex_buffer(&instance.mesh_handle)
+            else {
+                continue;
+            };
+
+            let Some(prev_vertex_buffer) =
+                engine.triangles.as_prev_vertex_buffer(&instance.mesh_handle)
             else {
                 continue;
             };
 
             pass.set_vertex_buffer(0, vertex_buffer);
+            pass.set_vertex_buffer(1, prev_vertex_buffer);
 
             pass.set_push_constants(
                 wgpu::ShaderStages::VERTEX_FRAGMENT,
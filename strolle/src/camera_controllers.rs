@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use crate::{CameraController, CameraHandle};
+use crate::{CameraController, CameraHandle, StrolleError};
 
 #[derive(Debug, Default)]
 pub struct CameraControllers {
@@ -18,19 +18,22 @@ impl CameraControllers {
         handle
     }
 
-    pub fn get(&self, camera_handle: CameraHandle) -> &CameraController {
-        self.cameras.get(&camera_handle).unwrap_or_else(|| {
-            panic!("camera does not exist: {:?}", camera_handle)
-        })
+    pub fn get(
+        &self,
+        camera_handle: CameraHandle,
+    ) -> Result<&CameraController, StrolleError> {
+        self.cameras
+            .get(&camera_handle)
+            .ok_or(StrolleError::UnknownCamera(camera_handle))
     }
 
     pub fn get_mut(
         &mut self,
         camera_handle: CameraHandle,
-    ) -> &mut CameraController {
-        self.cameras.get_mut(&camera_handle).unwrap_or_else(|| {
-            panic!("camera does not exist: {:?}", camera_handle)
-        })
+    ) -> Result<&mut CameraController, StrolleError> {
+        self.cameras
+            .get_mut(&camera_handle)
+            .ok_or(StrolleError::UnknownCamera(camera_handle))
     }
 
     pub fn iter_mut(
@@ -43,3 +46,24 @@ impl CameraControllers {
         self.cameras.remove(&camera_handle);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_camera() {
+        let mut cameras = CameraControllers::default();
+        let handle = CameraHandle::new(123);
+
+        assert_eq!(
+            Some(StrolleError::UnknownCamera(handle)),
+            cameras.get(handle).err()
+        );
+
+        assert_eq!(
+            Some(StrolleError::UnknownCamera(handle)),
+            cameras.get_mut(handle).err()
+        );
+    }
+}
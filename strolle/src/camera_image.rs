@@ -0,0 +1,178 @@
+use glam::{uvec2, UVec2};
+
+use crate::StrolleError;
+
+/// Camera's output that has been rendered and read back into the CPU memory;
+/// see: [`crate::Engine::render_camera_to_image()`].
+#[derive(Clone, Debug)]
+pub enum CameraImage {
+    /// Image rendered into one of the 8-bit formats (`Rgba8Unorm`,
+    /// `Rgba8UnormSrgb`, `Bgra8Unorm` or `Bgra8UnormSrgb`).
+    ///
+    /// Pixels are always returned in the RGBA order, even if the camera uses
+    /// one of the BGRA formats.
+    Rgba8(image::RgbaImage),
+
+    /// Image rendered into `Rgba32Float`.
+    Rgba32F(image::Rgba32FImage),
+}
+
+impl CameraImage {
+    /// Returns how many bytes a single pixel of given format takes or an
+    /// error if the format cannot be read back into an image.
+    pub(crate) fn bytes_per_pixel(
+        format: wgpu::TextureFormat,
+    ) -> Result<u32, StrolleError> {
+        match format {
+            wgpu::TextureFormat::Rgba8Unorm
+            | wgpu::TextureFormat::Rgba8UnormSrgb
+            | wgpu::TextureFormat::Bgra8Unorm
+            | wgpu::TextureFormat::Bgra8UnormSrgb => Ok(4),
+
+            wgpu::TextureFormat::Rgba32Float => Ok(16),
+
+            format => Err(StrolleError::UnsupportedImageFormat(format)),
+        }
+    }
+
+    /// Creates an image from tightly-packed (i.e. with row padding already
+    /// removed) texels.
+    pub(crate) fn from_raw(
+        format: wgpu::TextureFormat,
+        size: UVec2,
+        mut data: Vec<u8>,
+    ) -> Result<Self, StrolleError> {
+        let image = match format {
+            wgpu::TextureFormat::Rgba8Unorm
+            | wgpu::TextureFormat::Rgba8UnormSrgb => Self::Rgba8(
+                image::RgbaImage::from_raw(size.x, size.y, data)
+                    .expect("invalid image size"),
+            ),
+
+            wgpu::TextureFormat::Bgra8Unorm
+            | wgpu::TextureFormat::Bgra8UnormSrgb => {
+                for pixel in data.chunks_exact_mut(4) {
+                    pixel.swap(0, 2);
+                }
+
+                Self::Rgba8(
+                    image::RgbaImage::from_raw(size.x, size.y, data)
+                        .expect("invalid image size"),
+                )
+            }
+
+            wgpu::TextureFormat::Rgba32Float => {
+                let data = data
+                    .chunks_exact(4)
+                    .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+                    .collect();
+
+                Self::Rgba32F(
+                    image::Rgba32FImage::from_raw(size.x, size.y, data)
+                        .expect("invalid image size"),
+                )
+            }
+
+            format => {
+                return Err(StrolleError::UnsupportedImageFormat(format));
+            }
+        };
+
+        Ok(image)
+    }
+
+    pub fn size(&self) -> UVec2 {
+        match self {
+            CameraImage::Rgba8(image) => uvec2(image.width(), image.height()),
+            CameraImage::Rgba32F(image) => uvec2(image.width(), image.height()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: UVec2 = uvec2(2, 1);
+
+    fn rgba8(format: wgpu::TextureFormat) -> image::RgbaImage {
+        assert_eq!(Ok(4), CameraImage::bytes_per_pixel(format));
+
+        let data = vec![10, 20, 30, 40, 50, 60, 70, 80];
+
+        match CameraImage::from_raw(format, SIZE, data) {
+            Ok(CameraImage::Rgba8(image)) => image,
+            image => panic!("unexpected image: {image:?}"),
+        }
+    }
+
+    #[test]
+    fn rgba8_unorm() {
+        let image = rgba8(wgpu::TextureFormat::Rgba8Unorm);
+
+        assert_eq!([10, 20, 30, 40], image.get_pixel(0, 0).0);
+        assert_eq!([50, 60, 70, 80], image.get_pixel(1, 0).0);
+    }
+
+    #[test]
+    fn rgba8_unorm_srgb() {
+        let image = rgba8(wgpu::TextureFormat::Rgba8UnormSrgb);
+
+        assert_eq!([10, 20, 30, 40], image.get_pixel(0, 0).0);
+        assert_eq!([50, 60, 70, 80], image.get_pixel(1, 0).0);
+    }
+
+    #[test]
+    fn bgra8_unorm() {
+        let image = rgba8(wgpu::TextureFormat::Bgra8Unorm);
+
+        assert_eq!([30, 20, 10, 40], image.get_pixel(0, 0).0);
+        assert_eq!([70, 60, 50, 80], image.get_pixel(1, 0).0);
+    }
+
+    #[test]
+    fn bgra8_unorm_srgb() {
+        let image = rgba8(wgpu::TextureFormat::Bgra8UnormSrgb);
+
+        assert_eq!([30, 20, 10, 40], image.get_pixel(0, 0).0);
+        assert_eq!([70, 60, 50, 80], image.get_pixel(1, 0).0);
+    }
+
+    #[test]
+    fn rgba32_float() {
+        let format = wgpu::TextureFormat::Rgba32Float;
+
+        assert_eq!(Ok(16), CameraImage::bytes_per_pixel(format));
+
+        let data = (0..8)
+            .flat_map(|idx| (idx as f32 * 0.5).to_le_bytes())
+            .collect();
+
+        let Ok(CameraImage::Rgba32F(image)) =
+            CameraImage::from_raw(format, SIZE, data)
+        else {
+            panic!("unexpected image");
+        };
+
+        assert_eq!([0.0, 0.5, 1.0, 1.5], image.get_pixel(0, 0).0);
+        assert_eq!([2.0, 2.5, 3.0, 3.5], image.get_pixel(1, 0).0);
+    }
+
+    #[test]
+    fn unsupported() {
+        for format in [
+            wgpu::TextureFormat::Rgba16Float,
+            wgpu::TextureFormat::R8Unorm,
+            wgpu::TextureFormat::Depth32Float,
+        ] {
+            let err = StrolleError::UnsupportedImageFormat(format);
+
+            assert_eq!(Err(err.clone()), CameraImage::bytes_per_pixel(format));
+
+            assert_eq!(
+                Some(err),
+                CameraImage::from_raw(format, SIZE, vec![0; 16]).err()
+            );
+        }
+    }
+}
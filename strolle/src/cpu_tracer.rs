@@ -0,0 +1,767 @@
+use std::thread;
+
+use glam::{uvec2, UVec2, Vec3, Vec4, Vec4Swizzles};
+
+use crate::{gpu, Camera, CameraImage, CameraMode, StrolleError};
+
+/// Host-side counterpart of the shaders' ray-tracing code.
+///
+/// Traverses the BVH using the very same code as the GPU does (see:
+/// `Ray::traverse()` in `strolle-gpu`) and reuses `strolle-gpu`'s BRDFs and
+/// lights, so that results produced on the CPU match the results produced on
+/// the GPU.
+///
+/// Since the atlas lives only in the VRAM, textures are not available here -
+/// materials are evaluated using just their factors (base color, emissive
+/// etc.).
+#[derive(Clone, Copy, Debug)]
+pub struct CpuTracer<'a> {
+    pub triangles: &'a [gpu::Triangle],
+    pub bvh: &'a [Vec4],
+    pub materials: &'a [gpu::Material],
+    pub lights: &'a [gpu::Light],
+    pub world: gpu::World,
+}
+
+impl<'a> CpuTracer<'a> {
+    /// Returns the closest opaque intersection of given ray with the world
+    /// (within the ray's length), if any.
+    pub fn trace(&self, ray: gpu::Ray) -> Option<CpuHit> {
+        let mut hit = gpu::TriangleHit {
+            distance: ray.length(),
+            ..gpu::TriangleHit::none()
+        };
+
+        let (instance_idx, triangle_id) =
+            self.traverse(ray, gpu::Tracing::ReturnClosest, &mut hit)?;
+
+        Some(CpuHit {
+            instance_idx,
+            triangle_id,
+            hit,
+        })
+    }
+
+    /// Returns whether given ray intersects with anything in the world (within
+    /// the ray's length); used for shadow rays.
+    pub fn intersect(&self, ray: gpu::Ray) -> bool {
+        let mut hit = gpu::TriangleHit {
+            distance: ray.length(),
+            ..gpu::TriangleHit::none()
+        };
+
+        self.traverse(ray, gpu::Tracing::ReturnFirst, &mut hit);
+
+        hit.distance < ray.length()
+    }
+
+    /// Returns the index of the last instance and the id of the last triangle
+    /// that's been hit.
+    fn traverse(
+        &self,
+        ray: gpu::Ray,
+        tracing: gpu::Tracing,
+        hit: &mut gpu::TriangleHit,
+    ) -> Option<(usize, gpu::TriangleId)> {
+        if self.bvh.is_empty() {
+            return None;
+        }
+
+        let bvh = gpu::BvhView::new(self.bvh);
+
+        // Atlas lives only in the VRAM, so alpha-blended materials are tested
+        // using just their base color's alpha
+        let traversal = ray.traverse(
+            &mut Vec::new(),
+            gpu::TrianglesView::new(self.triangles),
+            bvh,
+            tracing,
+            |material_id, _| {
+                self.materials[material_id.get() as usize].base_color.w >= 1.0
+            },
+            hit,
+        );
+
+        if traversal.instance_ptr == gpu::BvhView::SENTINEL {
+            None
+        } else {
+            Some((
+                bvh.instance_idx(traversal.instance_ptr) as usize,
+                traversal.triangle_id,
+            ))
+        }
+    }
+
+    /// Path-traces given camera, following the same algorithm as
+    /// [`CameraMode::Reference`] - see: `ref_shading` in `strolle-shaders` -
+    /// except for textures and the sky, which are not available on the CPU.
+    ///
+    /// Returns an error for other camera modes, since those rely on passes
+    /// that exist only on the GPU.
+    pub fn render(
+        &self,
+        camera: &Camera,
+        samples: u32,
+    ) -> Result<CameraImage, StrolleError> {
+        let CameraMode::Reference { depth } = camera.mode else {
+            return Err(StrolleError::UnsupportedCameraMode(camera.mode));
+        };
+
+        let gpu_camera = camera.serialize();
+        let size = camera.viewport.size;
+        let mut colors = vec![Vec3::ZERO; (size.x * size.y) as usize];
+
+        let threads = thread::available_parallelism()
+            .map(|threads| threads.get())
+            .unwrap_or(1);
+
+        let rows_per_thread = (size.y as usize).div_ceil(threads).max(1);
+
+        thread::scope(|s| {
+            for (chunk_idx, chunk) in colors
+                .chunks_mut(rows_per_thread * size.x as usize)
+                .enumerate()
+            {
+                s.spawn(move || {
+                    for (idx, color) in chunk.iter_mut().enumerate() {
+                        let idx =
+                            chunk_idx * rows_per_thread * size.x as usize + idx;
+
+                        let screen_pos = uvec2(
+                            idx as u32 % size.x, //
+                            idx as u32 / size.x,
+                        );
+
+                        for sample in 0..samples {
+                            *color += self.render_sample(
+                                &gpu_camera,
+                                screen_pos,
+                                depth,
+                                sample,
+                            );
+                        }
+
+                        *color /= samples.max(1) as f32;
+                    }
+                });
+            }
+        });
+
+        let data = colors
+            .into_iter()
+            .flat_map(|color| color.extend(1.0).to_array())
+            .flat_map(f32::to_le_bytes)
+            .collect();
+
+        CameraImage::from_raw(wgpu::TextureFormat::Rgba32Float, size, data)
+    }
+
+    fn render_sample(
+        &self,
+        camera: &gpu::Camera,
+        screen_pos: UVec2,
+        depth: u8,
+        sample: u32,
+    ) -> Vec3 {
+        let mut wnoise = gpu::WhiteNoise::new(
+            sample.wrapping_mul(0x9e3779b9).wrapping_add(1),
+            screen_pos,
+        );
+
+        let mut ray = camera.ray(screen_pos);
+        let mut color = Vec3::ZERO;
+        let mut throughput = Vec3::ONE;
+
+        for depth in 0..=depth {
+            let Some(CpuHit { hit: t_hit, .. }) = self.trace(ray) else {
+                // Atmosphere's lookup textures live only in the VRAM, so on
+                // the CPU the sky is black (the sun still lights the world,
+                // though, since it's one of the lights)
+                break;
+            };
+
+            let mut material = self.materials[t_hit.material_id.get() as usize];
+
+            if depth > 0 {
+                material.regularize();
+            }
+
+            let hit = gpu::Hit {
+                point: t_hit.point + t_hit.normal * gpu::Hit::NUDGE_OFFSET,
+                origin: ray.origin(),
+                direction: ray.direction(),
+                gbuffer: gpu::GBufferEntry {
+                    base_color: material.base_color,
+                    normal: t_hit.normal,
+                    metallic: material.metallic,
+                    emissive: material.emissive.xyz(),
+                    roughness: material.roughness,
+                    reflectance: material.reflectance,
+                    depth: 0.0,
+                },
+            };
+
+            // ---
+
+            color += throughput * hit.gbuffer.emissive;
+
+            if self.world.light_count > 0 {
+                let light_id = wnoise.sample_int() % self.world.light_count;
+                let light_pdf = 1.0 / (self.world.light_count as f32);
+                let light = self.lights[light_id as usize];
+                let light_ray = light
+                    .ray_wnoise(&mut wnoise, hit.point)
+                    .with_visibility_mask(camera.visibility_mask());
+
+                if !self.intersect(light_ray) {
+                    color += throughput * light.contribution(hit) / light_pdf;
+                }
+            }
+
+            // ---
+
+            let reflected_sample = gpu::LayeredBrdf::sample(&mut wnoise, hit);
+
+            if reflected_sample.is_invalid() {
+                break;
+            }
+
+            ray = gpu::Ray::new(hit.point, reflected_sample.direction)
+                .with_visibility_mask(camera.visibility_mask())
+                .with_kind(reflected_sample.kind);
+
+            throughput *= reflected_sample.direction.dot(hit.gbuffer.normal);
+            throughput *= reflected_sample.throughput;
+        }
+
+        color
+    }
+}
+
+/// Intersection found by [`CpuTracer`].
+#[derive(Clone, Copy)]
+pub struct CpuHit {
+    /// Index of the instance within the top-level BVH; see:
+    /// `Instances::get_by_bvh_index()`.
+    pub instance_idx: usize,
+
+    pub triangle_id: gpu::TriangleId,
+    pub hit: gpu::TriangleHit,
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec3, vec4, Affine3A};
+
+    use super::*;
+    use crate::gpu::Affine3AExt;
+    use crate::{BvhConfig, BvhPrimitive, BvhQuantization, BvhTree, Triangle};
+
+    #[test]
+    fn trace() {
+        // Triangle located at z=0 in the object-space, but at z=-5 in the
+        // world-space (and twice as large)
+        let triangles = [Triangle {
+            positions: [
+                vec3(-0.5, -0.5, 0.0),
+                vec3(0.5, -0.5, 0.0),
+                vec3(0.0, 0.5, 0.0),
+            ],
+            normals: [Vec3::Z; 3],
+            uvs: Default::default(),
+            tangents: Default::default(),
+        }
+        .serialize()];
+
+        let xform = Affine3A::from_translation(vec3(0.0, 0.0, -5.0))
+            * Affine3A::from_scale(Vec3::splat(2.0));
+
+        let [xform_d0, xform_d1, xform_d2] = xform.inverse().encode();
+
+        // Just a single instance pointing at a single leaf-node
+        let bvh = [
+            vec4(f32::from_bits(1), 0.0, 0.0, 0.0),
+            vec4(
+                f32::from_bits(gpu::RayKind::ANY.get() << 2),
+                f32::from_bits(6),
+                f32::from_bits(0),
+                f32::from_bits(gpu::BvhView::OP_INSTANCE),
+            ),
+            xform_d0,
+            xform_d1,
+            xform_d2,
+            vec4(f32::from_bits(0), f32::from_bits(u32::MAX), 0.0, 0.0),
+            vec4(
+                f32::from_bits(0),
+                f32::from_bits(0),
+                f32::from_bits(0),
+                f32::from_bits(gpu::BvhView::OP_TRIANGLE),
+            ),
+        ];
+
+        let materials = [gpu::Material {
+            base_color: Vec4::ONE,
+            base_color_texture: Default::default(),
+            emissive: Default::default(),
+            emissive_texture: Default::default(),
+            roughness: 1.0,
+            metallic: 0.0,
+            reflectance: 0.5,
+            ior: 1.0,
+            normal_map_texture: Default::default(),
+        }];
+
+        let target = CpuTracer {
+            triangles: &triangles,
+            bvh: &bvh,
+            materials: &materials,
+            lights: &[],
+            world: Default::default(),
+        };
+
+        let hit = target
+            .trace(gpu::Ray::new(Vec3::ZERO, -Vec3::Z))
+            .unwrap()
+            .hit;
+
+        assert_eq!(5.0, hit.distance);
+        assert_eq!(vec3(0.0, 0.0, -5.0), hit.point);
+        assert_eq!(Vec3::Z, hit.normal);
+
+        assert!(target.trace(gpu::Ray::new(Vec3::ZERO, Vec3::Z)).is_none());
+        assert!(target
+            .trace(gpu::Ray::new(Vec3::ZERO, -Vec3::Z).with_length(4.0))
+            .is_none());
+
+        assert!(target
+            .intersect(gpu::Ray::new(Vec3::ZERO, -Vec3::Z).with_length(10.0)));
+
+        assert!(!target
+            .intersect(gpu::Ray::new(Vec3::ZERO, -Vec3::Z).with_length(4.0)));
+    }
+
+    #[test]
+    fn instance_visibility() {
+        let triangles = [Triangle {
+            positions: [
+                vec3(-0.5, -0.5, 0.0),
+                vec3(0.5, -0.5, 0.0),
+                vec3(0.0, 0.5, 0.0),
+            ],
+            normals: [Vec3::Z; 3],
+            uvs: Default::default(),
+            tangents: Default::default(),
+        }
+        .serialize()];
+
+        let instance = |z: f32,
+                        more: bool,
+                        idx: u32,
+                        visibility_mask: u32,
+                        ray_kinds: u32| {
+            let [xform_d0, xform_d1, xform_d2] =
+                Affine3A::from_translation(vec3(0.0, 0.0, z))
+                    .inverse()
+                    .encode();
+
+            [
+                vec4(
+                    f32::from_bits((more as u32) | (ray_kinds << 2)),
+                    f32::from_bits(11),
+                    f32::from_bits(0),
+                    f32::from_bits(gpu::BvhView::OP_INSTANCE),
+                ),
+                xform_d0,
+                xform_d1,
+                xform_d2,
+                vec4(
+                    f32::from_bits(idx),
+                    f32::from_bits(visibility_mask),
+                    0.0,
+                    0.0,
+                ),
+            ]
+        };
+
+        // Two instances of the same triangle, both in a single leaf-node: the
+        // first one located at z=-5, visible on layer 0 and not casting any
+        // shadows, the second one located at z=-10 and visible on layer 1
+        let mut bvh = vec![vec4(f32::from_bits(1), 0.0, 0.0, 0.0)];
+
+        bvh.extend(instance(
+            -5.0,
+            true,
+            0,
+            0b01,
+            gpu::RayKind::ANY.get() & !gpu::RayKind::SHADOW.get(),
+        ));
+
+        bvh.extend(instance(-10.0, false, 1, 0b10, gpu::RayKind::ANY.get()));
+
+        bvh.push(vec4(
+            f32::from_bits(0),
+            f32::from_bits(0),
+            f32::from_bits(0),
+            f32::from_bits(gpu::BvhView::OP_TRIANGLE),
+        ));
+
+        let materials = [gpu::Material {
+            base_color: Vec4::ONE,
+            base_color_texture: Default::default(),
+            emissive: Default::default(),
+            emissive_texture: Default::default(),
+            roughness: 1.0,
+            metallic: 0.0,
+            reflectance: 0.5,
+            ior: 1.0,
+            normal_map_texture: Default::default(),
+        }];
+
+        let target = CpuTracer {
+            triangles: &triangles,
+            bvh: &bvh,
+            materials: &materials,
+            lights: &[],
+            world: Default::default(),
+        };
+
+        let trace = |visibility_mask, kind| {
+            target
+                .trace(
+                    gpu::Ray::new(Vec3::ZERO, -Vec3::Z)
+                        .with_visibility_mask(visibility_mask)
+                        .with_kind(kind),
+                )
+                .map(|hit| (hit.instance_idx, hit.hit.distance))
+        };
+
+        assert_eq!(Some((0, 5.0)), trace(u32::MAX, gpu::RayKind::ANY));
+        assert_eq!(Some((0, 5.0)), trace(0b01, gpu::RayKind::CAMERA));
+        assert_eq!(Some((1, 10.0)), trace(0b10, gpu::RayKind::CAMERA));
+        assert_eq!(None, trace(0b100, gpu::RayKind::ANY));
+
+        // Shadow rays go through the first instance
+        assert_eq!(Some((1, 10.0)), trace(u32::MAX, gpu::RayKind::SHADOW));
+        assert_eq!(None, trace(0b01, gpu::RayKind::SHADOW));
+
+        assert!(!target.intersect(
+            gpu::Ray::new(Vec3::ZERO, -Vec3::Z)
+                .with_length(20.0)
+                .with_visibility_mask(0b01)
+                .with_kind(gpu::RayKind::SHADOW)
+        ));
+    }
+
+    #[test]
+    fn wide_bvh() {
+        // Grid of triangles located at different depths
+        let triangles: Vec<_> = (0..256)
+            .map(|idx| {
+                let offset = vec3(
+                    (idx % 16) as f32,
+                    (idx / 16) as f32,
+                    -(idx % 7) as f32,
+                );
+
+                Triangle {
+                    positions: [
+                        offset,
+                        offset + vec3(1.0, 0.0, 0.0),
+                        offset + vec3(0.0, 1.0, 0.0),
+                    ],
+                    normals: [Vec3::Z; 3],
+                    uvs: Default::default(),
+                    tangents: Default::default(),
+                }
+                .serialize()
+            })
+            .collect();
+
+        let mut blas = BvhTree::default();
+
+        blas.build(
+            triangles.iter().enumerate().map(|(item_id, triangle)| {
+                let bounds: crate::BoundingBox =
+                    triangle.positions().into_iter().collect();
+
+                BvhPrimitive {
+                    item_id: item_id as u32,
+                    center: bounds.center(),
+                    bounds,
+                }
+            }),
+            BvhConfig::default(),
+        );
+
+        let mut serialize = |wide, quantization| {
+            let mut bvh = vec![Vec4::ZERO];
+
+            blas.serialize(
+                &mut bvh,
+                BvhConfig {
+                    wide,
+                    quantization,
+                    ..Default::default()
+                },
+                &mut |bvh, primitive, got_more_entries| {
+                    bvh.push(vec4(
+                        f32::from_bits(got_more_entries as u32),
+                        f32::from_bits(primitive.item_id),
+                        f32::from_bits(0),
+                        f32::from_bits(gpu::BvhView::OP_TRIANGLE),
+                    ));
+
+                    0
+                },
+            );
+
+            // Instance is moved, so that going back to the world-space ray
+            // in the middle of traversing its BLAS would yield wrong hits
+            let [xform_d0, xform_d1, xform_d2] =
+                Affine3A::from_translation(vec3(0.5, 1.0, 0.0)).encode();
+
+            bvh[0] = vec4(f32::from_bits(bvh.len() as u32), 0.0, 0.0, 0.0);
+
+            bvh.extend([
+                vec4(
+                    f32::from_bits(gpu::RayKind::ANY.get() << 2),
+                    f32::from_bits(blas.ptr),
+                    f32::from_bits(0),
+                    f32::from_bits(gpu::BvhView::OP_INSTANCE),
+                ),
+                xform_d0,
+                xform_d1,
+                xform_d2,
+                vec4(f32::from_bits(0), f32::from_bits(u32::MAX), 0.0, 0.0),
+            ]);
+
+            bvh
+        };
+
+        let binary_bvh = serialize(false, None);
+        let wide_bvh = serialize(true, None);
+        let wide_q8_bvh = serialize(true, Some(BvhQuantization::Bits8));
+        let wide_q16_bvh = serialize(true, Some(BvhQuantization::Bits16));
+
+        assert!(wide_bvh.len() < binary_bvh.len());
+        assert!(wide_q16_bvh.len() < wide_bvh.len());
+        assert!(wide_q8_bvh.len() < wide_q16_bvh.len());
+
+        let tracer = |bvh| CpuTracer {
+            triangles: &triangles,
+            bvh,
+            materials: &[],
+            lights: &[],
+            world: Default::default(),
+        };
+
+        let binary_tracer = tracer(&binary_bvh);
+
+        let other_tracers = [
+            tracer(&wide_bvh),
+            tracer(&wide_q8_bvh),
+            tracer(&wide_q16_bvh),
+        ];
+
+        let mut hits = 0;
+
+        for x in 0..32 {
+            for y in 0..32 {
+                // Offsets are chosen so that no ray passes exactly through a
+                // triangle's edge, where the intersection test's tolerance
+                // could accept hits lying just outside of the exact bounds
+                // (and so hits that only quantized bounds would find)
+                let ray = gpu::Ray::new(
+                    vec3(x as f32 * 0.5 + 0.13, y as f32 * 0.5 + 0.21, 5.0),
+                    vec3(0.07, -0.05, -1.0).normalize(),
+                );
+
+                let expected = binary_tracer
+                    .trace(ray)
+                    .map(|hit| (hit.triangle_id, hit.hit.distance));
+
+                hits += expected.is_some() as usize;
+
+                for tracer in &other_tracers {
+                    let actual = tracer
+                        .trace(ray)
+                        .map(|hit| (hit.triangle_id, hit.hit.distance));
+
+                    assert_eq!(expected, actual);
+
+                    // Infinite length is what callers naturally use to say
+                    // "no limit", so it must behave the same as the default
+                    let actual = tracer
+                        .trace(ray.with_length(f32::INFINITY))
+                        .map(|hit| (hit.triangle_id, hit.hit.distance));
+
+                    assert_eq!(expected, actual);
+                }
+            }
+        }
+
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn render() {
+        let target = CpuTracer {
+            triangles: &[],
+            bvh: &[],
+            materials: &[],
+            lights: &[],
+            world: Default::default(),
+        };
+
+        let camera = |mode| Camera {
+            mode,
+            viewport: crate::CameraViewport {
+                size: uvec2(4, 2),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let image = target
+            .render(&camera(CameraMode::Reference { depth: 1 }), 1)
+            .unwrap();
+
+        assert_eq!(uvec2(4, 2), image.size());
+
+        for mode in [
+            CameraMode::Image,
+            CameraMode::DirectLighting,
+            CameraMode::BvhHeatmap,
+        ] {
+            assert_eq!(
+                Some(StrolleError::UnsupportedCameraMode(mode)),
+                target.render(&camera(mode), 1).err(),
+            );
+        }
+    }
+
+    #[test]
+    fn render_direct_lighting() {
+        // Diffuse 2x2 quad located at z=0, facing the camera
+        let quad = [
+            [
+                vec3(-1.0, -1.0, 0.0),
+                vec3(1.0, -1.0, 0.0),
+                vec3(1.0, 1.0, 0.0),
+            ],
+            [
+                vec3(-1.0, -1.0, 0.0),
+                vec3(1.0, 1.0, 0.0),
+                vec3(-1.0, 1.0, 0.0),
+            ],
+        ];
+
+        let triangles = quad.map(|positions| {
+            Triangle {
+                positions,
+                normals: [Vec3::Z; 3],
+                uvs: Default::default(),
+                tangents: Default::default(),
+            }
+            .serialize()
+        });
+
+        let [xform_d0, xform_d1, xform_d2] = Affine3A::IDENTITY.encode();
+
+        let bvh = [
+            vec4(f32::from_bits(1), 0.0, 0.0, 0.0),
+            vec4(
+                f32::from_bits(gpu::RayKind::ANY.get() << 2),
+                f32::from_bits(6),
+                f32::from_bits(0),
+                f32::from_bits(gpu::BvhView::OP_INSTANCE),
+            ),
+            xform_d0,
+            xform_d1,
+            xform_d2,
+            vec4(f32::from_bits(0), f32::from_bits(u32::MAX), 0.0, 0.0),
+            vec4(
+                f32::from_bits(1),
+                f32::from_bits(0),
+                f32::from_bits(0),
+                f32::from_bits(gpu::BvhView::OP_TRIANGLE),
+            ),
+            vec4(
+                f32::from_bits(0),
+                f32::from_bits(1),
+                f32::from_bits(0),
+                f32::from_bits(gpu::BvhView::OP_TRIANGLE),
+            ),
+        ];
+
+        let albedo = vec3(0.5, 0.25, 1.0);
+
+        let materials = [gpu::Material {
+            base_color: albedo.extend(1.0),
+            base_color_texture: Default::default(),
+            emissive: Default::default(),
+            emissive_texture: Default::default(),
+            roughness: 1.0,
+            metallic: 0.0,
+            reflectance: 0.5,
+            ior: 1.0,
+            normal_map_texture: Default::default(),
+        }];
+
+        let light_position = vec3(1.0, 0.0, 2.0);
+        let light_color = vec3(2.0, 3.0, 4.0);
+
+        let lights = [crate::Light::Point {
+            position: light_position,
+            radius: 0.0,
+            color: light_color,
+            range: 1000.0,
+        }
+        .serialize()];
+
+        let target = CpuTracer {
+            triangles: &triangles,
+            bvh: &bvh,
+            materials: &materials,
+            lights: &lights,
+            world: gpu::World {
+                light_count: 1,
+                ..Default::default()
+            },
+        };
+
+        // Single-pixel camera located at z=5, looking at the quad's center
+        let camera = Camera {
+            mode: CameraMode::Reference { depth: 0 },
+            viewport: crate::CameraViewport {
+                size: uvec2(1, 1),
+                ..Default::default()
+            },
+            transform: glam::Mat4::from_translation(vec3(0.0, 0.0, 5.0)),
+            projection: glam::Mat4::perspective_infinite_reverse_rh(
+                1.0, 1.0, 0.1,
+            ),
+            ..Default::default()
+        };
+
+        let Ok(CameraImage::Rgba32F(image)) = target.render(&camera, 1) else {
+            panic!("unexpected image");
+        };
+
+        let actual = Vec3::from_slice(&image.get_pixel(0, 0).0);
+
+        // Strolle's Lambertian BRDF is just the albedo (i.e. lights' colors
+        // are expected to already account for the `1/π` factor), so a point
+        // light contributes `color * cos(θ) / d² * albedo`
+        let to_light = light_position - Vec3::ZERO;
+        let cos_theta = to_light.normalize().dot(Vec3::Z);
+
+        let expected =
+            light_color * cos_theta / to_light.length_squared() * albedo;
+
+        assert!(
+            actual.abs_diff_eq(expected, 1e-3 * expected.max_element()),
+            "expected {expected}, got {actual}"
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use std::{error, fmt};
+
+use crate::{CameraHandle, CameraMode};
+
+/// Error returned by the fallible (`try_*`) functions.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StrolleError {
+    /// Image's texture is not two-dimensional, which is the only kind of
+    /// texture supported at the moment.
+    UnsupportedImageDimension(wgpu::TextureDimension),
+
+    /// Camera's texture format cannot be read back into a [`CameraImage`].
+    ///
+    /// [`CameraImage`]: crate::CameraImage
+    UnsupportedImageFormat(wgpu::TextureFormat),
+
+    /// Camera's image couldn't be read back from the GPU, e.g. because mapping
+    /// its buffer has failed.
+    ImageReadbackFailed(String),
+
+    /// Mesh cannot be used for rendering, e.g. because it lacks normals.
+    InvalidMesh(String),
+
+    /// Instance cannot be used for rendering, e.g. because its transform is
+    /// not invertible.
+    InvalidInstance(String),
+
+    /// BVH cannot be built using given configuration, e.g. because it uses
+    /// too few bins.
+    InvalidBvhConfig(String),
+
+    /// Camera doesn't exist, e.g. because it has been already deleted.
+    UnknownCamera(CameraHandle),
+
+    /// Camera's mode cannot be rendered this way, e.g. only
+    /// [`CameraMode::Reference`] can be rendered on the CPU.
+    UnsupportedCameraMode(CameraMode),
+}
+
+impl fmt::Display for StrolleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StrolleError::UnsupportedImageDimension(dimension) => {
+                write!(
+                    f,
+                    "unsupported image dimension: {dimension:?} (only 2D \
+                     textures are supported)"
+                )
+            }
+
+            StrolleError::UnsupportedImageFormat(format) => {
+                write!(
+                    f,
+                    "unsupported image format: {format:?} (only Rgba8Unorm, \
+                     Rgba8UnormSrgb, Bgra8Unorm, Bgra8UnormSrgb and \
+                     Rgba32Float can be read back)"
+                )
+            }
+
+            StrolleError::ImageReadbackFailed(reason) => {
+                write!(f, "couldn't read camera's image back: {reason}")
+            }
+
+            StrolleError::InvalidMesh(reason) => {
+                write!(f, "invalid mesh: {reason}")
+            }
+
+            StrolleError::InvalidInstance(reason) => {
+                write!(f, "invalid instance: {reason}")
+            }
+
+            StrolleError::InvalidBvhConfig(reason) => {
+                write!(f, "invalid BVH config: {reason}")
+            }
+
+            StrolleError::UnknownCamera(handle) => {
+                write!(f, "camera does not exist: {handle:?}")
+            }
+
+            StrolleError::UnsupportedCameraMode(mode) => {
+                write!(f, "unsupported camera mode: {mode:?}")
+            }
+        }
+    }
+}
+
+impl error::Error for StrolleError {}
@@ -1,4 +1,4 @@
-use crate::Params;
+use crate::{Params, StrolleError};
 
 #[derive(Debug)]
 pub struct Image<P>
@@ -17,18 +17,36 @@ impl<P> Image<P>
 where
     P: Params,
 {
+    /// Creates a new image.
+    ///
+    /// Panics if the texture is not two-dimensional; see: [`Self::try_new()`].
     pub fn new(
         data: ImageData<P>,
         texture_descriptor: wgpu::TextureDescriptor<'static>,
         sampler_descriptor: wgpu::SamplerDescriptor<'static>,
     ) -> Self {
-        assert_eq!(texture_descriptor.dimension, wgpu::TextureDimension::D2);
+        Self::try_new(data, texture_descriptor, sampler_descriptor)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
 
-        Self {
+    /// Creates a new image, returning an error if the texture is not
+    /// two-dimensional.
+    pub fn try_new(
+        data: ImageData<P>,
+        texture_descriptor: wgpu::TextureDescriptor<'static>,
+        sampler_descriptor: wgpu::SamplerDescriptor<'static>,
+    ) -> Result<Self, StrolleError> {
+        if texture_descriptor.dimension != wgpu::TextureDimension::D2 {
+            return Err(StrolleError::UnsupportedImageDimension(
+                texture_descriptor.dimension,
+            ));
+        }
+
+        Ok(Self {
             data,
             texture_descriptor,
             _sampler_descriptor: sampler_descriptor,
-        }
+        })
     }
 }
 
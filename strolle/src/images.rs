@@ -103,6 +103,10 @@ where
         }
     }
 
+    pub fn handles(&self) -> impl Iterator<Item = &P::ImageHandle> + '_ {
+        self.images.keys()
+    }
+
     pub fn remove(&mut self, image_handle: &P::ImageHandle) {
         let Some(image_alloc) = self.images.remove(image_handle) else {
             return;
@@ -1,6 +1,6 @@
 use glam::Affine3A;
 
-use crate::Params;
+use crate::{gpu, Params, StrolleError};
 
 #[derive(Debug)]
 pub struct Instance<P>
@@ -11,6 +11,8 @@ where
     pub(crate) material_handle: P::MaterialHandle,
     pub(crate) transform: Affine3A,
     pub(crate) transform_inverse: Affine3A,
+    pub(crate) visibility_mask: u32,
+    pub(crate) ray_visibility: RayVisibility,
 }
 
 impl<P> Instance<P>
@@ -27,6 +29,155 @@ where
             material_handle,
             transform,
             transform_inverse: transform.inverse(),
+            visibility_mask: u32::MAX,
+            ray_visibility: Default::default(),
         }
     }
+
+    /// Sets which cameras can see this instance - an instance is visible to
+    /// a camera only if their masks have at least one bit in common; see:
+    /// [`crate::Camera::visibility_mask`].
+    ///
+    /// By default instances are visible to all cameras.
+    pub fn with_visibility_mask(mut self, visibility_mask: u32) -> Self {
+        self.visibility_mask = visibility_mask;
+        self
+    }
+
+    /// Sets which kinds of rays can hit this instance; by default instances
+    /// are visible to all rays.
+    pub fn with_ray_visibility(
+        mut self,
+        ray_visibility: RayVisibility,
+    ) -> Self {
+        self.ray_visibility = ray_visibility;
+        self
+    }
+
+    /// Checks whether this instance can be rendered; see:
+    /// [`crate::Engine::try_insert_instance()`].
+    pub(crate) fn validate(&self) -> Result<(), StrolleError> {
+        if !self.transform.is_finite() {
+            return Err(StrolleError::InvalidInstance(
+                "instance's transform is not finite".into(),
+            ));
+        }
+
+        if !self.transform_inverse.is_finite()
+            || self.transform.matrix3.determinant() == 0.0
+        {
+            return Err(StrolleError::InvalidInstance(
+                "instance's transform is not invertible".into(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Specifies which kinds of rays can hit an instance.
+///
+/// For instance, a first-person player's body can be made invisible to the
+/// camera while still casting shadows, while decals can be made to not occlude
+/// any indirect lighting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RayVisibility {
+    /// Whether the instance is visible to the camera directly.
+    pub visible_to_camera: bool,
+
+    /// Whether the instance occludes lights.
+    pub casts_shadows: bool,
+
+    /// Whether the instance is visible in indirect diffuse lighting.
+    pub visible_in_indirect: bool,
+
+    /// Whether the instance is visible in indirect specular lighting (i.e.
+    /// in reflections).
+    pub visible_in_reflections: bool,
+}
+
+impl RayVisibility {
+    pub(crate) fn serialize(&self) -> u32 {
+        let mut bits = 0;
+
+        if self.visible_to_camera {
+            bits |= gpu::RayKind::CAMERA.get();
+        }
+
+        if self.casts_shadows {
+            bits |= gpu::RayKind::SHADOW.get();
+        }
+
+        if self.visible_in_indirect {
+            bits |= gpu::RayKind::INDIRECT.get();
+        }
+
+        if self.visible_in_reflections {
+            bits |= gpu::RayKind::REFLECTION.get();
+        }
+
+        bits
+    }
+}
+
+impl Default for RayVisibility {
+    fn default() -> Self {
+        Self {
+            visible_to_camera: true,
+            casts_shadows: true,
+            visible_in_indirect: true,
+            visible_in_reflections: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec3, Quat, Vec3};
+
+    use super::*;
+    use crate::SceneParams;
+
+    fn instance(transform: Affine3A) -> Instance<SceneParams> {
+        Instance::new("mesh".into(), "material".into(), transform)
+    }
+
+    #[test]
+    fn validate() {
+        let transform = Affine3A::from_scale_rotation_translation(
+            vec3(1.0, 2.0, 3.0),
+            Quat::from_rotation_y(1.0),
+            vec3(4.0, 5.0, 6.0),
+        );
+
+        assert_eq!(Ok(()), instance(transform).validate());
+    }
+
+    #[test]
+    fn validate_non_finite() {
+        assert_eq!(
+            Err(StrolleError::InvalidInstance(
+                "instance's transform is not finite".into()
+            )),
+            instance(Affine3A::from_translation(vec3(f32::NAN, 0.0, 0.0)))
+                .validate()
+        );
+    }
+
+    #[test]
+    fn validate_non_invertible() {
+        assert_eq!(
+            Err(StrolleError::InvalidInstance(
+                "instance's transform is not invertible".into()
+            )),
+            instance(Affine3A::from_scale(vec3(1.0, 0.0, 1.0))).validate()
+        );
+
+        assert_eq!(
+            Err(StrolleError::InvalidInstance(
+                "instance's transform is not invertible".into()
+            )),
+            instance(Affine3A::from_scale(Vec3::ZERO)).validate()
+        );
+    }
 }
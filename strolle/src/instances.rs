@@ -4,13 +4,14 @@ use std::mem;
 
 use derivative::Derivative;
 use glam::Affine3A;
+use log::warn;
 use rand::Rng;
 
 use crate::bvh::Bvh;
 use crate::materials::Materials;
 use crate::meshes::Meshes;
 use crate::triangles::Triangles;
-use crate::{Instance, Params};
+use crate::{AlphaMode, BvhInstance, Instance, MeshTriangle, Params};
 
 #[derive(Debug, Derivative)]
 #[derivative(Default(bound = ""))]
@@ -19,6 +20,11 @@ where
     P: Params,
 {
     instances: HashMap<P::InstanceHandle, InstanceEntry<P>>,
+
+    /// Instances in the order they appear in the top-level BVH; used to go
+    /// from a ray-hit back to the instance.
+    bvh_order: Vec<P::InstanceHandle>,
+
     dirty: bool,
 }
 
@@ -37,14 +43,12 @@ where
 
                 entry.prev_transform = entry.instance.transform;
                 entry.instance = instance;
-                entry.dirty = true;
             }
 
             Entry::Vacant(entry) => {
                 entry.insert(InstanceEntry {
                     prev_transform: instance.transform,
                     uuid: rand::thread_rng().gen(),
-                    dirty: true,
                     instance,
                 });
             }
@@ -64,6 +68,15 @@ where
             })
     }
 
+    pub fn get(
+        &self,
+        instance_handle: &P::InstanceHandle,
+    ) -> Option<&Instance<P>> {
+        self.instances
+            .get(instance_handle)
+            .map(|instance_entry| &instance_entry.instance)
+    }
+
     pub fn remove(&mut self, instance_handle: &P::InstanceHandle) {
         self.dirty |= self.instances.remove(instance_handle).is_some();
     }
@@ -72,6 +85,25 @@ where
         self.instances.is_empty()
     }
 
+    /// Returns the instance located at given index within the top-level BVH;
+    /// see: [`BvhInstance`].
+    pub fn get_by_bvh_index(
+        &self,
+        idx: usize,
+    ) -> Option<(&P::InstanceHandle, &Instance<P>)> {
+        let instance_handle = self.bvh_order.get(idx)?;
+
+        Some((instance_handle, self.get(instance_handle)?))
+    }
+
+    /// Marks all instances as dirty, forcing the top-level BVH to be rebuilt;
+    /// necessary when meshes or materials change.
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Rebuilds the top-level BVH, if any of the instances has changed;
+    /// returns whether that was the case.
     pub fn refresh(
         &mut self,
         meshes: &Meshes<P>,
@@ -83,16 +115,17 @@ where
             return false;
         }
 
-        for (instance_handle, entry) in &mut self.instances {
-            if !mem::take(&mut entry.dirty) {
-                continue;
-            }
+        let mut bvh_instances = Vec::with_capacity(self.instances.len());
+
+        self.bvh_order.clear();
 
-            let Some(mesh) = meshes.get(&entry.instance.mesh_handle) else {
+        for (instance_handle, entry) in &self.instances {
+            let mesh_handle = &entry.instance.mesh_handle;
+
+            let Some(mesh) = meshes.get(mesh_handle) else {
                 // If the mesh is not yet available, it might be still being
                 // loaded in the background - in that case let's try again next
                 // frame
-                entry.dirty = true;
                 self.dirty = true;
                 continue;
             };
@@ -101,46 +134,47 @@ where
                 materials.lookup(&entry.instance.material_handle)
             else {
                 // Same for materials
-                entry.dirty = true;
                 self.dirty = true;
                 continue;
             };
 
-            let mesh_triangles = mesh.triangles().iter().map(|triangle| {
-                triangle.build(
-                    entry.instance.transform,
-                    entry.instance.transform_inverse,
-                )
-            });
+            // Meshes get uploaded lazily, when the first instance that uses
+            // them appears
+            if !triangles.has(mesh_handle) {
+                let result = triangles.create(
+                    bvh,
+                    mesh_handle.to_owned(),
+                    mesh.triangles().iter().map(MeshTriangle::build),
+                );
 
-            if let Some(count) = triangles.count(instance_handle) {
-                if mesh.triangles().len() == count {
-                    triangles.update(
-                        bvh,
-                        instance_handle,
-                        mesh_triangles,
-                        material_id,
-                    );
-                } else {
-                    triangles.remove(bvh, instance_handle);
-
-                    triangles.create(
-                        bvh,
-                        instance_handle.to_owned(),
-                        mesh_triangles,
-                        material_id,
+                if let Err(err) = result {
+                    // E.g. empty meshes have nothing to render
+                    warn!(
+                        "Cannot add instance `{:?}` - {}",
+                        instance_handle, err
                     );
+                    continue;
                 }
-            } else {
-                triangles.create(
-                    bvh,
-                    instance_handle.to_owned(),
-                    mesh_triangles,
-                    material_id,
-                );
             }
+
+            bvh_instances.push(BvhInstance {
+                blas_id: triangles.blas_id(mesh_handle).unwrap(),
+                material_id,
+                has_alpha_blending: matches!(
+                    materials[material_id].alpha_mode,
+                    AlphaMode::Blend
+                ),
+                transform: entry.instance.transform,
+                transform_inverse: entry.instance.transform_inverse,
+                visibility_mask: entry.instance.visibility_mask,
+                ray_visibility: entry.instance.ray_visibility,
+            });
+
+            self.bvh_order.push(instance_handle.to_owned());
         }
 
+        bvh.refresh(&bvh_instances);
+
         true
     }
 }
@@ -153,5 +187,4 @@ where
     pub instance: Instance<P>,
     pub uuid: u32,
     pub prev_transform: Affine3A,
-    pub dirty: bool,
 }
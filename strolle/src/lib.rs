@@ -51,18 +51,26 @@ mod bvh;
 mod camera;
 mod camera_controller;
 mod camera_controllers;
+mod camera_image;
+mod cpu_tracer;
+mod error;
 mod image;
 mod images;
 mod instance;
 mod instances;
 mod light;
+mod light_tree;
 mod lights;
+mod loaders;
 mod material;
 mod materials;
 mod mesh;
 mod mesh_triangle;
 mod meshes;
 mod noise;
+mod ray_queries;
+mod raycast;
+mod scene;
 mod shaders;
 mod sun;
 mod triangle;
@@ -72,23 +80,31 @@ mod utils;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::ops::Deref;
+use std::path::PathBuf;
 use std::time::Instant;
-use std::{env, mem};
+use std::{env, io, mem};
 
 pub use glam;
+use glam::Vec3;
 use log::{info, trace};
 use strolle_gpu as gpu;
+pub use strolle_gpu::{RayQuery, RayQueryHit};
 
 pub(crate) use self::buffers::*;
 pub(crate) use self::bvh::*;
+pub use self::bvh::{BvhConfig, BvhQuantization, BvhStats, BvhTreeStats};
 pub use self::camera::*;
 pub(crate) use self::camera_controller::*;
 pub(crate) use self::camera_controllers::*;
+pub use self::camera_image::*;
+pub(crate) use self::cpu_tracer::*;
+pub use self::error::*;
 pub use self::image::*;
 pub(crate) use self::images::*;
 pub use self::instance::*;
 pub(crate) use self::instances::*;
 pub use self::light::*;
+pub(crate) use self::light_tree::*;
 pub(crate) use self::lights::*;
 pub use self::material::*;
 pub(crate) use self::materials::*;
@@ -96,6 +112,9 @@ pub use self::mesh::*;
 pub use self::mesh_triangle::*;
 pub(crate) use self::meshes::*;
 pub(crate) use self::noise::*;
+pub(crate) use self::ray_queries::*;
+pub use self::raycast::*;
+pub use self::scene::*;
 pub(crate) use self::shaders::*;
 pub use self::sun::*;
 pub(crate) use self::triangle::*;
@@ -118,6 +137,7 @@ where
     materials: Materials<P>,
     world: MappedUniformBuffer<gpu::World>,
     cameras: CameraControllers,
+    ray_queries: Option<RayQueriesPass>,
     sun: Sun,
     frame: u32,
     has_dirty_materials: bool,
@@ -149,6 +169,7 @@ where
                 Default::default(),
             ),
             cameras: Default::default(),
+            ray_queries: None,
             sun: Default::default(),
             frame: 0,
             has_dirty_materials: false,
@@ -159,10 +180,26 @@ where
     }
 
     /// Creates or updates a mesh.
+    ///
+    /// Invalid meshes (e.g. empty ones) are accepted, but instances that refer
+    /// to them don't get rendered; see: [`Self::try_insert_mesh()`].
     pub fn insert_mesh(&mut self, mesh_handle: P::MeshHandle, mesh: Mesh) {
         self.meshes.insert(mesh_handle, mesh);
     }
 
+    /// Creates or updates a mesh, returning an error if the mesh cannot be
+    /// rendered (e.g. because it contains no triangles).
+    pub fn try_insert_mesh(
+        &mut self,
+        mesh_handle: P::MeshHandle,
+        mesh: Mesh,
+    ) -> Result<(), StrolleError> {
+        mesh.validate()?;
+        self.meshes.insert(mesh_handle, mesh);
+
+        Ok(())
+    }
+
     /// Removes a mesh.
     ///
     /// Note that removing a mesh doesn't automatically remove instances that
@@ -215,6 +252,9 @@ where
     }
 
     /// Creates or updates an instance.
+    ///
+    /// Invalid instances are accepted, but don't get rendered; see:
+    /// [`Self::try_insert_instance()`].
     pub fn insert_instance(
         &mut self,
         instance_handle: P::InstanceHandle,
@@ -223,10 +263,31 @@ where
         self.instances.insert(instance_handle, instance);
     }
 
+    /// Creates or updates an instance, returning an error if the instance
+    /// cannot be rendered (e.g. because its transform is not invertible or it
+    /// refers to an invalid mesh).
+    ///
+    /// Note that instances referring to meshes that haven't been inserted yet
+    /// are accepted, since those meshes might be still loading.
+    pub fn try_insert_instance(
+        &mut self,
+        instance_handle: P::InstanceHandle,
+        instance: Instance<P>,
+    ) -> Result<(), StrolleError> {
+        instance.validate()?;
+
+        if let Some(mesh) = self.meshes.get(&instance.mesh_handle) {
+            mesh.validate()?;
+        }
+
+        self.instances.insert(instance_handle, instance);
+
+        Ok(())
+    }
+
     /// Removes an instance.
     pub fn remove_instance(&mut self, instance_handle: &P::InstanceHandle) {
         self.instances.remove(instance_handle);
-        self.triangles.remove(&mut self.bvh, instance_handle);
     }
 
     /// Creates or updates a light.
@@ -245,6 +306,183 @@ where
         self.has_dirty_sun = true;
     }
 
+    /// Rebuilds the BVH from scratch (its top-level part gets rebuilt during
+    /// the next [`Self::tick()`]).
+    ///
+    /// When meshes get deformed or instances get moved, the BVH gets refitted
+    /// instead of being rebuilt - this is much faster, but the BVH's quality
+    /// (and so the ray-tracing performance) degrades over time; the engine
+    /// rebuilds the BVH automatically when it degrades too much, but you can
+    /// also call this function, say, after teleporting lots of instances at
+    /// once.
+    pub fn rebuild_bvh(&mut self) {
+        self.triangles.rebuild(&mut self.bvh);
+        self.bvh.rebuild();
+        self.instances.invalidate();
+    }
+
+    /// Changes how the BVH gets built, e.g. to trade build times for better
+    /// ray-tracing performance in static scenes; rebuilds the BVH.
+    ///
+    /// Invalid configurations are corrected (and logged); see:
+    /// [`Self::try_set_bvh_config()`].
+    pub fn set_bvh_config(&mut self, config: BvhConfig) {
+        self.bvh.set_config(config);
+        self.rebuild_bvh();
+    }
+
+    /// Changes how the BVH gets built, returning an error if the
+    /// configuration is invalid (e.g. because it uses too few bins).
+    pub fn try_set_bvh_config(
+        &mut self,
+        config: BvhConfig,
+    ) -> Result<(), StrolleError> {
+        config.validate()?;
+        self.set_bvh_config(config);
+
+        Ok(())
+    }
+
+    /// Returns quality statistics of the BVH, as of the last [`Self::tick()`];
+    /// useful to tell whether a slow frame is caused by a poor tree.
+    pub fn bvh_stats(&self) -> BvhStats {
+        self.bvh.stats()
+    }
+
+    /// Writes bounding boxes of BVH nodes, as of the last [`Self::tick()`],
+    /// into an OBJ file that can be inspected in external tools.
+    ///
+    /// Boxes of the top-level BVH are exported in the world-space, while boxes
+    /// of meshes' bottom-level BVHs are exported in the object-space.
+    pub fn export_bvh(&self, writer: impl io::Write) -> io::Result<()> {
+        self.bvh.export_obj(writer)
+    }
+
+    /// Enables (or, if `dir` is `None`, disables) the on-disk cache of meshes'
+    /// BVHs.
+    ///
+    /// When enabled, BVHs of newly uploaded meshes are loaded from given
+    /// directory (if they've been built there before) instead of being built
+    /// from scratch, which makes loading large static scenes much faster;
+    /// cache files are keyed by meshes' contents, so they never get stale.
+    ///
+    /// Affects only meshes added after calling this function (or all meshes,
+    /// after calling [`Self::rebuild_bvh()`]).
+    pub fn set_bvh_cache_dir(&mut self, dir: Option<PathBuf>) {
+        self.bvh.set_cache(dir.map(BvhCache::new));
+    }
+
+    /// Exports meshes, materials, images, instances, lights and the sun into a
+    /// scene that can be then saved into a file with [`Scene::write()`].
+    ///
+    /// Since the engine doesn't keep images in the CPU memory, images are
+    /// exported through [`SceneMapping::image_source()`].
+    pub fn export_scene(&self, mapping: &mut impl SceneMapping<P>) -> Scene {
+        let mut scene = Scene {
+            sun: self.sun,
+            ..Default::default()
+        };
+
+        for image_handle in self.images.handles() {
+            scene.images.insert(
+                mapping.image_key(image_handle),
+                mapping.image_source(image_handle),
+            );
+        }
+
+        for (mesh_handle, mesh) in self.meshes.iter() {
+            scene
+                .meshes
+                .insert(mapping.mesh_key(mesh_handle), mesh.clone());
+        }
+
+        for (material_handle, material) in self.materials.iter() {
+            scene.materials.insert(
+                mapping.material_key(material_handle),
+                scene::map_material(material, |image_handle| {
+                    mapping.image_key(image_handle)
+                }),
+            );
+        }
+
+        for (instance_handle, entry) in self.instances.iter() {
+            scene.instances.insert(
+                mapping.instance_key(instance_handle),
+                Instance::new(
+                    mapping.mesh_key(&entry.instance.mesh_handle),
+                    mapping.material_key(&entry.instance.material_handle),
+                    entry.instance.transform,
+                )
+                .with_visibility_mask(entry.instance.visibility_mask)
+                .with_ray_visibility(entry.instance.ray_visibility),
+            );
+        }
+
+        for (light_handle, light) in self.lights.iter() {
+            scene
+                .lights
+                .insert(mapping.light_key(light_handle), light.clone());
+        }
+
+        scene
+    }
+
+    /// Imports given scene, creating (or updating) its meshes, materials,
+    /// images, instances and lights and updating the sun.
+    ///
+    /// Objects that already exist in the engine, but are not a part of the
+    /// scene, are left intact.
+    ///
+    /// Returns an error if any of the scene's images couldn't be loaded, in
+    /// which case nothing gets imported.
+    pub fn import_scene(
+        &mut self,
+        mapping: &mut impl SceneMapping<P>,
+        scene: Scene,
+    ) -> io::Result<()> {
+        let images = scene
+            .images
+            .iter()
+            .map(|(key, image)| Ok((mapping.image_handle(key), image.load()?)))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        for (image_handle, image) in images {
+            self.insert_image(image_handle, image);
+        }
+
+        for (key, mesh) in scene.meshes {
+            self.insert_mesh(mapping.mesh_handle(&key), mesh);
+        }
+
+        for (key, material) in scene.materials {
+            let material = scene::map_material(&material, |image_key| {
+                mapping.image_handle(image_key)
+            });
+
+            self.insert_material(mapping.material_handle(&key), material);
+        }
+
+        for (key, instance) in scene.instances {
+            let instance = Instance::new(
+                mapping.mesh_handle(&instance.mesh_handle),
+                mapping.material_handle(&instance.material_handle),
+                instance.transform,
+            )
+            .with_visibility_mask(instance.visibility_mask)
+            .with_ray_visibility(instance.ray_visibility);
+
+            self.insert_instance(mapping.instance_handle(&key), instance);
+        }
+
+        for (key, light) in scene.lights {
+            self.insert_light(mapping.light_handle(&key), light);
+        }
+
+        self.update_sun(scene.sun);
+
+        Ok(())
+    }
+
     /// Creates a new camera that can be used to render the world.
     ///
     /// Note that this is a pretty heavy operation that allocates per-camera
@@ -260,30 +498,276 @@ where
     }
 
     /// Updates camera, changing its mode, position, size etc.
+    ///
+    /// Panics if the camera doesn't exist; see: [`Self::try_update_camera()`].
     pub fn update_camera(
         &mut self,
         device: &wgpu::Device,
         handle: CameraHandle,
         camera: Camera,
     ) {
+        self.try_update_camera(device, handle, camera)
+            .unwrap_or_else(|err| panic!("{err}"));
+    }
+
+    /// Updates camera, changing its mode, position, size etc.
+    pub fn try_update_camera(
+        &mut self,
+        device: &wgpu::Device,
+        handle: CameraHandle,
+        camera: Camera,
+    ) -> Result<(), StrolleError> {
         let mut cameras = mem::take(&mut self.cameras);
 
-        cameras.get_mut(handle).update(self, device, camera);
+        let result = cameras
+            .get_mut(handle)
+            .map(|cam| cam.update(self, device, camera));
 
         self.cameras = cameras;
+
+        result
     }
 
     /// Renders camera to texture.
     ///
     /// Note that `view`'s texture format must be the same as the format given
     /// to [`Self::create_camera()`].
+    ///
+    /// Panics if the camera doesn't exist; see: [`Self::try_render_camera()`].
     pub fn render_camera(
         &self,
         handle: CameraHandle,
         encoder: &mut wgpu::CommandEncoder,
         view: &wgpu::TextureView,
     ) {
-        self.cameras.get(handle).render(self, encoder, view);
+        self.try_render_camera(handle, encoder, view)
+            .unwrap_or_else(|err| panic!("{err}"));
+    }
+
+    /// Renders camera to texture.
+    ///
+    /// Note that `view`'s texture format must be the same as the format given
+    /// to [`Self::create_camera()`].
+    pub fn try_render_camera(
+        &self,
+        handle: CameraHandle,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+    ) -> Result<(), StrolleError> {
+        self.cameras.get(handle)?.render(self, encoder, view);
+
+        Ok(())
+    }
+
+    /// Renders camera into a newly-allocated texture and reads it back into
+    /// the CPU memory, e.g. for offline renders or image-based tests.
+    ///
+    /// This function blocks until the GPU is done rendering. Similarly as
+    /// with [`Self::render_camera()`], you should call [`Self::tick()`]
+    /// beforehand - and since most of the camera modes accumulate samples
+    /// across frames, you might want to render a few frames first to get a
+    /// converged image.
+    ///
+    /// The returned image covers only the camera's viewport. Supported
+    /// viewport formats are `Rgba8Unorm`, `Rgba8UnormSrgb`, `Bgra8Unorm`,
+    /// `Bgra8UnormSrgb` and `Rgba32Float`.
+    ///
+    /// Panics if the camera doesn't exist or if its format is not supported;
+    /// see: [`Self::try_render_camera_to_image()`].
+    pub fn render_camera_to_image(
+        &self,
+        handle: CameraHandle,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> CameraImage {
+        self.try_render_camera_to_image(handle, device, queue)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Renders camera into a newly-allocated texture and reads it back into
+    /// the CPU memory; see: [`Self::render_camera_to_image()`].
+    pub fn try_render_camera_to_image(
+        &self,
+        handle: CameraHandle,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<CameraImage, StrolleError> {
+        self.cameras
+            .get(handle)?
+            .render_to_image(self, device, queue)
+    }
+
+    /// Traces a batch of rays on the GPU, e.g. for visibility or occlusion
+    /// queries.
+    ///
+    /// `rays` must be a storage buffer containing `count` queries, each
+    /// encoded through [`RayQuery::pack()`]; for each query, a hit encoded
+    /// through [`RayQueryHit::pack()`] gets written into `hits`, at the same
+    /// index. `rays` must be at least `count * 32` bytes long and `hits` must
+    /// be at least `count * 48` bytes long - to read the hits back, copy them
+    /// into a mappable buffer after submitting the encoder.
+    ///
+    /// Rays' directions must be normalized.
+    ///
+    /// To find out what's been hit, see: [`Self::resolve_ray_query_hit()`].
+    ///
+    /// Similarly as with [`Self::render_camera()`], you should call
+    /// [`Self::tick()`] beforehand.
+    pub fn trace_rays(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        rays: &wgpu::Buffer,
+        hits: &wgpu::Buffer,
+        count: u32,
+    ) {
+        if self.ray_queries.is_none() {
+            self.ray_queries = Some(RayQueriesPass::new(self, device));
+        }
+
+        self.ray_queries
+            .as_ref()
+            .unwrap()
+            .run(device, encoder, rays, hits, count);
+    }
+
+    /// Returns the instance hit by a ray query, together with the index of
+    /// the triangle that's been hit (within the instance's mesh, i.e. an index
+    /// into the triangles given to [`Mesh::new()`]); see:
+    /// [`Self::trace_rays()`].
+    ///
+    /// Hits refer to the world as of the last [`Self::tick()`], so they should
+    /// be resolved before the next one; returns `None` if the ray didn't hit
+    /// anything or if the instance has been removed or modified since.
+    pub fn resolve_ray_query_hit(
+        &self,
+        hit: &RayQueryHit,
+    ) -> Option<(&P::InstanceHandle, usize)> {
+        if hit.is_none() {
+            return None;
+        }
+
+        let (instance_handle, _, triangle) =
+            self.resolve_hit(hit.instance_idx as usize, hit.triangle_id)?;
+
+        Some((instance_handle, triangle))
+    }
+
+    /// Returns the closest instance hit by given ray, if any.
+    ///
+    /// This function traverses the same BVH that's used for rendering, so it
+    /// reflects the state of the world as of the last [`Self::tick()`] - e.g.
+    /// instances created after that are not going to be hit yet.
+    ///
+    /// Textures are not taken into account, so alpha-blended materials are
+    /// tested using just their base color's alpha.
+    pub fn raycast(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        max_distance: f32,
+    ) -> Option<RaycastHit<P>> {
+        let tracer = CpuTracer {
+            triangles: self.triangles.as_slice(),
+            bvh: self.bvh.as_slice(),
+            materials: self.materials.as_slice(),
+            lights: self.lights.as_slice(),
+            world: *self.world,
+        };
+
+        let ray = gpu::Ray::new(origin, direction.normalize())
+            .with_length(max_distance);
+
+        let CpuHit {
+            instance_idx,
+            triangle_id,
+            hit,
+        } = tracer.trace(ray)?;
+
+        let (instance_handle, instance, triangle) =
+            self.resolve_hit(instance_idx, triangle_id)?;
+
+        // Triangles are kept in the object-space, so that's where we have to
+        // compute the barycentrics as well
+        let positions =
+            self.triangles.as_slice()[triangle_id.get() as usize].positions();
+
+        let barycentrics = raycast::barycentrics(
+            positions,
+            instance.transform_inverse.transform_point3(hit.point),
+        );
+
+        Some(RaycastHit {
+            instance_handle: instance_handle.clone(),
+            material_handle: instance.material_handle.clone(),
+            triangle,
+            distance: hit.distance,
+            point: hit.point,
+            normal: hit.normal,
+            barycentrics,
+            uv: hit.uv,
+        })
+    }
+
+    /// Maps instance's index within the top-level BVH and a triangle id back
+    /// to the instance and the index of the triangle within its mesh.
+    fn resolve_hit(
+        &self,
+        instance_idx: usize,
+        triangle_id: gpu::TriangleId,
+    ) -> Option<(&P::InstanceHandle, &Instance<P>, usize)> {
+        let (instance_handle, instance) =
+            self.instances.get_by_bvh_index(instance_idx)?;
+
+        // (the instance might've been modified since the last tick, hence the
+        // checks)
+        let triangle_ids =
+            self.triangles.triangle_ids(&instance.mesh_handle)?;
+        let triangle_id = triangle_id.get() as usize;
+
+        if !triangle_ids.contains(&triangle_id) {
+            return None;
+        }
+
+        Some((instance_handle, instance, triangle_id - triangle_ids.start))
+    }
+
+    /// Renders given camera on the CPU, using the same algorithm as
+    /// [`CameraMode::Reference`] does on the GPU; useful for generating
+    /// ground-truth images of the lighting and testing the shading code on
+    /// machines without a GPU.
+    ///
+    /// Each pixel accumulates `samples` paths, using deterministic noise - so
+    /// the same world rendered twice yields the same image.
+    ///
+    /// Since textures and the atmosphere live only in the VRAM, the result
+    /// matches the GPU's reference image only for untextured worlds without a
+    /// visible sky:
+    ///
+    /// - materials are evaluated using just their factors (base color,
+    ///   emissive etc.),
+    ///
+    /// - the sky is not evaluated (rays that miss the world contribute black),
+    ///   but the sun still lights the world.
+    ///
+    /// Note that this function uses the state of the world as of the last
+    /// [`Self::tick()`] (i.e. you have to tick the engine at least once to get
+    /// anything rendered).
+    ///
+    /// Returns an error if camera's mode is not [`CameraMode::Reference`].
+    pub fn render_camera_on_cpu(
+        &self,
+        camera: &Camera,
+        samples: u32,
+    ) -> Result<CameraImage, StrolleError> {
+        CpuTracer {
+            triangles: self.triangles.as_slice(),
+            bvh: self.bvh.as_slice(),
+            materials: self.materials.as_slice(),
+            lights: self.lights.as_slice(),
+            world: *self.world,
+        }
+        .render(camera, samples)
     }
 
     /// Deletes a camera.
@@ -320,6 +804,14 @@ where
 
         // ---
 
+        let any_mesh_changed = utils::measure("tick.meshes", || {
+            self.meshes.refresh(&mut self.triangles, &mut self.bvh)
+        });
+
+        if any_mesh_changed || any_material_modified {
+            self.instances.invalidate();
+        }
+
         let any_instance_changed = utils::measure("tick.instances", || {
             self.instances.refresh(
                 &self.meshes,
@@ -329,9 +821,15 @@ where
             )
         });
 
-        if any_instance_changed {
-            utils::measure("tick.bvh", || {
-                self.bvh.refresh(&self.materials);
+        // Images matter as well, since materials with emissive textures don't
+        // count as triangle lights
+        if any_instance_changed || any_image_modified {
+            utils::measure("tick.triangle_lights", || {
+                self.lights.refresh_triangle_lights(
+                    &self.instances,
+                    &self.triangles,
+                    &self.materials,
+                );
             });
         }
 
@@ -341,6 +839,7 @@ where
             light_count: self.lights.len(),
             sun_azimuth: self.sun.azimuth,
             sun_altitude: self.sun.altitude,
+            triangle_light_count: self.lights.triangle_light_count(),
         };
 
         utils::measure("tick.world", || {
@@ -348,9 +847,11 @@ where
         });
 
         if mem::take(&mut self.has_dirty_sun) {
-            self.lights.update_sun(*self.world);
+            self.lights.update_sun(*self.world, self.sun.angular_size);
         }
 
+        let any_bvh_modified = self.bvh.is_dirty();
+
         let any_buffer_reallocated = utils::measure("tick.buffers", || {
             false
                 | self.bvh.flush(device, queue).reallocated
@@ -369,6 +870,7 @@ where
             }
 
             self.cameras = cameras;
+            self.ray_queries = None;
         }
 
         utils::measure("tick.cameras", || {
@@ -392,6 +894,12 @@ where
                 self.materials.len(),
                 self.lights.len(),
             );
+
+            // Gathering statistics requires walking through all of the trees,
+            // so let's do it only when something's actually changed
+            if any_bvh_modified {
+                trace!("bvh: {}", self.bvh.stats());
+            }
         }
     }
 }
@@ -1,8 +1,8 @@
-use glam::{vec4, Vec3};
+use glam::{vec2, vec4, Vec3};
 
 use crate::gpu;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Light {
     Point {
         position: Vec3,
@@ -19,6 +19,30 @@ pub enum Light {
         direction: Vec3,
         angle: f32,
     },
+
+    /// Rectangular area light that emits light only towards the side its
+    /// `normal` points to.
+    Rect {
+        position: Vec3,
+        color: Vec3,
+        normal: Vec3,
+
+        /// Direction of the rect's width; doesn't have to be perpendicular to
+        /// `normal`, it gets orthogonalized.
+        tangent: Vec3,
+
+        width: f32,
+        height: f32,
+    },
+
+    /// Disk-shaped area light that emits light only towards the side its
+    /// `normal` points to.
+    Disk {
+        position: Vec3,
+        color: Vec3,
+        normal: Vec3,
+        radius: f32,
+    },
 }
 
 impl Light {
@@ -38,6 +62,7 @@ impl Light {
                     Default::default(),
                     Default::default(),
                 ),
+                d3: Default::default(),
             },
 
             Light::Spot {
@@ -59,6 +84,56 @@ impl Light {
                         direction.y,
                         *angle,
                     ),
+                    d3: Default::default(),
+                }
+            }
+
+            Light::Rect {
+                position,
+                color,
+                normal,
+                tangent,
+                width,
+                height,
+            } => {
+                let normal = normal.normalize();
+                let tangent =
+                    (*tangent - normal * normal.dot(*tangent)).normalize();
+
+                let radius = 0.5 * vec2(*width, *height).length();
+                let encoded_normal = gpu::Normal::encode(normal);
+
+                gpu::Light {
+                    d0: position.extend(radius),
+                    d1: color.extend(f32::INFINITY),
+                    d2: vec4(
+                        f32::from_bits(gpu::Light::TYPE_RECT),
+                        encoded_normal.x,
+                        encoded_normal.y,
+                        0.5 * height,
+                    ),
+                    d3: tangent.extend(0.5 * width),
+                }
+            }
+
+            Light::Disk {
+                position,
+                color,
+                normal,
+                radius,
+            } => {
+                let normal = gpu::Normal::encode(normal.normalize());
+
+                gpu::Light {
+                    d0: position.extend(*radius),
+                    d1: color.extend(f32::INFINITY),
+                    d2: vec4(
+                        f32::from_bits(gpu::Light::TYPE_DISK),
+                        normal.x,
+                        normal.y,
+                        Default::default(),
+                    ),
+                    d3: Default::default(),
                 }
             }
         }
@@ -0,0 +1,179 @@
+use glam::Vec4;
+
+use crate::{
+    gpu, Bindable, BoundingBox, BufferFlushOutcome, MappedStorageBuffer,
+};
+
+/// Bounding volume hierarchy built over lights, which allows for shaders to
+/// pick lights proportionally to their (estimated) contribution without going
+/// through all of them; see: [`gpu::LightTreeView`].
+#[derive(Debug)]
+pub struct LightTree {
+    buffer: MappedStorageBuffer<Vec<Vec4>>,
+}
+
+impl LightTree {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            buffer: MappedStorageBuffer::new_default(device, "light_tree"),
+        }
+    }
+
+    /// Rebuilds the tree from scratch; `first_light_id` is the id of the first
+    /// light in given slice.
+    pub fn rebuild(&mut self, lights: &[gpu::Light], first_light_id: u32) {
+        *self.buffer = Self::build(lights, first_light_id);
+    }
+
+    pub fn flush(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> BufferFlushOutcome {
+        self.buffer.flush(device, queue)
+    }
+
+    pub fn bind_readable(&self) -> impl Bindable + '_ {
+        self.buffer.bind_readable()
+    }
+
+    fn build(lights: &[gpu::Light], first_light_id: u32) -> Vec<Vec4> {
+        let mut items: Vec<_> = lights
+            .iter()
+            .zip(first_light_id..)
+            .map(|(light, light_id)| {
+                let radius = light.radius();
+
+                LightTreeItem {
+                    light_id,
+                    bounds: BoundingBox::new(
+                        light.center() - radius,
+                        light.center() + radius,
+                    ),
+                    power: light.power(),
+                }
+            })
+            .collect();
+
+        let mut nodes = Vec::new();
+
+        if !items.is_empty() {
+            Self::build_node(&mut nodes, &mut items);
+        }
+
+        nodes
+    }
+
+    /// Builds node for given lights (and, recursively, its children),
+    /// splitting lights at the median of their centers along the longest
+    /// axis.
+    fn build_node(nodes: &mut Vec<Vec4>, items: &mut [LightTreeItem]) {
+        let bounds: BoundingBox =
+            items.iter().map(|item| item.bounds).collect();
+        let power = items.iter().map(|item| item.power).sum::<f32>();
+        let node_idx = nodes.len();
+
+        nodes.push(bounds.min().extend(power));
+        nodes.push(bounds.max().extend(Default::default()));
+
+        if let [item] = items {
+            nodes[node_idx + 1].w =
+                f32::from_bits(item.light_id | gpu::LightTreeView::LEAF_BIT);
+
+            return;
+        }
+
+        let axis = {
+            let centers: BoundingBox =
+                items.iter().map(|item| item.bounds.center()).collect();
+
+            let extent = centers.extent();
+
+            if extent.x >= extent.y && extent.x >= extent.z {
+                0
+            } else if extent.y >= extent.z {
+                1
+            } else {
+                2
+            }
+        };
+
+        items.sort_unstable_by(|a, b| {
+            a.bounds.center()[axis].total_cmp(&b.bounds.center()[axis])
+        });
+
+        let (left, right) = items.split_at_mut(items.len() / 2);
+
+        Self::build_node(nodes, left);
+
+        nodes[node_idx + 1].w = f32::from_bits((nodes.len() / 2) as u32);
+
+        Self::build_node(nodes, right);
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct LightTreeItem {
+    light_id: u32,
+    bounds: BoundingBox,
+    power: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec3, Vec3};
+
+    use super::*;
+    use crate::Light;
+
+    #[test]
+    fn sample() {
+        let lights: Vec<_> = (0..13)
+            .map(|idx| {
+                Light::Point {
+                    position: vec3(idx as f32, 1.0, (idx % 3) as f32),
+                    radius: 0.1,
+                    color: Vec3::splat(1.0 + idx as f32),
+                    range: 10.0,
+                }
+                .serialize()
+            })
+            .collect();
+
+        let nodes = LightTree::build(&lights, 1);
+        let target = gpu::LightTreeView::new(&nodes);
+
+        // Stochastic traversal should pick every light with probability that
+        // it reports
+        let mut picks = vec![0; lights.len()];
+        let mut pdfs = vec![0.0; lights.len()];
+        let samples = 100_000;
+
+        for idx in 0..samples {
+            let (light_id, pdf) = target.sample(
+                vec3(2.5, 0.0, 1.0),
+                Vec3::Y,
+                (idx as f32 + 0.5) / (samples as f32),
+            );
+
+            let idx = (light_id.get() - 1) as usize;
+
+            picks[idx] += 1;
+            pdfs[idx] = pdf;
+        }
+
+        for (picks, pdf) in picks.into_iter().zip(pdfs) {
+            assert!(picks > 0);
+
+            let actual = (picks as f32) / (samples as f32);
+
+            assert!((actual - pdf).abs() < 0.001, "{actual} != {pdf}");
+        }
+
+        // Lights that are closer should be picked more often
+        let (_, near) = target.sample(vec3(0.0, 0.0, 0.0), Vec3::Y, 0.0);
+        let (_, far) = target.sample(vec3(0.0, 0.0, 0.0), Vec3::Y, 0.999);
+
+        assert!(near > far, "{near} <= {far}");
+    }
+}
@@ -1,9 +1,16 @@
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::mem;
+use std::ops::Range;
 
+use glam::{Affine3A, Vec3, Vec4, Vec4Swizzles};
+
+use crate::gpu::Vec3Ext;
+use crate::utils::Allocator;
 use crate::{
-    gpu, Bindable, BufferFlushOutcome, Light, MappedStorageBuffer, Params,
+    gpu, Bindable, BufferFlushOutcome, Instance, Instances, Light, LightTree,
+    MappedStorageBuffer, Materials, Params, Triangles,
 };
 
 #[derive(Debug)]
@@ -12,7 +19,25 @@ where
     P: Params,
 {
     buffer: MappedStorageBuffer<Vec<gpu::Light>>,
-    index: HashMap<P::LightHandle, gpu::LightId>,
+    index: HashMap<P::LightHandle, (gpu::LightId, Light)>,
+
+    /// Number of triangle lights, stored right after the regular lights; see:
+    /// [`Self::refresh_triangle_lights()`].
+    triangle_lights: usize,
+
+    /// Instances with emissive materials, each owning a range of slots in
+    /// [`Self::emitter_lights`].
+    emitters: HashMap<P::InstanceHandle, Emitter>,
+
+    /// Slots released by emitters that have been removed or modified.
+    emitter_slots: Allocator,
+
+    /// Triangle lights, before computing their cumulative probabilities.
+    emitter_lights: Vec<TriangleLight>,
+
+    /// Tree built over regular lights (except for the sun).
+    tree: LightTree,
+    has_dirty_tree: bool,
 }
 
 impl<P> Lights<P>
@@ -25,48 +50,66 @@ where
             "stolle_lights",
         );
 
-        buffer.push(gpu::Light::sun(Default::default(), Default::default()));
+        buffer.push(gpu::Light::sun(Vec3::Y, 0.0, Vec3::ZERO));
 
         Self {
             buffer,
             index: Default::default(),
+            triangle_lights: 0,
+            emitters: Default::default(),
+            emitter_slots: Default::default(),
+            emitter_lights: Default::default(),
+            tree: LightTree::new(device),
+            has_dirty_tree: false,
         }
     }
 
     pub fn insert(&mut self, light_handle: P::LightHandle, light: Light) {
-        let light = light.serialize();
+        let gpu_light = light.serialize();
+        let len = self.len();
 
         match self.index.entry(light_handle) {
-            Entry::Occupied(entry) => {
-                let light_id = *entry.get();
+            Entry::Occupied(mut entry) => {
+                let (light_id, prev_light) = entry.get_mut();
 
-                self.buffer[light_id.get() as usize] = light;
+                self.buffer[light_id.get() as usize] = gpu_light;
+                *prev_light = light;
             }
 
             Entry::Vacant(entry) => {
-                let light_id = gpu::LightId::new(self.buffer.len() as u32);
+                // Regular lights go before triangle lights
+                let light_id = gpu::LightId::new(len);
 
-                self.buffer.push(light);
-                entry.insert(light_id);
+                self.buffer.insert(light_id.get() as usize, gpu_light);
+                entry.insert((light_id, light));
             }
         }
+
+        self.has_dirty_tree = true;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&P::LightHandle, &Light)> + '_ {
+        self.index
+            .iter()
+            .map(|(light_handle, (_, light))| (light_handle, light))
     }
 
     pub fn remove(&mut self, light_handle: &P::LightHandle) {
-        let Some(light_id) = self.index.remove(light_handle) else {
+        let Some((light_id, _)) = self.index.remove(light_handle) else {
             return;
         };
 
         self.buffer.remove(light_id.get() as usize);
+        self.has_dirty_tree = true;
 
-        for light_id2 in self.index.values_mut() {
+        for (light_id2, _) in self.index.values_mut() {
             if light_id2.get() > light_id.get() {
                 *light_id2.get_mut() -= 1;
             }
         }
     }
 
-    pub fn update_sun(&mut self, world: gpu::World) {
+    pub fn update_sun(&mut self, world: gpu::World, angular_size: f32) {
         let sun_color =
             strolle_shaders::atmosphere::generate_transmittance_lut::eval(
                 gpu::Atmosphere::VIEW_POS,
@@ -75,11 +118,173 @@ where
 
         let sun_color = sun_color * gpu::Atmosphere::EXPOSURE * 0.5;
 
-        self.buffer[0] = gpu::Light::sun(world.sun_position(), sun_color);
+        self.buffer[0] =
+            gpu::Light::sun(world.sun_direction(), angular_size, sun_color);
+    }
+
+    /// Collects emissive triangles of all instances into triangle lights, so
+    /// that they can be sampled directly, just like regular lights.
+    ///
+    /// Triangle lights are stored in world-space, so this function has to be
+    /// called whenever instances, their meshes or materials change - only
+    /// instances that have actually changed get their triangles transformed
+    /// again, though.
+    ///
+    /// Each emissive instance keeps its triangle lights at the same ids for as
+    /// long as it doesn't change, so that reservoirs from the previous frame
+    /// keep pointing at the same lights (with the exception of inserting or
+    /// removing regular lights, which shifts all triangle lights).
+    pub fn refresh_triangle_lights(
+        &mut self,
+        instances: &Instances<P>,
+        triangles: &Triangles<P>,
+        materials: &Materials<P>,
+    ) {
+        let mut changed = false;
+
+        // Release emitters whose instances have been removed or modified
+        self.emitters.retain(|instance_handle, emitter| {
+            let key = instances.get(instance_handle).and_then(|instance| {
+                EmitterKey::new(instance, triangles, materials)
+            });
+
+            if key == Some(emitter.key) {
+                return true;
+            }
+
+            for light in &mut self.emitter_lights[emitter.slots.clone()] {
+                *light = TriangleLight::default();
+            }
+
+            self.emitter_slots.give(emitter.slots.clone());
+
+            changed = true;
+            false
+        });
+
+        // Create emitters for instances that have been added or modified
+        for (instance_handle, entry) in instances.iter() {
+            if self.emitters.contains_key(instance_handle) {
+                continue;
+            }
+
+            let instance = &entry.instance;
+
+            let Some(key) = EmitterKey::new(instance, triangles, materials)
+            else {
+                continue;
+            };
+
+            let Some(triangle_ids) =
+                triangles.triangle_ids(&instance.mesh_handle)
+            else {
+                continue;
+            };
+
+            let slots = self
+                .emitter_slots
+                .take(triangle_ids.len())
+                .unwrap_or_else(|| {
+                    let start = self.emitter_lights.len();
+                    let end = start + triangle_ids.len();
+
+                    self.emitter_lights.resize(end, TriangleLight::default());
+
+                    start..end
+                });
+
+            let color = key.emissive.xyz();
+
+            for (triangle, light) in triangles.as_slice()[triangle_ids]
+                .iter()
+                .zip(&mut self.emitter_lights[slots.clone()])
+            {
+                let positions = triangle.positions().map(|position| {
+                    instance.transform.transform_point3(position)
+                });
+
+                let area = 0.5
+                    * (positions[1] - positions[0])
+                        .cross(positions[2] - positions[0])
+                        .length();
+
+                *light = TriangleLight {
+                    positions,
+                    color,
+                    power: color.luma() * area,
+                    visibility_mask: key.visibility_mask,
+                };
+            }
+
+            self.emitters
+                .insert(instance_handle.clone(), Emitter { key, slots });
+
+            changed = true;
+        }
+
+        if self.emitters.is_empty() {
+            self.emitter_slots = Default::default();
+            self.emitter_lights.clear();
+        }
+
+        if changed {
+            self.upload_triangle_lights();
+        }
     }
 
+    fn upload_triangle_lights(&mut self) {
+        let len = self.len() as usize;
+
+        self.buffer.truncate(len);
+        self.triangle_lights = 0;
+
+        let total_power: f32 =
+            self.emitter_lights.iter().map(|light| light.power).sum();
+
+        // Released slots and degenerate triangles have zero power, so they
+        // never get picked, but they still keep their ids - this way ids of
+        // the other triangle lights stay the same
+        let Some(last_idx) = self
+            .emitter_lights
+            .iter()
+            .rposition(|light| light.power > 0.0)
+        else {
+            return;
+        };
+
+        let mut cdf = 0.0;
+
+        for (idx, light) in self.emitter_lights.iter().enumerate() {
+            cdf += light.power / total_power;
+
+            // Make sure the last light gets picked even if the cumulative
+            // probability doesn't quite add up to one due to rounding errors
+            if idx >= last_idx {
+                cdf = 1.0;
+            }
+
+            self.buffer.push(gpu::Light::triangle(
+                light.positions,
+                light.color,
+                cdf,
+                light.visibility_mask,
+            ));
+        }
+
+        self.triangle_lights = self.emitter_lights.len();
+    }
+
+    /// Returns the number of regular lights (including the sun).
     pub fn len(&self) -> u32 {
-        self.buffer.len() as u32
+        (self.buffer.len() - self.triangle_lights) as u32
+    }
+
+    pub fn triangle_light_count(&self) -> u32 {
+        self.triangle_lights as u32
+    }
+
+    pub fn as_slice(&self) -> &[gpu::Light] {
+        &self.buffer
     }
 
     pub fn flush(
@@ -87,10 +292,86 @@ where
         device: &wgpu::Device,
         queue: &wgpu::Queue,
     ) -> BufferFlushOutcome {
-        self.buffer.flush(device, queue)
+        if mem::take(&mut self.has_dirty_tree) {
+            // The sun is infinitely far away, so it doesn't fit into the tree
+            // - shaders always consider it separately
+            self.tree.rebuild(&self.buffer[1..(self.len() as usize)], 1);
+        }
+
+        let tree_reallocated = self.tree.flush(device, queue).reallocated;
+        let reallocated = self.buffer.flush(device, queue).reallocated;
+
+        BufferFlushOutcome {
+            reallocated: reallocated || tree_reallocated,
+        }
     }
 
     pub fn bind_readable(&self) -> impl Bindable + '_ {
         self.buffer.bind_readable()
     }
+
+    pub fn bind_tree(&self) -> impl Bindable + '_ {
+        self.tree.bind_readable()
+    }
+}
+
+/// Instance whose triangles are triangle lights; see:
+/// [`Lights::refresh_triangle_lights()`].
+#[derive(Debug)]
+struct Emitter {
+    key: EmitterKey,
+    slots: Range<usize>,
+}
+
+/// Everything triangle lights of an instance depend on - if any of these
+/// changes, the instance's triangle lights have to be generated again.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct EmitterKey {
+    mesh_version: u64,
+    transform: Affine3A,
+    emissive: Vec4,
+    visibility_mask: u32,
+}
+
+impl EmitterKey {
+    /// Returns `None` if given instance doesn't emit any light.
+    fn new<P>(
+        instance: &Instance<P>,
+        triangles: &Triangles<P>,
+        materials: &Materials<P>,
+    ) -> Option<Self>
+    where
+        P: Params,
+    {
+        // Triangle lights illuminate other surfaces, so instances that don't
+        // take part in indirect lighting don't emit any light
+        if !instance.ray_visibility.visible_in_indirect {
+            return None;
+        }
+
+        let material_id = materials.lookup(&instance.material_handle)?;
+
+        // Using the GPU-side material, since whether a material is a
+        // triangle light depends on whether its textures are loaded
+        let material = &materials.as_slice()[material_id.get() as usize];
+
+        if !material.is_triangle_light() {
+            return None;
+        }
+
+        Some(Self {
+            mesh_version: triangles.version(&instance.mesh_handle)?,
+            transform: instance.transform,
+            emissive: material.emissive,
+            visibility_mask: instance.visibility_mask,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct TriangleLight {
+    positions: [Vec3; 3],
+    color: Vec3,
+    power: f32,
+    visibility_mask: u32,
 }
@@ -0,0 +1,3 @@
+#[cfg(feature = "gltf")]
+mod gltf;
+mod obj;
@@ -0,0 +1,504 @@
+use std::io;
+use std::path::Path;
+
+use ::gltf::image::Format;
+use ::gltf::khr_lights_punctual::Kind;
+use ::gltf::mesh::Mode;
+use ::gltf::{buffer, image, Document, Node};
+use glam::{Affine3A, Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+use log::warn;
+
+use crate::{
+    AlphaMode, Instance, Light, Material, Mesh, MeshTriangle, Scene,
+    SceneImage, SceneParams,
+};
+
+/// Range used for lights that don't specify one; glTF treats such lights as
+/// having an infinite range, but our lights need some cutoff.
+const DEFAULT_LIGHT_RANGE: f32 = 20.0;
+
+impl Scene {
+    /// Loads a glTF 2.0 scene (either `.gltf` or `.glb`).
+    ///
+    /// Meshes, materials (metallic-roughness, emissive, normal map and alpha
+    /// mode), images, instances and `KHR_lights_punctual`'s point- and
+    /// spot-lights are supported; everything else (cameras, animations,
+    /// directional lights etc.) gets skipped.
+    ///
+    /// Since Strolle's instances consist of a single mesh and a single
+    /// material, each glTF primitive gets loaded as a separate mesh.
+    ///
+    /// Keys follow glTF's indices - e.g. `meshes/1/0` is the first primitive
+    /// of the second mesh and `instances/3/0` is its instance created by the
+    /// fourth node.
+    pub fn load_gltf(path: impl AsRef<Path>) -> io::Result<Self> {
+        let (document, buffers, images) =
+            ::gltf::import(path).map_err(into_io_error)?;
+
+        Self::from_gltf(&document, &buffers, &images)
+    }
+
+    /// Loads a glTF 2.0 scene from memory; buffers and images referenced by
+    /// relative paths are not supported here - use [`Self::load_gltf()`] for
+    /// those.
+    pub fn load_gltf_slice(slice: &[u8]) -> io::Result<Self> {
+        let (document, buffers, images) =
+            ::gltf::import_slice(slice).map_err(into_io_error)?;
+
+        Self::from_gltf(&document, &buffers, &images)
+    }
+
+    fn from_gltf(
+        document: &Document,
+        buffers: &[buffer::Data],
+        images: &[image::Data],
+    ) -> io::Result<Self> {
+        let mut scene = Scene::default();
+
+        for (image_idx, image) in images.iter().enumerate() {
+            scene
+                .images
+                .insert(format!("images/{}", image_idx), convert_image(image)?);
+        }
+
+        for material in document.materials() {
+            let Some(material_idx) = material.index() else {
+                continue;
+            };
+
+            scene.materials.insert(
+                format!("materials/{}", material_idx),
+                convert_material(&material),
+            );
+        }
+
+        for mesh in document.meshes() {
+            for primitive in mesh.primitives() {
+                let key =
+                    format!("meshes/{}/{}", mesh.index(), primitive.index());
+
+                if primitive.mode() != Mode::Triangles {
+                    warn!(
+                        "Skipping glTF primitive `{}`: unsupported mode {:?}",
+                        key,
+                        primitive.mode()
+                    );
+                    continue;
+                }
+
+                let reader = primitive.reader(|buffer| {
+                    buffers.get(buffer.index()).map(|data| &data.0[..])
+                });
+
+                let Some(positions) = reader.read_positions() else {
+                    warn!("Skipping glTF primitive `{}`: no positions", key);
+                    continue;
+                };
+
+                let positions: Vec<Vec3> = positions.map(Vec3::from).collect();
+
+                let normals: Vec<Vec3> = reader
+                    .read_normals()
+                    .map(|normals| normals.map(Vec3::from).collect())
+                    .unwrap_or_default();
+
+                let uvs: Vec<Vec2> = reader
+                    .read_tex_coords(0)
+                    .map(|uvs| uvs.into_f32().map(Vec2::from).collect())
+                    .unwrap_or_default();
+
+                let tangents: Vec<Vec4> = reader
+                    .read_tangents()
+                    .map(|tangents| tangents.map(Vec4::from).collect())
+                    .unwrap_or_default();
+
+                let indices: Vec<usize> =
+                    if let Some(indices) = reader.read_indices() {
+                        indices.into_u32().map(|idx| idx as usize).collect()
+                    } else {
+                        (0..positions.len()).collect()
+                    };
+
+                if indices.iter().any(|&idx| idx >= positions.len()) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("glTF primitive `{}` has invalid indices", key),
+                    ));
+                }
+
+                let triangles = indices
+                    .chunks_exact(3)
+                    .map(|vs| {
+                        let vs = [vs[0], vs[1], vs[2]];
+                        let positions = vs.map(|idx| positions[idx]);
+
+                        let normals = if normals.is_empty() {
+                            [flat_normal(positions); 3]
+                        } else {
+                            vs.map(|idx| normals[idx])
+                        };
+
+                        let uvs = vs.map(|idx| {
+                            uvs.get(idx).copied().unwrap_or_default()
+                        });
+
+                        let tangents = vs.map(|idx| {
+                            tangents.get(idx).copied().unwrap_or_default()
+                        });
+
+                        MeshTriangle::default()
+                            .with_positions(positions)
+                            .with_normals(normals)
+                            .with_uvs(uvs)
+                            .with_tangents(tangents)
+                    })
+                    .collect();
+
+                scene.meshes.insert(key, Mesh::new(triangles));
+            }
+        }
+
+        let nodes = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+            .into_iter()
+            .flat_map(|scene| scene.nodes());
+
+        for node in nodes {
+            load_node(&mut scene, node, Affine3A::IDENTITY);
+        }
+
+        Ok(scene)
+    }
+}
+
+fn load_node(scene: &mut Scene, node: Node, parent_xform: Affine3A) {
+    let xform = parent_xform
+        * Affine3A::from_mat4(Mat4::from_cols_array_2d(
+            &node.transform().matrix(),
+        ));
+
+    if let Some(mesh) = node.mesh() {
+        for primitive in mesh.primitives() {
+            let mesh_key =
+                format!("meshes/{}/{}", mesh.index(), primitive.index());
+
+            if !scene.meshes.contains_key(&mesh_key) {
+                continue;
+            }
+
+            let material = primitive.material();
+
+            let material_key = if let Some(material_idx) = material.index() {
+                format!("materials/{}", material_idx)
+            } else {
+                let key = "materials/default".to_string();
+
+                scene
+                    .materials
+                    .entry(key.clone())
+                    .or_insert_with(|| convert_material(&material));
+
+                key
+            };
+
+            scene.instances.insert(
+                format!("instances/{}/{}", node.index(), primitive.index()),
+                Instance::new(mesh_key, material_key, xform),
+            );
+        }
+    }
+
+    if let Some(light) = node.light() {
+        let key = format!("lights/{}", node.index());
+        let position = xform.translation.into();
+        let color = Vec3::from(light.color()) * light.intensity();
+        let range = light.range().unwrap_or(DEFAULT_LIGHT_RANGE);
+
+        let light = match light.kind() {
+            Kind::Point => Some(Light::Point {
+                position,
+                radius: 0.0,
+                color,
+                range,
+            }),
+
+            Kind::Spot {
+                outer_cone_angle, ..
+            } => Some(Light::Spot {
+                position,
+                radius: 0.0,
+                color,
+                range,
+                direction: xform.transform_vector3(-Vec3::Z).normalize(),
+                angle: outer_cone_angle,
+            }),
+
+            Kind::Directional => {
+                warn!(
+                    "Skipping glTF light `{}`: directional lights are not \
+                     supported",
+                    key
+                );
+
+                None
+            }
+        };
+
+        if let Some(light) = light {
+            scene.lights.insert(key, light);
+        }
+    }
+
+    for child in node.children() {
+        load_node(scene, child, xform);
+    }
+}
+
+fn convert_material(material: &::gltf::Material) -> Material<SceneParams> {
+    let pbr = material.pbr_metallic_roughness();
+    let base_color = Vec4::from(pbr.base_color_factor());
+
+    let base_color = match material.alpha_mode() {
+        ::gltf::material::AlphaMode::Opaque => base_color.xyz().extend(1.0),
+
+        ::gltf::material::AlphaMode::Mask => {
+            let cutoff = material.alpha_cutoff().unwrap_or(0.5);
+
+            if base_color.w >= cutoff {
+                base_color.xyz().extend(1.0)
+            } else {
+                base_color.xyz().extend(0.0)
+            }
+        }
+
+        ::gltf::material::AlphaMode::Blend => base_color,
+    };
+
+    let alpha_mode = match material.alpha_mode() {
+        ::gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+        _ => AlphaMode::Blend,
+    };
+
+    let emissive = Vec3::from(material.emissive_factor())
+        * material.emissive_strength().unwrap_or(1.0);
+
+    let image_key = |texture: ::gltf::Texture| {
+        format!("images/{}", texture.source().index())
+    };
+
+    Material {
+        base_color,
+        base_color_texture: pbr
+            .base_color_texture()
+            .map(|info| image_key(info.texture())),
+        emissive: emissive.extend(1.0),
+        emissive_texture: material
+            .emissive_texture()
+            .map(|info| image_key(info.texture())),
+        perceptual_roughness: pbr.roughness_factor(),
+        metallic: pbr.metallic_factor(),
+        normal_map_texture: material
+            .normal_texture()
+            .map(|normal| image_key(normal.texture())),
+        alpha_mode,
+        ..Default::default()
+    }
+}
+
+fn convert_image(image: &image::Data) -> io::Result<SceneImage> {
+    let data: Vec<u8> = match image.format {
+        Format::R8 => {
+            image.pixels.iter().flat_map(|&r| [r, r, r, 255]).collect()
+        }
+
+        Format::R8G8 => image
+            .pixels
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[1], 0, 255])
+            .collect(),
+
+        Format::R8G8B8 => image
+            .pixels
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+
+        Format::R8G8B8A8 => image.pixels.clone(),
+
+        // For 16-bit formats, let's just take the most significant byte of
+        // each channel (glTF stores them as little-endian)
+        Format::R16 => image
+            .pixels
+            .chunks_exact(2)
+            .flat_map(|p| [p[1], p[1], p[1], 255])
+            .collect(),
+
+        Format::R16G16 => image
+            .pixels
+            .chunks_exact(4)
+            .flat_map(|p| [p[1], p[3], 0, 255])
+            .collect(),
+
+        Format::R16G16B16 => image
+            .pixels
+            .chunks_exact(6)
+            .flat_map(|p| [p[1], p[3], p[5], 255])
+            .collect(),
+
+        Format::R16G16B16A16 => image
+            .pixels
+            .chunks_exact(8)
+            .flat_map(|p| [p[1], p[3], p[5], p[7]])
+            .collect(),
+
+        format => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported glTF image format: {:?}", format),
+            ));
+        }
+    };
+
+    Ok(SceneImage::Embedded {
+        width: image.width,
+        height: image.height,
+        data,
+    })
+}
+
+fn flat_normal([a, b, c]: [Vec3; 3]) -> Vec3 {
+    (b - a).cross(c - a).normalize_or_zero()
+}
+
+fn into_io_error(err: ::gltf::Error) -> io::Error {
+    match err {
+        ::gltf::Error::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec3, vec4};
+
+    use super::*;
+
+    const GLTF: &str = r#"{
+        "asset": { "version": "2.0" },
+        "extensionsUsed": ["KHR_lights_punctual"],
+        "extensions": {
+            "KHR_lights_punctual": {
+                "lights": [
+                    { "type": "point", "color": [1.0, 0.5, 0.25], "intensity": 2.0 }
+                ]
+            }
+        },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [
+            {
+                "translation": [0.0, 0.0, -5.0],
+                "mesh": 0,
+                "children": [1]
+            },
+            {
+                "translation": [0.0, 3.0, 0.0],
+                "extensions": { "KHR_lights_punctual": { "light": 0 } }
+            }
+        ],
+        "materials": [
+            {
+                "pbrMetallicRoughness": {
+                    "baseColorFactor": [1.0, 0.0, 0.0, 0.5],
+                    "metallicFactor": 0.25,
+                    "roughnessFactor": 0.75
+                },
+                "emissiveFactor": [0.0, 1.0, 0.0],
+                "alphaMode": "BLEND"
+            }
+        ],
+        "meshes": [
+            {
+                "primitives": [
+                    { "attributes": { "POSITION": 0 }, "material": 0 },
+                    { "attributes": { "POSITION": 0 } }
+                ]
+            }
+        ],
+        "buffers": [
+            {
+                "byteLength": 36,
+                "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA"
+            }
+        ],
+        "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+        "accessors": [
+            {
+                "bufferView": 0,
+                "componentType": 5126,
+                "count": 3,
+                "type": "VEC3",
+                "min": [0.0, 0.0, 0.0],
+                "max": [1.0, 1.0, 0.0]
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn load() {
+        let scene = Scene::load_gltf_slice(GLTF.as_bytes()).unwrap();
+
+        // ---
+
+        let mesh = &scene.meshes["meshes/0/0"];
+
+        assert_eq!(1, mesh.triangles().len());
+
+        assert_eq!(
+            [Vec3::ZERO, Vec3::X, Vec3::Y],
+            mesh.triangles()[0].positions()
+        );
+
+        // Normals are missing, so they should've been generated
+        assert_eq!([Vec3::Z; 3], mesh.triangles()[0].normals());
+
+        // ---
+
+        let material = &scene.materials["materials/0"];
+
+        assert_eq!(vec4(1.0, 0.0, 0.0, 0.5), material.base_color);
+        assert_eq!(vec4(0.0, 1.0, 0.0, 1.0), material.emissive);
+        assert_eq!(0.25, material.metallic);
+        assert_eq!(0.75, material.perceptual_roughness);
+        assert!(matches!(material.alpha_mode, AlphaMode::Blend));
+
+        // Second primitive has no material, so it should use the default one
+        assert!(scene.materials.contains_key("materials/default"));
+
+        // ---
+
+        let instance = &scene.instances["instances/0/0"];
+
+        assert_eq!("meshes/0/0", instance.mesh_handle);
+        assert_eq!("materials/0", instance.material_handle);
+        assert_eq!(vec3(0.0, 0.0, -5.0), instance.transform.translation.into());
+
+        let instance = &scene.instances["instances/0/1"];
+
+        assert_eq!("meshes/0/1", instance.mesh_handle);
+        assert_eq!("materials/default", instance.material_handle);
+
+        // ---
+
+        assert_eq!(
+            Light::Point {
+                position: vec3(0.0, 3.0, -5.0),
+                radius: 0.0,
+                color: vec3(2.0, 1.0, 0.5),
+                range: DEFAULT_LIGHT_RANGE,
+            },
+            scene.lights["lights/1"],
+        );
+    }
+}
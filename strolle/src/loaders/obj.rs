@@ -0,0 +1,599 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::SplitWhitespace;
+use std::{fs, io};
+
+use glam::{vec2, Affine3A, Vec3};
+
+use crate::{
+    AlphaMode, Instance, Material, Mesh, MeshTriangle, Scene, SceneImage,
+    SceneParams,
+};
+
+impl Scene {
+    /// Loads a Wavefront OBJ file, together with materials from the MTL files
+    /// it refers to.
+    ///
+    /// Polygons get triangulated (as fans, so they should be convex) and
+    /// vertices without normals get flat normals.
+    ///
+    /// Each group of faces sharing the same object (`o` / `g`) and material
+    /// (`usemtl`) gets loaded as a separate mesh, with a single instance that
+    /// has the identity transform; keys follow the order of appearance - e.g.
+    /// `meshes/0` with `instances/0`. Materials are keyed by their names (e.g.
+    /// `materials/Light`), and images by their paths (e.g.
+    /// `images/textures/wall.png`).
+    ///
+    /// MTL's properties are mapped as follows:
+    ///
+    /// - `Kd` -> `base_color`,
+    /// - `Ke` -> `emissive`,
+    /// - `Ns` -> `perceptual_roughness`,
+    /// - `d` (or `Tr`) -> `base_color`'s alpha + `alpha_mode`,
+    /// - `Ni` -> `ior`,
+    /// - `map_Kd` -> `base_color_texture`,
+    /// - `map_Ke` -> `emissive_texture`,
+    /// - `map_Bump` / `bump` / `norm` -> `normal_map_texture`.
+    pub fn load_obj(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        load(&fs::read_to_string(path)?, |mtl| {
+            let mtl_path = dir.join(mtl);
+
+            Ok((fs::read_to_string(&mtl_path)?, mtl_path))
+        })
+    }
+}
+
+/// Parses given OBJ; `load_mtl` is called for each file referred to by
+/// `mtllib` and returns the MTL's contents together with its path (which
+/// textures are relative to).
+fn load(
+    obj: &str,
+    mut load_mtl: impl FnMut(&str) -> io::Result<(String, PathBuf)>,
+) -> io::Result<Scene> {
+    let mut scene = Scene::default();
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut groups: Vec<Group> = Vec::new();
+    let mut material = None;
+
+    for (line_idx, line) in obj.lines().enumerate() {
+        let err = |msg: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("OBJ, line {}: {}", line_idx + 1, msg),
+            )
+        };
+
+        let mut args = line.split_whitespace();
+
+        match args.next() {
+            Some("v") => {
+                positions.push(parse_vec3(&mut args).ok_or_else(|| {
+                    err("expected vertex position in format: `v x y z`")
+                })?);
+            }
+
+            Some("vn") => {
+                normals.push(parse_vec3(&mut args).ok_or_else(|| {
+                    err("expected vertex normal in format: `vn x y z`")
+                })?);
+            }
+
+            Some("vt") => {
+                let uv = parse_f32s::<1>(&mut args)
+                    .map(|[u]| {
+                        let v = args
+                            .next()
+                            .and_then(|v| v.parse().ok())
+                            .unwrap_or(0.0);
+
+                        // OBJ's textures start at the bottom-left corner, our
+                        // textures start at the top-left one
+                        vec2(u, 1.0 - v)
+                    })
+                    .ok_or_else(|| {
+                        err("expected texture coordinate in format: `vt u v`")
+                    })?;
+
+                uvs.push(uv);
+            }
+
+            Some("f") => {
+                let vertices = args
+                    .map(|vertex| {
+                        parse_vertex(
+                            vertex,
+                            positions.len(),
+                            uvs.len(),
+                            normals.len(),
+                        )
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| err("invalid face"))?;
+
+                if vertices.len() < 3 {
+                    return Err(err("face must have at least three vertices"));
+                }
+
+                let group = match groups.last_mut() {
+                    Some(group) if !group.is_sealed => group,
+
+                    _ => {
+                        groups.push(Group {
+                            material: material.clone(),
+                            triangles: Default::default(),
+                            is_sealed: false,
+                        });
+
+                        groups.last_mut().unwrap()
+                    }
+                };
+
+                for idx in 1..(vertices.len() - 1) {
+                    let vs = [vertices[0], vertices[idx], vertices[idx + 1]];
+                    let tri_positions = vs.map(|v| positions[v.position]);
+
+                    let flat_normal = {
+                        let [a, b, c] = tri_positions;
+
+                        (b - a).cross(c - a).normalize_or_zero()
+                    };
+
+                    let tri_normals = vs.map(|v| {
+                        v.normal.map(|n| normals[n]).unwrap_or(flat_normal)
+                    });
+
+                    let tri_uvs =
+                        vs.map(|v| v.uv.map(|uv| uvs[uv]).unwrap_or_default());
+
+                    group.triangles.push(
+                        MeshTriangle::default()
+                            .with_positions(tri_positions)
+                            .with_normals(tri_normals)
+                            .with_uvs(tri_uvs),
+                    );
+                }
+            }
+
+            Some("o" | "g") => {
+                seal(&mut groups);
+            }
+
+            Some("usemtl") => {
+                let name = rest(line, "usemtl")
+                    .ok_or_else(|| err("expected material name"))?;
+
+                material = Some(name.to_owned());
+                seal(&mut groups);
+            }
+
+            Some("mtllib") => {
+                let mut mtls = args.peekable();
+
+                if mtls.peek().is_none() {
+                    return Err(err("expected file name"));
+                }
+
+                // A single `mtllib` can refer to many files
+                for mtl in mtls {
+                    let (mtl, mtl_path) = load_mtl(mtl)?;
+                    let mtl_dir =
+                        mtl_path.parent().unwrap_or_else(|| Path::new(""));
+
+                    load_materials(&mut scene, &mtl, mtl_dir)?;
+                }
+            }
+
+            _ => {
+                // Comments, smoothing groups, lines, curves etc.
+            }
+        }
+    }
+
+    for (group_idx, group) in groups.into_iter().enumerate() {
+        if group.triangles.is_empty() {
+            continue;
+        }
+
+        let material_key = if let Some(material) = group.material {
+            let key = format!("materials/{}", material);
+
+            // Referring to an unknown material is not considered an error,
+            // since most of the tools just fall back to a default material
+            // in this case
+            scene.materials.entry(key.clone()).or_default();
+
+            key
+        } else {
+            let key = "materials/default".to_owned();
+
+            scene.materials.entry(key.clone()).or_default();
+
+            key
+        };
+
+        let mesh_key = format!("meshes/{}", group_idx);
+
+        scene
+            .meshes
+            .insert(mesh_key.clone(), Mesh::new(group.triangles));
+
+        scene.instances.insert(
+            format!("instances/{}", group_idx),
+            Instance::new(mesh_key, material_key, Affine3A::IDENTITY),
+        );
+    }
+
+    Ok(scene)
+}
+
+fn load_materials(scene: &mut Scene, mtl: &str, dir: &Path) -> io::Result<()> {
+    let mut materials = BTreeMap::new();
+    let mut current: Option<(String, Material<SceneParams>)> = None;
+
+    for (line_idx, line) in mtl.lines().enumerate() {
+        let err = |msg: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("MTL, line {}: {}", line_idx + 1, msg),
+            )
+        };
+
+        let mut args = line.split_whitespace();
+
+        let Some(op) = args.next() else {
+            continue;
+        };
+
+        if op == "newmtl" {
+            let name =
+                rest(line, "newmtl").ok_or_else(|| err("expected name"))?;
+
+            materials.extend(current.take());
+            current = Some((name.to_owned(), Material::default()));
+            continue;
+        }
+
+        if op.starts_with('#') {
+            continue;
+        }
+
+        let Some((_, material)) = &mut current else {
+            return Err(err("expected `newmtl` first"));
+        };
+
+        match op {
+            "Kd" => {
+                let color = parse_vec3(&mut args)
+                    .ok_or_else(|| err("expected color: `Kd r g b`"))?;
+
+                material.base_color = color.extend(material.base_color.w);
+            }
+
+            "Ke" => {
+                let color = parse_vec3(&mut args)
+                    .ok_or_else(|| err("expected color: `Ke r g b`"))?;
+
+                material.emissive = color.extend(1.0);
+            }
+
+            "Ns" => {
+                let [ns] = parse_f32s(&mut args)
+                    .ok_or_else(|| err("expected exponent: `Ns value`"))?;
+
+                // Approximates Blinn-Phong's exponent as Beckmann's roughness
+                // (alpha = sqrt(2 / (ns + 2))), which we then convert into
+                // perceptual roughness (= sqrt(alpha))
+                material.perceptual_roughness =
+                    (2.0 / (ns.max(0.0) + 2.0)).powf(0.25);
+            }
+
+            "d" | "Tr" => {
+                let [value] = parse_f32s(&mut args)
+                    .ok_or_else(|| err("expected value: `d value`"))?;
+
+                let alpha = if op == "d" { value } else { 1.0 - value };
+                let alpha = alpha.clamp(0.0, 1.0);
+
+                material.base_color.w = alpha;
+
+                material.alpha_mode = if alpha < 1.0 {
+                    AlphaMode::Blend
+                } else {
+                    AlphaMode::Opaque
+                };
+            }
+
+            "Ni" => {
+                let [ior] = parse_f32s(&mut args)
+                    .ok_or_else(|| err("expected value: `Ni value`"))?;
+
+                material.ior = ior;
+            }
+
+            "map_Kd" | "map_Ke" | "map_Bump" | "map_bump" | "bump" | "norm" => {
+                // Texture options (e.g. `-bm 1.0`) are not supported, so let's
+                // just assume the path is the last argument
+                let path =
+                    args.last().ok_or_else(|| err("expected file name"))?;
+
+                let key = format!("images/{}", path);
+
+                scene
+                    .images
+                    .entry(key.clone())
+                    .or_insert_with(|| SceneImage::Path(dir.join(path)));
+
+                let texture = Some(key);
+
+                match op {
+                    "map_Kd" => material.base_color_texture = texture,
+                    "map_Ke" => material.emissive_texture = texture,
+                    _ => material.normal_map_texture = texture,
+                }
+            }
+
+            _ => {
+                // Specular color, illumination model etc.
+            }
+        }
+    }
+
+    materials.extend(current);
+
+    for (name, material) in materials {
+        scene
+            .materials
+            .insert(format!("materials/{}", name), material);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug)]
+struct Group {
+    material: Option<String>,
+    triangles: Vec<MeshTriangle>,
+
+    /// Whether this group has been finished (by `o`, `g` or `usemtl`), i.e.
+    /// whether the next face should start a new group
+    is_sealed: bool,
+}
+
+fn seal(groups: &mut [Group]) {
+    if let Some(group) = groups.last_mut() {
+        group.is_sealed = true;
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Vertex {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+/// Parses vertex in one of the formats: `v`, `v/vt`, `v//vn` or `v/vt/vn`,
+/// where indices can be negative (i.e. relative to the end).
+fn parse_vertex(
+    vertex: &str,
+    positions: usize,
+    uvs: usize,
+    normals: usize,
+) -> Option<Vertex> {
+    let mut indices = vertex.split('/');
+
+    let position = parse_index(indices.next()?, positions)?;
+
+    let uv = match indices.next() {
+        None | Some("") => None,
+        Some(idx) => Some(parse_index(idx, uvs)?),
+    };
+
+    let normal = match indices.next() {
+        None | Some("") => None,
+        Some(idx) => Some(parse_index(idx, normals)?),
+    };
+
+    if indices.next().is_some() {
+        return None;
+    }
+
+    Some(Vertex {
+        position,
+        uv,
+        normal,
+    })
+}
+
+fn parse_index(idx: &str, len: usize) -> Option<usize> {
+    let idx: isize = idx.parse().ok()?;
+
+    let idx = if idx > 0 {
+        idx as usize - 1
+    } else {
+        len.checked_sub(idx.unsigned_abs())?
+    };
+
+    (idx < len).then_some(idx)
+}
+
+fn parse_f32s<const N: usize>(args: &mut SplitWhitespace) -> Option<[f32; N]> {
+    let mut values = [0.0; N];
+
+    for value in &mut values {
+        *value = args.next()?.parse().ok()?;
+    }
+
+    Some(values)
+}
+
+fn parse_vec3(args: &mut SplitWhitespace) -> Option<Vec3> {
+    parse_f32s(args).map(Vec3::from_array)
+}
+
+/// Returns everything after given operator, so that names with spaces (e.g.
+/// `usemtl Light Blue`) are supported.
+fn rest<'a>(line: &'a str, op: &str) -> Option<&'a str> {
+    let rest = line.trim_start().strip_prefix(op)?.trim();
+
+    (!rest.is_empty()).then_some(rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec3, vec4};
+
+    use super::*;
+
+    const OBJ: &str = "
+        # Comment
+        mtllib box.mtl
+
+        v 0 0 0
+        v 1 0 0
+        v 1 1 0
+        v 0 1 0
+        vt 0 0
+        vt 1 1
+        vn 0 0 -1
+
+        o Floor
+        usemtl White
+        f 1/1 2/1 3/2 4/2
+
+        o Light
+        usemtl Light
+        f -4//1 -3//1 -2//1
+    ";
+
+    const MTL: &str = "
+        newmtl White
+        Kd 0.5 0.5 0.5
+        Ns 0
+        map_Kd white.png
+
+        newmtl Light
+        Kd 0 0 0
+        Ke 10 10 10
+        d 0.25
+        Ni 1.5
+    ";
+
+    #[test]
+    fn load() {
+        let scene = super::load(OBJ, |mtl| {
+            assert_eq!("box.mtl", mtl);
+
+            Ok((MTL.into(), "assets/box.mtl".into()))
+        })
+        .unwrap();
+
+        // ---
+
+        let floor = &scene.meshes["meshes/0"];
+
+        // Quad should've been triangulated
+        assert_eq!(2, floor.triangles().len());
+
+        assert_eq!(
+            [Vec3::ZERO, vec3(1.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0)],
+            floor.triangles()[1].positions()
+        );
+
+        // Normals are missing, so they should've been generated
+        assert_eq!([Vec3::Z; 3], floor.triangles()[0].normals());
+
+        assert_eq!(
+            [vec2(0.0, 1.0), vec2(0.0, 1.0), vec2(1.0, 0.0)],
+            floor.triangles()[0].uvs()
+        );
+
+        let light = &scene.meshes["meshes/1"];
+
+        assert_eq!(1, light.triangles().len());
+        assert_eq!([-Vec3::Z; 3], light.triangles()[0].normals());
+
+        // ---
+
+        let white = &scene.materials["materials/White"];
+
+        assert_eq!(vec4(0.5, 0.5, 0.5, 1.0), white.base_color);
+        assert_eq!(1.0, white.perceptual_roughness);
+        assert_eq!(
+            Some("images/white.png"),
+            white.base_color_texture.as_deref()
+        );
+
+        assert_eq!(
+            SceneImage::Path("assets/white.png".into()),
+            scene.images["images/white.png"],
+        );
+
+        let light = &scene.materials["materials/Light"];
+
+        assert_eq!(vec4(0.0, 0.0, 0.0, 0.25), light.base_color);
+        assert_eq!(vec4(10.0, 10.0, 10.0, 1.0), light.emissive);
+        assert_eq!(1.5, light.ior);
+        assert!(matches!(light.alpha_mode, AlphaMode::Blend));
+
+        // ---
+
+        assert_eq!(
+            "materials/White",
+            scene.instances["instances/0"].material_handle
+        );
+        assert_eq!(
+            "materials/Light",
+            scene.instances["instances/1"].material_handle
+        );
+    }
+
+    #[test]
+    fn load_many_mtllibs() {
+        let obj = "
+            mtllib white.mtl light.mtl
+
+            v 0 0 0
+            v 1 0 0
+            v 0 1 0
+
+            usemtl White
+            f 1 2 3
+
+            usemtl Light
+            f 1 2 3
+        ";
+
+        let mut mtls = Vec::new();
+
+        let scene = super::load(obj, |mtl| {
+            mtls.push(mtl.to_owned());
+
+            let contents = match mtl {
+                "white.mtl" => "newmtl White\nKd 0.5 0.5 0.5",
+                "light.mtl" => "newmtl Light\nKe 10 10 10",
+                _ => unreachable!(),
+            };
+
+            Ok((contents.into(), mtl.into()))
+        })
+        .unwrap();
+
+        assert_eq!(["white.mtl", "light.mtl"], mtls.as_slice());
+
+        assert_eq!(
+            vec4(0.5, 0.5, 0.5, 1.0),
+            scene.materials["materials/White"].base_color
+        );
+
+        assert_eq!(
+            vec4(10.0, 10.0, 10.0, 1.0),
+            scene.materials["materials/Light"].emissive
+        );
+    }
+}
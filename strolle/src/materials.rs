@@ -63,6 +63,14 @@ where
         }
     }
 
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = (&P::MaterialHandle, &Material<P>)> + '_ {
+        self.index.iter().map(|(material_handle, material_id)| {
+            (material_handle, &self.materials[material_id.get() as usize])
+        })
+    }
+
     pub fn has(&self, material_handle: &P::MaterialHandle) -> bool {
         self.index.contains_key(material_handle)
     }
@@ -85,6 +93,10 @@ where
         self.buffer.len()
     }
 
+    pub fn as_slice(&self) -> &[gpu::Material] {
+        &self.buffer
+    }
+
     pub fn lookup(
         &self,
         material_handle: &P::MaterialHandle,
@@ -1,4 +1,4 @@
-use crate::MeshTriangle;
+use crate::{MeshTriangle, StrolleError};
 
 #[derive(Clone, Debug)]
 pub struct Mesh {
@@ -13,4 +13,86 @@ impl Mesh {
     pub(crate) fn triangles(&self) -> &[MeshTriangle] {
         &self.triangles
     }
+
+    /// Checks whether this mesh can be rendered; see:
+    /// [`crate::Engine::try_insert_mesh()`].
+    pub(crate) fn validate(&self) -> Result<(), StrolleError> {
+        if self.triangles.is_empty() {
+            return Err(StrolleError::InvalidMesh(
+                "mesh contains no triangles".into(),
+            ));
+        }
+
+        let has_invalid_positions = self.triangles.iter().any(|triangle| {
+            triangle
+                .positions()
+                .iter()
+                .any(|position| !position.is_finite())
+        });
+
+        if has_invalid_positions {
+            return Err(StrolleError::InvalidMesh(
+                "mesh contains non-finite positions".into(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec3, Vec3};
+
+    use super::*;
+
+    fn triangle(positions: [Vec3; 3]) -> MeshTriangle {
+        MeshTriangle::default()
+            .with_positions(positions)
+            .with_normals([Vec3::Z; 3])
+    }
+
+    #[test]
+    fn validate() {
+        let mesh = Mesh::new(vec![triangle([
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+        ])]);
+
+        assert_eq!(Ok(()), mesh.validate());
+    }
+
+    #[test]
+    fn validate_empty() {
+        assert_eq!(
+            Err(StrolleError::InvalidMesh(
+                "mesh contains no triangles".into()
+            )),
+            Mesh::new(Vec::new()).validate()
+        );
+    }
+
+    #[test]
+    fn validate_non_finite_positions() {
+        let mesh = Mesh::new(vec![
+            triangle([
+                vec3(0.0, 0.0, 0.0),
+                vec3(1.0, 0.0, 0.0),
+                vec3(0.0, 1.0, 0.0),
+            ]),
+            triangle([
+                vec3(0.0, 0.0, 0.0),
+                vec3(f32::NAN, 0.0, 0.0),
+                vec3(0.0, f32::INFINITY, 0.0),
+            ]),
+        ]);
+
+        assert_eq!(
+            Err(StrolleError::InvalidMesh(
+                "mesh contains non-finite positions".into()
+            )),
+            mesh.validate()
+        );
+    }
 }
@@ -1,8 +1,12 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::mem;
 
 use derivative::Derivative;
+use log::warn;
 
-use crate::{Mesh, Params};
+use crate::bvh::Bvh;
+use crate::triangles::Triangles;
+use crate::{Mesh, MeshTriangle, Params};
 
 #[derive(Debug, Derivative)]
 #[derivative(Default)]
@@ -11,6 +15,7 @@ where
     P: Params,
 {
     meshes: HashMap<P::MeshHandle, Mesh>,
+    dirty: HashSet<P::MeshHandle>,
 }
 
 impl<P> Meshes<P>
@@ -18,18 +23,65 @@ where
     P: Params,
 {
     pub fn insert(&mut self, mesh_handle: P::MeshHandle, mesh: Mesh) {
-        self.meshes.insert(mesh_handle, mesh);
+        self.meshes.insert(mesh_handle.clone(), mesh);
+        self.dirty.insert(mesh_handle);
     }
 
     pub fn get(&self, mesh_handle: &P::MeshHandle) -> Option<&Mesh> {
         self.meshes.get(mesh_handle)
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = (&P::MeshHandle, &Mesh)> + '_ {
+        self.meshes.iter()
+    }
+
     pub fn remove(&mut self, mesh_handle: &P::MeshHandle) {
         self.meshes.remove(mesh_handle);
+        self.dirty.insert(mesh_handle.clone());
     }
 
     pub fn len(&self) -> usize {
         self.meshes.len()
     }
+
+    /// Updates triangles (and bottom-level BVHs) of meshes that have been
+    /// modified or removed since the last refresh; returns whether there were
+    /// any such meshes.
+    ///
+    /// Meshes that have been only deformed (i.e. their number of triangles
+    /// stayed the same) get updated in place, refitting their BVHs; other
+    /// modified meshes get uploaded again, lazily, during the instances'
+    /// refresh.
+    pub fn refresh(
+        &mut self,
+        triangles: &mut Triangles<P>,
+        bvh: &mut Bvh,
+    ) -> bool {
+        let dirty = mem::take(&mut self.dirty);
+
+        for mesh_handle in &dirty {
+            let mesh = self.meshes.get(mesh_handle).filter(|mesh| {
+                !mesh.triangles().is_empty()
+                    && triangles.count(mesh_handle)
+                        == Some(mesh.triangles().len())
+            });
+
+            if let Some(mesh) = mesh {
+                let result = triangles.update(
+                    bvh,
+                    mesh_handle,
+                    mesh.triangles().iter().map(MeshTriangle::build),
+                );
+
+                if let Err(err) = result {
+                    warn!("Cannot update mesh `{:?}` - {}", mesh_handle, err);
+                    triangles.remove(bvh, mesh_handle);
+                }
+            } else {
+                triangles.remove(bvh, mesh_handle);
+            }
+        }
+
+        !dirty.is_empty()
+    }
 }
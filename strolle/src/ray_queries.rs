@@ -0,0 +1,153 @@
+use std::mem;
+use std::ops::Range;
+
+use log::debug;
+
+use crate::{gpu, BindGroup, Engine, Params};
+
+/// Compute pass that traces arbitrary rays submitted by the user; see:
+/// [`Engine::trace_rays()`].
+#[derive(Debug)]
+pub struct RayQueriesPass {
+    bind_group: BindGroup,
+    queries_bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl RayQueriesPass {
+    /// Number of threads per workgroup, as declared in the shader.
+    const WORKGROUP_SIZE: u32 = 64;
+
+    pub fn new<P>(engine: &Engine<P>, device: &wgpu::Device) -> Self
+    where
+        P: Params,
+    {
+        debug!("Initializing pass: ray_queries");
+
+        let bind_group = BindGroup::builder("ray_queries_bg0")
+            .add(&engine.triangles.bind_readable())
+            .add(&engine.bvh.bind_readable())
+            .add(&engine.materials.bind_readable())
+            .add(&engine.images.bind_atlas())
+            .build(device);
+
+        // Rays and hits are provided by the user on each call, so - contrary
+        // to the other bind group - here we can prepare just the layout
+        let queries_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("strolle_ray_queries_bg1_layout"),
+                entries: &[
+                    Self::storage_buffer_layout(0, true),
+                    Self::storage_buffer_layout(1, false),
+                ],
+            });
+
+        let pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("strolle_ray_queries_pipeline_layout"),
+                bind_group_layouts: &[
+                    bind_group.layout(),
+                    &queries_bind_group_layout,
+                ],
+                push_constant_ranges: &[wgpu::PushConstantRange {
+                    stages: wgpu::ShaderStages::COMPUTE,
+                    range: Range {
+                        start: 0,
+                        end: mem::size_of::<gpu::RayQueriesPassParams>() as u32,
+                    },
+                }],
+            });
+
+        let (module, entry_point) = &engine.shaders.ray_queries;
+
+        let pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("strolle_ray_queries_pipeline"),
+                layout: Some(&pipeline_layout),
+                module,
+                entry_point,
+            });
+
+        Self {
+            bind_group,
+            queries_bind_group_layout,
+            pipeline,
+        }
+    }
+
+    fn storage_buffer_layout(
+        binding: u32,
+        read_only: bool,
+    ) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+
+    pub fn run(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        rays: &wgpu::Buffer,
+        hits: &wgpu::Buffer,
+        count: u32,
+    ) {
+        if count == 0 {
+            return;
+        }
+
+        let queries_bind_group =
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("strolle_ray_queries_bg1"),
+                layout: &self.queries_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: rays.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: hits.as_entire_binding(),
+                    },
+                ],
+            });
+
+        let mut pass =
+            encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("strolle_ray_queries_pass"),
+            });
+
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, self.bind_group.get(false), &[]);
+        pass.set_bind_group(1, &queries_bind_group, &[]);
+
+        // Large batches might require more workgroups than a single dispatch
+        // allows for, in which case we trace them chunk by chunk
+        let queries_per_dispatch = device
+            .limits()
+            .max_compute_workgroups_per_dimension
+            .saturating_mul(Self::WORKGROUP_SIZE);
+
+        let mut offset = 0;
+
+        while offset < count {
+            let queries = (count - offset).min(queries_per_dispatch);
+            let workgroups =
+                (queries + Self::WORKGROUP_SIZE - 1) / Self::WORKGROUP_SIZE;
+
+            let params = gpu::RayQueriesPassParams { offset, count };
+
+            pass.set_push_constants(0, bytemuck::bytes_of(&params));
+            pass.dispatch_workgroups(workgroups, 1, 1);
+
+            offset += queries;
+        }
+    }
+}
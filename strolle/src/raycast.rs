@@ -0,0 +1,56 @@
+use glam::{vec3, Vec2, Vec3};
+
+use crate::Params;
+
+/// Intersection found by [`crate::Engine::raycast()`].
+#[derive(Clone, Debug)]
+pub struct RaycastHit<P>
+where
+    P: Params,
+{
+    /// Instance that's been hit.
+    pub instance_handle: P::InstanceHandle,
+
+    /// Material of the instance that's been hit.
+    pub material_handle: P::MaterialHandle,
+
+    /// Index of the triangle that's been hit, within the instance's mesh (i.e.
+    /// an index into the triangles given to [`crate::Mesh::new()`]).
+    pub triangle: usize,
+
+    /// Distance from the ray's origin to the hit point.
+    pub distance: f32,
+
+    /// Hit point, in world-space.
+    pub point: Vec3,
+
+    /// Interpolated normal at the hit point, in world-space.
+    pub normal: Vec3,
+
+    /// Barycentric coordinates of the hit point; the n-th component says how
+    /// much the n-th triangle's vertex contributes to the hit point.
+    pub barycentrics: Vec3,
+
+    /// Interpolated texture coordinates at the hit point.
+    pub uv: Vec2,
+}
+
+/// Returns barycentric coordinates of given point that lies on given
+/// triangle.
+pub(crate) fn barycentrics([p0, p1, p2]: [Vec3; 3], point: Vec3) -> Vec3 {
+    let v0 = p1 - p0;
+    let v1 = p2 - p0;
+    let v2 = point - p0;
+
+    let d00 = v0.dot(v0);
+    let d01 = v0.dot(v1);
+    let d11 = v1.dot(v1);
+    let d20 = v2.dot(v0);
+    let d21 = v2.dot(v1);
+    let denom = d00 * d11 - d01 * d01;
+
+    let v = (d11 * d20 - d01 * d21) / denom;
+    let w = (d00 * d21 - d01 * d20) / denom;
+
+    vec3(1.0 - v - w, v, w)
+}
@@ -0,0 +1,847 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::ops::Deref;
+use std::path::PathBuf;
+
+use glam::{Affine3A, Vec2, Vec3, Vec4};
+
+use crate::{
+    AlphaMode, Image, ImageData, Instance, Light, Material, Mesh, MeshTriangle,
+    Params, RayVisibility, Sun,
+};
+
+/// Engine's state (meshes, materials, images, instances, lights and the sun)
+/// detached from the engine itself, so that it can be saved into a file and
+/// loaded back.
+///
+/// All objects are identified by string keys - when exporting or importing
+/// the scene, those keys are converted from / into engine's handles through
+/// [`SceneMapping`].
+///
+/// # Format
+///
+/// Scenes are stored in a simple binary format that starts with
+/// [`Self::MAGIC`] followed by [`Self::VERSION`] - when the format changes in
+/// an incompatible way, the version gets bumped and older files get rejected
+/// with [`io::ErrorKind::InvalidData`].
+#[derive(Debug, Default)]
+pub struct Scene {
+    pub sun: Sun,
+    pub images: BTreeMap<String, SceneImage>,
+    pub meshes: BTreeMap<String, Mesh>,
+    pub materials: BTreeMap<String, Material<SceneParams>>,
+    pub instances: BTreeMap<String, Instance<SceneParams>>,
+    pub lights: BTreeMap<String, Light>,
+}
+
+impl Scene {
+    pub const MAGIC: [u8; 8] = *b"STROLLE\0";
+    pub const VERSION: u32 = 1;
+
+    pub fn read(reader: impl Read) -> io::Result<Self> {
+        let mut reader = SceneReader(reader);
+
+        if reader.read_array::<8>()? != Self::MAGIC {
+            return Err(invalid_data("not a Strolle scene"));
+        }
+
+        let version = reader.read_u32()?;
+
+        if version != Self::VERSION {
+            return Err(invalid_data(format!(
+                "unsupported scene version: {} (expected {})",
+                version,
+                Self::VERSION
+            )));
+        }
+
+        let sun = Sun {
+            azimuth: reader.read_f32()?,
+            altitude: reader.read_f32()?,
+            angular_size: reader.read_f32()?,
+        };
+
+        let images = reader.read_map(SceneReader::read_image)?;
+        let meshes = reader.read_map(SceneReader::read_mesh)?;
+        let materials = reader.read_map(SceneReader::read_material)?;
+        let instances = reader.read_map(SceneReader::read_instance)?;
+        let lights = reader.read_map(SceneReader::read_light)?;
+
+        Ok(Self {
+            sun,
+            images,
+            meshes,
+            materials,
+            instances,
+            lights,
+        })
+    }
+
+    pub fn write(&self, writer: impl Write) -> io::Result<()> {
+        let mut writer = SceneWriter(writer);
+
+        writer.write_bytes(&Self::MAGIC)?;
+        writer.write_u32(Self::VERSION)?;
+        writer.write_f32(self.sun.azimuth)?;
+        writer.write_f32(self.sun.altitude)?;
+        writer.write_f32(self.sun.angular_size)?;
+        writer.write_map(&self.images, SceneWriter::write_image)?;
+        writer.write_map(&self.meshes, SceneWriter::write_mesh)?;
+        writer.write_map(&self.materials, SceneWriter::write_material)?;
+        writer.write_map(&self.instances, SceneWriter::write_instance)?;
+        writer.write_map(&self.lights, SceneWriter::write_light)?;
+
+        Ok(())
+    }
+}
+
+/// Image stored in a scene.
+///
+/// Note that the engine doesn't keep images in the CPU memory, so when
+/// exporting a scene it's [`SceneMapping::image_source()`] that has to provide
+/// either the path or the pixels.
+///
+/// Only the pixels are stored - images always get imported as RGBA8 (sRGB)
+/// textures with the default sampler (i.e. the original sampler descriptor,
+/// including its addressing and filtering modes, is lost). Images loaded from
+/// paths are decoded with the `image` crate, which is built only with PNG
+/// support, so other formats have to be embedded instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SceneImage {
+    /// Image that should be loaded from given file when the scene is being
+    /// imported; relative paths are resolved against the current working
+    /// directory
+    Path(PathBuf),
+
+    /// Image embedded in the scene, as RGBA8 (sRGB) pixels
+    Embedded {
+        width: u32,
+        height: u32,
+        data: Vec<u8>,
+    },
+}
+
+impl SceneImage {
+    pub(crate) fn load<P>(&self) -> io::Result<Image<P>>
+    where
+        P: Params,
+    {
+        let (width, height, data) = match self {
+            SceneImage::Path(path) => {
+                let image = image::open(path)
+                    .map_err(|err| {
+                        invalid_data(format!(
+                            "couldn't load image `{}`: {}",
+                            path.display(),
+                            err
+                        ))
+                    })?
+                    .into_rgba8();
+
+                (image.width(), image.height(), image.into_raw())
+            }
+
+            SceneImage::Embedded {
+                width,
+                height,
+                data,
+            } => {
+                if data.len() != (*width as usize) * (*height as usize) * 4 {
+                    return Err(invalid_data(format!(
+                        "embedded image has invalid size: expected {}x{} \
+                         pixels, got {} bytes",
+                        width,
+                        height,
+                        data.len()
+                    )));
+                }
+
+                (*width, *height, data.clone())
+            }
+        };
+
+        let texture_descriptor = wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        };
+
+        Ok(Image::new(
+            ImageData::Raw { data },
+            texture_descriptor,
+            Default::default(),
+        ))
+    }
+}
+
+/// Converts engine's handles into scene's keys and the other way around; used
+/// by [`crate::Engine::export_scene()`] and
+/// [`crate::Engine::import_scene()`].
+pub trait SceneMapping<P>
+where
+    P: Params,
+{
+    fn image_key(&mut self, handle: &P::ImageHandle) -> String;
+    fn image_handle(&mut self, key: &str) -> P::ImageHandle;
+
+    /// Returns where the image should be loaded from when the scene gets
+    /// imported - either from a file or from the pixels embedded in the scene.
+    fn image_source(&mut self, handle: &P::ImageHandle) -> SceneImage;
+
+    fn mesh_key(&mut self, handle: &P::MeshHandle) -> String;
+    fn mesh_handle(&mut self, key: &str) -> P::MeshHandle;
+
+    fn material_key(&mut self, handle: &P::MaterialHandle) -> String;
+    fn material_handle(&mut self, key: &str) -> P::MaterialHandle;
+
+    fn instance_key(&mut self, handle: &P::InstanceHandle) -> String;
+    fn instance_handle(&mut self, key: &str) -> P::InstanceHandle;
+
+    fn light_key(&mut self, handle: &P::LightHandle) -> String;
+    fn light_handle(&mut self, key: &str) -> P::LightHandle;
+}
+
+/// [`Params`] used by [`Scene`], where all of the handles are string keys.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SceneParams;
+
+impl Params for SceneParams {
+    type ImageHandle = String;
+    type ImageTexture = SceneTexture;
+    type InstanceHandle = String;
+    type LightHandle = String;
+    type MaterialHandle = String;
+    type MeshHandle = String;
+}
+
+/// Scenes don't contain GPU textures, so this type cannot be constructed.
+#[derive(Debug)]
+pub enum SceneTexture {}
+
+impl Deref for SceneTexture {
+    type Target = wgpu::Texture;
+
+    fn deref(&self) -> &Self::Target {
+        match *self {}
+    }
+}
+
+pub(crate) fn map_material<P, Q>(
+    material: &Material<P>,
+    mut map_image: impl FnMut(&P::ImageHandle) -> Q::ImageHandle,
+) -> Material<Q>
+where
+    P: Params,
+    Q: Params,
+{
+    Material {
+        base_color: material.base_color,
+        base_color_texture: material
+            .base_color_texture
+            .as_ref()
+            .map(&mut map_image),
+        emissive: material.emissive,
+        emissive_texture: material
+            .emissive_texture
+            .as_ref()
+            .map(&mut map_image),
+        perceptual_roughness: material.perceptual_roughness,
+        metallic: material.metallic,
+        reflectance: material.reflectance,
+        ior: material.ior,
+        normal_map_texture: material
+            .normal_map_texture
+            .as_ref()
+            .map(&mut map_image),
+        alpha_mode: material.alpha_mode,
+    }
+}
+
+fn invalid_data(
+    err: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+// ---
+
+struct SceneReader<R>(R);
+
+impl<R> SceneReader<R>
+where
+    R: Read,
+{
+    fn read_array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut buf = [0; N];
+
+        self.0.read_exact(&mut buf)?;
+
+        Ok(buf)
+    }
+
+    fn read_u8(&mut self) -> io::Result<u8> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_f32s<const N: usize>(&mut self) -> io::Result<[f32; N]> {
+        let mut values = [0.0; N];
+
+        for value in &mut values {
+            *value = self.read_f32()?;
+        }
+
+        Ok(values)
+    }
+
+    fn read_vec2(&mut self) -> io::Result<Vec2> {
+        self.read_f32s().map(Vec2::from_array)
+    }
+
+    fn read_vec3(&mut self) -> io::Result<Vec3> {
+        self.read_f32s().map(Vec3::from_array)
+    }
+
+    fn read_vec4(&mut self) -> io::Result<Vec4> {
+        self.read_f32s().map(Vec4::from_array)
+    }
+
+    fn read_bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.read_u32()? as usize;
+        let mut buf = Vec::new();
+
+        // Going through `take()` instead of allocating `len` bytes up front
+        // so that a corrupted length doesn't make us allocate gigabytes
+        (&mut self.0).take(len as u64).read_to_end(&mut buf)?;
+
+        if buf.len() != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(buf)
+    }
+
+    fn read_string(&mut self) -> io::Result<String> {
+        String::from_utf8(self.read_bytes()?).map_err(invalid_data)
+    }
+
+    fn read_opt_string(&mut self) -> io::Result<Option<String>> {
+        match self.read_u8()? {
+            0 => Ok(None),
+            1 => self.read_string().map(Some),
+            tag => Err(invalid_data(format!("invalid option tag: {}", tag))),
+        }
+    }
+
+    fn read_map<T>(
+        &mut self,
+        mut read_item: impl FnMut(&mut Self) -> io::Result<T>,
+    ) -> io::Result<BTreeMap<String, T>> {
+        let len = self.read_u32()?;
+        let mut items = BTreeMap::new();
+
+        for _ in 0..len {
+            let key = self.read_string()?;
+            let item = read_item(self)?;
+
+            items.insert(key, item);
+        }
+
+        Ok(items)
+    }
+
+    fn read_image(&mut self) -> io::Result<SceneImage> {
+        match self.read_u8()? {
+            0 => Ok(SceneImage::Path(self.read_string()?.into())),
+
+            1 => Ok(SceneImage::Embedded {
+                width: self.read_u32()?,
+                height: self.read_u32()?,
+                data: self.read_bytes()?,
+            }),
+
+            tag => Err(invalid_data(format!("invalid image tag: {}", tag))),
+        }
+    }
+
+    fn read_mesh(&mut self) -> io::Result<Mesh> {
+        let len = self.read_u32()?;
+        let mut triangles = Vec::new();
+
+        for _ in 0..len {
+            let positions =
+                [self.read_vec3()?, self.read_vec3()?, self.read_vec3()?];
+
+            let normals =
+                [self.read_vec3()?, self.read_vec3()?, self.read_vec3()?];
+
+            let uvs = [self.read_vec2()?, self.read_vec2()?, self.read_vec2()?];
+
+            let tangents =
+                [self.read_vec4()?, self.read_vec4()?, self.read_vec4()?];
+
+            triangles.push(
+                MeshTriangle::default()
+                    .with_positions(positions)
+                    .with_normals(normals)
+                    .with_uvs(uvs)
+                    .with_tangents(tangents),
+            );
+        }
+
+        Ok(Mesh::new(triangles))
+    }
+
+    fn read_material(&mut self) -> io::Result<Material<SceneParams>> {
+        Ok(Material {
+            base_color: self.read_vec4()?,
+            base_color_texture: self.read_opt_string()?,
+            emissive: self.read_vec4()?,
+            emissive_texture: self.read_opt_string()?,
+            perceptual_roughness: self.read_f32()?,
+            metallic: self.read_f32()?,
+            reflectance: self.read_f32()?,
+            ior: self.read_f32()?,
+            normal_map_texture: self.read_opt_string()?,
+            alpha_mode: match self.read_u8()? {
+                0 => AlphaMode::Opaque,
+                1 => AlphaMode::Blend,
+                tag => {
+                    return Err(invalid_data(format!(
+                        "invalid alpha mode: {}",
+                        tag
+                    )));
+                }
+            },
+        })
+    }
+
+    fn read_instance(&mut self) -> io::Result<Instance<SceneParams>> {
+        let mesh_handle = self.read_string()?;
+        let material_handle = self.read_string()?;
+        let transform = Affine3A::from_cols_array(&self.read_f32s()?);
+        let visibility_mask = self.read_u32()?;
+        let ray_visibility = self.read_u8()?;
+
+        let ray_visibility = RayVisibility {
+            visible_to_camera: ray_visibility & 1 != 0,
+            casts_shadows: ray_visibility & 2 != 0,
+            visible_in_indirect: ray_visibility & 4 != 0,
+            visible_in_reflections: ray_visibility & 8 != 0,
+        };
+
+        Ok(Instance::new(mesh_handle, material_handle, transform)
+            .with_visibility_mask(visibility_mask)
+            .with_ray_visibility(ray_visibility))
+    }
+
+    fn read_light(&mut self) -> io::Result<Light> {
+        match self.read_u8()? {
+            0 => Ok(Light::Point {
+                position: self.read_vec3()?,
+                radius: self.read_f32()?,
+                color: self.read_vec3()?,
+                range: self.read_f32()?,
+            }),
+
+            1 => Ok(Light::Spot {
+                position: self.read_vec3()?,
+                radius: self.read_f32()?,
+                color: self.read_vec3()?,
+                range: self.read_f32()?,
+                direction: self.read_vec3()?,
+                angle: self.read_f32()?,
+            }),
+
+            2 => Ok(Light::Rect {
+                position: self.read_vec3()?,
+                color: self.read_vec3()?,
+                normal: self.read_vec3()?,
+                tangent: self.read_vec3()?,
+                width: self.read_f32()?,
+                height: self.read_f32()?,
+            }),
+
+            3 => Ok(Light::Disk {
+                position: self.read_vec3()?,
+                color: self.read_vec3()?,
+                normal: self.read_vec3()?,
+                radius: self.read_f32()?,
+            }),
+
+            tag => Err(invalid_data(format!("invalid light tag: {}", tag))),
+        }
+    }
+}
+
+// ---
+
+struct SceneWriter<W>(W);
+
+impl<W> SceneWriter<W>
+where
+    W: Write,
+{
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.0.write_all(bytes)
+    }
+
+    fn write_u8(&mut self, value: u8) -> io::Result<()> {
+        self.write_bytes(&[value])
+    }
+
+    fn write_u32(&mut self, value: u32) -> io::Result<()> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    fn write_f32(&mut self, value: f32) -> io::Result<()> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    fn write_f32s(&mut self, values: &[f32]) -> io::Result<()> {
+        for value in values {
+            self.write_f32(*value)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_len(&mut self, len: usize) -> io::Result<()> {
+        let len = u32::try_from(len).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "collection too large")
+        })?;
+
+        self.write_u32(len)
+    }
+
+    fn write_sized_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.write_len(bytes.len())?;
+        self.write_bytes(bytes)
+    }
+
+    fn write_string(&mut self, value: &str) -> io::Result<()> {
+        self.write_sized_bytes(value.as_bytes())
+    }
+
+    fn write_opt_string(&mut self, value: Option<&String>) -> io::Result<()> {
+        if let Some(value) = value {
+            self.write_u8(1)?;
+            self.write_string(value)
+        } else {
+            self.write_u8(0)
+        }
+    }
+
+    fn write_map<T>(
+        &mut self,
+        items: &BTreeMap<String, T>,
+        mut write_item: impl FnMut(&mut Self, &T) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.write_len(items.len())?;
+
+        for (key, item) in items {
+            self.write_string(key)?;
+            write_item(self, item)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_image(&mut self, image: &SceneImage) -> io::Result<()> {
+        match image {
+            SceneImage::Path(path) => {
+                let path = path.to_str().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "image path is not valid UTF-8: {}",
+                            path.display()
+                        ),
+                    )
+                })?;
+
+                self.write_u8(0)?;
+                self.write_string(path)
+            }
+
+            SceneImage::Embedded {
+                width,
+                height,
+                data,
+            } => {
+                self.write_u8(1)?;
+                self.write_u32(*width)?;
+                self.write_u32(*height)?;
+                self.write_sized_bytes(data)
+            }
+        }
+    }
+
+    fn write_mesh(&mut self, mesh: &Mesh) -> io::Result<()> {
+        self.write_len(mesh.triangles().len())?;
+
+        for triangle in mesh.triangles() {
+            for position in triangle.positions() {
+                self.write_f32s(&position.to_array())?;
+            }
+
+            for normal in triangle.normals() {
+                self.write_f32s(&normal.to_array())?;
+            }
+
+            for uv in triangle.uvs() {
+                self.write_f32s(&uv.to_array())?;
+            }
+
+            for tangent in triangle.tangents() {
+                self.write_f32s(&tangent.to_array())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_material(
+        &mut self,
+        material: &Material<SceneParams>,
+    ) -> io::Result<()> {
+        self.write_f32s(&material.base_color.to_array())?;
+        self.write_opt_string(material.base_color_texture.as_ref())?;
+        self.write_f32s(&material.emissive.to_array())?;
+        self.write_opt_string(material.emissive_texture.as_ref())?;
+        self.write_f32(material.perceptual_roughness)?;
+        self.write_f32(material.metallic)?;
+        self.write_f32(material.reflectance)?;
+        self.write_f32(material.ior)?;
+        self.write_opt_string(material.normal_map_texture.as_ref())?;
+
+        self.write_u8(match material.alpha_mode {
+            AlphaMode::Opaque => 0,
+            AlphaMode::Blend => 1,
+        })
+    }
+
+    fn write_instance(
+        &mut self,
+        instance: &Instance<SceneParams>,
+    ) -> io::Result<()> {
+        self.write_string(&instance.mesh_handle)?;
+        self.write_string(&instance.material_handle)?;
+        self.write_f32s(&instance.transform.to_cols_array())?;
+        self.write_u32(instance.visibility_mask)?;
+
+        self.write_u8(
+            (instance.ray_visibility.visible_to_camera as u8)
+                | ((instance.ray_visibility.casts_shadows as u8) << 1)
+                | ((instance.ray_visibility.visible_in_indirect as u8) << 2)
+                | ((instance.ray_visibility.visible_in_reflections as u8) << 3),
+        )
+    }
+
+    fn write_light(&mut self, light: &Light) -> io::Result<()> {
+        match light {
+            Light::Point {
+                position,
+                radius,
+                color,
+                range,
+            } => {
+                self.write_u8(0)?;
+                self.write_f32s(&position.to_array())?;
+                self.write_f32(*radius)?;
+                self.write_f32s(&color.to_array())?;
+                self.write_f32(*range)
+            }
+
+            Light::Spot {
+                position,
+                radius,
+                color,
+                range,
+                direction,
+                angle,
+            } => {
+                self.write_u8(1)?;
+                self.write_f32s(&position.to_array())?;
+                self.write_f32(*radius)?;
+                self.write_f32s(&color.to_array())?;
+                self.write_f32(*range)?;
+                self.write_f32s(&direction.to_array())?;
+                self.write_f32(*angle)
+            }
+
+            Light::Rect {
+                position,
+                color,
+                normal,
+                tangent,
+                width,
+                height,
+            } => {
+                self.write_u8(2)?;
+                self.write_f32s(&position.to_array())?;
+                self.write_f32s(&color.to_array())?;
+                self.write_f32s(&normal.to_array())?;
+                self.write_f32s(&tangent.to_array())?;
+                self.write_f32(*width)?;
+                self.write_f32(*height)
+            }
+
+            Light::Disk {
+                position,
+                color,
+                normal,
+                radius,
+            } => {
+                self.write_u8(3)?;
+                self.write_f32s(&position.to_array())?;
+                self.write_f32s(&color.to_array())?;
+                self.write_f32s(&normal.to_array())?;
+                self.write_f32(*radius)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec2, vec3, vec4};
+
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut target = Scene {
+            sun: Sun {
+                azimuth: 1.0,
+                altitude: 0.5,
+                angular_size: 0.02,
+            },
+            ..Default::default()
+        };
+
+        target.images.insert(
+            "image-a".into(),
+            SceneImage::Path("textures/wall.png".into()),
+        );
+
+        target.images.insert(
+            "image-b".into(),
+            SceneImage::Embedded {
+                width: 1,
+                height: 2,
+                data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            },
+        );
+
+        target.meshes.insert(
+            "mesh".into(),
+            Mesh::new(vec![MeshTriangle::default()
+                .with_positions([
+                    vec3(1.0, 2.0, 3.0),
+                    vec3(4.0, 5.0, 6.0),
+                    vec3(7.0, 8.0, 9.0),
+                ])
+                .with_normals([Vec3::X, Vec3::Y, Vec3::Z])
+                .with_uvs([vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0)])
+                .with_tangents([vec4(1.0, 0.0, 0.0, -1.0); 3])]),
+        );
+
+        target.materials.insert(
+            "material".into(),
+            Material {
+                base_color: vec4(0.1, 0.2, 0.3, 0.4),
+                base_color_texture: Some("image-a".into()),
+                normal_map_texture: Some("image-b".into()),
+                metallic: 0.75,
+                alpha_mode: AlphaMode::Blend,
+                ..Default::default()
+            },
+        );
+
+        target.instances.insert(
+            "instance".into(),
+            Instance::new(
+                "mesh".into(),
+                "material".into(),
+                Affine3A::from_translation(vec3(1.0, 2.0, 3.0)),
+            )
+            .with_visibility_mask(0b101)
+            .with_ray_visibility(RayVisibility {
+                visible_to_camera: false,
+                casts_shadows: true,
+                visible_in_indirect: false,
+                visible_in_reflections: true,
+            }),
+        );
+
+        target.lights.insert(
+            "light".into(),
+            Light::Spot {
+                position: vec3(1.0, 2.0, 3.0),
+                radius: 0.1,
+                color: vec3(4.0, 5.0, 6.0),
+                range: 20.0,
+                direction: -Vec3::Y,
+                angle: 0.5,
+            },
+        );
+
+        target.lights.insert(
+            "light-rect".into(),
+            Light::Rect {
+                position: vec3(0.0, 3.0, 0.0),
+                color: vec3(2.0, 2.0, 2.0),
+                normal: -Vec3::Y,
+                tangent: Vec3::X,
+                width: 1.5,
+                height: 0.5,
+            },
+        );
+
+        target.lights.insert(
+            "light-disk".into(),
+            Light::Disk {
+                position: vec3(0.0, 3.0, 2.0),
+                color: vec3(1.0, 1.0, 1.0),
+                normal: -Vec3::Y,
+                radius: 0.25,
+            },
+        );
+
+        let mut buf = Vec::new();
+
+        target.write(&mut buf).unwrap();
+
+        let actual = Scene::read(buf.as_slice()).unwrap();
+
+        // Not all of the types implement `PartialEq`, so let's just compare
+        // the debug representations
+        assert_eq!(format!("{:?}", target), format!("{:?}", actual));
+
+        // ---
+
+        buf[8] = 123;
+
+        let err = Scene::read(buf.as_slice()).unwrap_err();
+
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+}
@@ -43,6 +43,7 @@ shaders!([
     atmosphere_generate_sky_lut,
     atmosphere_generate_transmittance_lut,
     bvh_heatmap,
+    di_grid,
     di_resolving,
     di_shading,
     di_spatial_resampling,
@@ -62,6 +63,7 @@ shaders!([
     gi_tracing,
     prim_raster_fs,
     prim_raster_vs,
+    ray_queries,
     ref_shading,
     ref_tracing,
 ]);
@@ -2,6 +2,10 @@
 pub struct Sun {
     pub azimuth: f32,
     pub altitude: f32,
+
+    /// Angular diameter of the sun's disk (in radians); the larger, the
+    /// softer the shadows.
+    pub angular_size: f32,
 }
 
 impl Default for Sun {
@@ -9,6 +13,7 @@ impl Default for Sun {
         Self {
             azimuth: 0.0,
             altitude: 0.35,
+            angular_size: 0.0093,
         }
     }
 }
@@ -2,7 +2,6 @@ use glam::Vec3Swizzles;
 use spirv_std::glam::{Vec2, Vec3, Vec4};
 
 use crate::gpu;
-use crate::utils::BoundingBox;
 
 #[derive(Clone, Debug)]
 pub struct Triangle {
@@ -13,14 +12,6 @@ pub struct Triangle {
 }
 
 impl Triangle {
-    pub fn center(&self) -> Vec3 {
-        self.positions.iter().sum::<Vec3>() / 3.0
-    }
-
-    pub fn bounds(&self) -> BoundingBox {
-        self.positions.iter().copied().collect()
-    }
-
     pub fn serialize(&self) -> gpu::Triangle {
         gpu::Triangle {
             d0: self.positions[0].xyz().extend(self.uvs[0].x),
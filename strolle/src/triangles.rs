@@ -3,13 +3,23 @@ use std::fmt::Debug;
 use std::mem;
 use std::ops::Range;
 
+use glam::Vec4;
+
 use crate::bvh::Bvh;
 use crate::utils::Allocator;
 use crate::{
-    gpu, Bindable, BufferFlushOutcome, BvhPrimitive, MappedStorageBuffer,
-    Params, Triangle,
+    gpu, Bindable, BlasId, BufferFlushOutcome, MappedStorageBuffer, Params,
+    StrolleError, Triangle,
 };
 
+/// Triangles of meshes that are used by at least one instance.
+///
+/// Triangles are kept in the object-space, so that all instances of the same
+/// mesh share the same triangles (and the same bottom-level BVH).
+///
+/// Next to triangles we keep a stream of their vertices' positions from the
+/// previous frame, which allows for the rasterizer to compute motion vectors of
+/// deforming meshes (e.g. skinned ones).
 #[derive(Debug)]
 pub struct Triangles<P>
 where
@@ -17,8 +27,13 @@ where
 {
     allocator: Allocator,
     buffer: MappedStorageBuffer<Vec<gpu::Triangle>>,
-    index: HashMap<P::InstanceHandle, IndexedInstance>,
+    prev_positions: MappedStorageBuffer<Vec<Vec4>>,
+    index: HashMap<P::MeshHandle, IndexedMesh>,
     dirty: bool,
+
+    /// Version assigned to the next mesh that gets created or updated; see:
+    /// [`Self::version()`].
+    next_version: u64,
 }
 
 impl<P> Triangles<P>
@@ -29,163 +44,209 @@ where
         Self {
             allocator: Default::default(),
             buffer: MappedStorageBuffer::new_default(device, "triangles"),
+            prev_positions: MappedStorageBuffer::new_default(
+                device,
+                "triangles_prev_positions",
+            ),
             index: Default::default(),
             dirty: Default::default(),
+            next_version: 0,
         }
     }
 
     pub fn create(
         &mut self,
         bvh: &mut Bvh,
-        instance_handle: P::InstanceHandle,
+        mesh_handle: P::MeshHandle,
         triangles: impl Iterator<Item = Triangle> + ExactSizeIterator,
-        material_id: gpu::MaterialId,
-    ) {
-        assert!(
-            !self.index.contains_key(&instance_handle),
-            "instance {instance_handle:?} has been already added - now it can \
-             be only updated or removed"
-        );
+    ) -> Result<(), StrolleError> {
+        if self.index.contains_key(&mesh_handle) {
+            return Err(StrolleError::InvalidMesh(format!(
+                "mesh {mesh_handle:?} has been already added - now it can be \
+                 only removed"
+            )));
+        }
 
-        assert!(
-            triangles.len() > 0,
-            "instance {instance_handle:?} contains no triangles"
-        );
+        if triangles.len() == 0 {
+            return Err(StrolleError::InvalidMesh(
+                "mesh contains no triangles".into(),
+            ));
+        }
+
+        let triangle_ids =
+            if let Some(triangle_ids) = self.allocator.take(triangles.len()) {
+                triangle_ids
+            } else {
+                let triangle_ids =
+                    self.buffer.len()..(self.buffer.len() + triangles.len());
+
+                self.buffer
+                    .resize(triangle_ids.end, gpu::Triangle::default());
 
-        let triangle_ids = if let Some(triangle_ids) =
-            self.allocator.take(triangles.len())
+                self.prev_positions
+                    .resize(3 * triangle_ids.end, Vec4::default());
+
+                triangle_ids
+            };
+
+        for (triangle, tri) in
+            triangles.zip(&mut self.buffer[triangle_ids.clone()])
         {
-            self.create_reusing_space(bvh, triangles, material_id, triangle_ids)
-        } else {
-            self.create_allocating_space(bvh, triangles, material_id)
-        };
+            *tri = triangle.serialize();
+        }
+
+        // Freshly created mesh doesn't have any previous positions, so let's
+        // pretend it hasn't moved
+        Self::copy_positions(
+            &self.buffer,
+            &mut self.prev_positions,
+            &triangle_ids,
+        );
+
+        let blas_id = bvh.create_blas(
+            triangle_ids.start,
+            &self.buffer[triangle_ids.clone()],
+        );
 
         self.index.insert(
-            instance_handle,
-            IndexedInstance {
+            mesh_handle,
+            IndexedMesh {
                 triangle_ids,
+                blas_id,
                 dirty: true,
+                deformed: false,
+                version: self.next_version,
             },
         );
 
+        self.next_version += 1;
+
         self.dirty = true;
+
+        Ok(())
     }
 
-    fn create_reusing_space(
+    /// Updates triangles of given mesh, refitting its bottom-level BVH; the
+    /// number of triangles must stay the same.
+    ///
+    /// Positions the mesh had before the update are kept for the rasterizer
+    /// to compute per-vertex motion vectors.
+    pub fn update(
         &mut self,
         bvh: &mut Bvh,
-        triangles: impl Iterator<Item = Triangle>,
-        material_id: gpu::MaterialId,
-        triangle_ids: Range<usize>,
-    ) -> Range<usize> {
-        let mut triangle_id = triangle_ids.start;
-
-        let iter = triangles
-            .into_iter()
-            .zip(&mut self.buffer[triangle_ids.clone()])
-            .zip(bvh.update(triangle_ids.clone()));
-
-        for ((triangle, tri), prim) in iter {
-            *tri = triangle.serialize();
-
-            *prim = BvhPrimitive {
-                triangle_id: gpu::TriangleId::new(triangle_id as u32),
-                material_id,
-                center: triangle.center(),
-                bounds: triangle.bounds(),
-            };
+        mesh_handle: &P::MeshHandle,
+        triangles: impl Iterator<Item = Triangle> + ExactSizeIterator,
+    ) -> Result<(), StrolleError> {
+        let Some(mesh) = self.index.get_mut(mesh_handle) else {
+            return Err(StrolleError::InvalidMesh(format!(
+                "mesh {mesh_handle:?} has not been added yet"
+            )));
+        };
 
-            triangle_id += 1;
+        if mesh.triangle_ids.len() != triangles.len() {
+            return Err(StrolleError::InvalidMesh(format!(
+                "mesh {mesh_handle:?} has changed its number of triangles - it \
+                 has to be removed and created again"
+            )));
         }
 
-        triangle_ids
-    }
+        // If the mesh has been updated more than once within the same frame,
+        // keep the positions from before the first update, since that's what
+        // the previous frame has actually seen
+        if !mesh.dirty || !mesh.deformed {
+            Self::copy_positions(
+                &self.buffer,
+                &mut self.prev_positions,
+                &mesh.triangle_ids,
+            );
+        }
 
-    fn create_allocating_space(
-        &mut self,
-        bvh: &mut Bvh,
-        triangles: impl Iterator<Item = Triangle>,
-        material_id: gpu::MaterialId,
-    ) -> Range<usize> {
-        let first_triangle_id = self.buffer.len();
-
-        for (triangle_idx, triangle) in triangles.enumerate() {
-            self.buffer.push(triangle.serialize());
-
-            bvh.add(BvhPrimitive {
-                triangle_id: gpu::TriangleId::new(
-                    (first_triangle_id + triangle_idx) as u32,
-                ),
-                material_id,
-                center: triangle.center(),
-                bounds: triangle.bounds(),
-            });
+        for (triangle, tri) in
+            triangles.zip(&mut self.buffer[mesh.triangle_ids.clone()])
+        {
+            *tri = triangle.serialize();
         }
 
-        first_triangle_id..self.buffer.len()
-    }
+        bvh.update_blas(
+            mesh.blas_id,
+            mesh.triangle_ids.start,
+            &self.buffer[mesh.triangle_ids.clone()],
+        );
 
-    pub fn update(
-        &mut self,
-        bvh: &mut Bvh,
-        instance_handle: &P::InstanceHandle,
-        triangles: impl Iterator<Item = Triangle> + ExactSizeIterator,
-        material_id: gpu::MaterialId,
-    ) {
-        let instance =
-            self.index.get_mut(instance_handle).unwrap_or_else(|| {
-                panic!("instance not known: {instance_handle:?}")
-            });
+        mesh.dirty = true;
+        mesh.deformed = true;
+        mesh.version = self.next_version;
 
-        let iter = triangles
-            .into_iter()
-            .zip(&mut self.buffer[instance.triangle_ids.clone()])
-            .zip(bvh.update(instance.triangle_ids.clone()));
+        self.dirty = true;
+        self.next_version += 1;
 
-        for ((triangle, tri), prim) in iter {
-            *tri = triangle.serialize();
+        Ok(())
+    }
 
-            prim.material_id = material_id;
-            prim.center = triangle.center();
-            prim.bounds = triangle.bounds();
+    /// Rebuilds bottom-level BVHs of all meshes from scratch.
+    pub fn rebuild(&self, bvh: &mut Bvh) {
+        for mesh in self.index.values() {
+            bvh.rebuild_blas(
+                mesh.blas_id,
+                mesh.triangle_ids.start,
+                &self.buffer[mesh.triangle_ids.clone()],
+            );
         }
-
-        instance.dirty = true;
-        self.dirty = true;
     }
 
-    pub fn remove(
-        &mut self,
-        bvh: &mut Bvh,
-        instance_handle: &P::InstanceHandle,
-    ) {
-        let Some(instance) = self.index.remove(instance_handle) else {
+    pub fn remove(&mut self, bvh: &mut Bvh, mesh_handle: &P::MeshHandle) {
+        let Some(mesh) = self.index.remove(mesh_handle) else {
             return;
         };
 
-        self.allocator.give(instance.triangle_ids.clone());
+        self.allocator.give(mesh.triangle_ids);
+        bvh.remove_blas(mesh.blas_id);
+    }
 
-        for prim in bvh.update(instance.triangle_ids) {
-            prim.kill();
-        }
+    pub fn has(&self, mesh_handle: &P::MeshHandle) -> bool {
+        self.index.contains_key(mesh_handle)
+    }
+
+    pub fn count(&self, mesh_handle: &P::MeshHandle) -> Option<usize> {
+        self.index
+            .get(mesh_handle)
+            .map(|mesh| mesh.triangle_ids.len())
+    }
+
+    pub fn blas_id(&self, mesh_handle: &P::MeshHandle) -> Option<BlasId> {
+        self.index.get(mesh_handle).map(|mesh| mesh.blas_id)
+    }
+
+    /// Returns version of given mesh - it changes each time the mesh gets
+    /// created or updated, allowing to cheaply detect whether the mesh's
+    /// triangles are still the same as before.
+    pub fn version(&self, mesh_handle: &P::MeshHandle) -> Option<u64> {
+        self.index.get(mesh_handle).map(|mesh| mesh.version)
+    }
+
+    /// Returns ids of triangles belonging to given mesh.
+    pub fn triangle_ids(
+        &self,
+        mesh_handle: &P::MeshHandle,
+    ) -> Option<Range<usize>> {
+        self.index
+            .get(mesh_handle)
+            .map(|mesh| mesh.triangle_ids.clone())
     }
 
     pub fn len(&self) -> usize {
         self.buffer.len()
     }
 
-    pub fn count(&self, instance_handle: &P::InstanceHandle) -> Option<usize> {
-        self.index
-            .get(instance_handle)
-            .map(|instance| instance.triangle_ids.len())
+    pub fn as_slice(&self) -> &[gpu::Triangle] {
+        &self.buffer
     }
 
     pub fn as_vertex_buffer(
         &self,
-        instance_handle: &P::InstanceHandle,
+        mesh_handle: &P::MeshHandle,
     ) -> Option<(usize, wgpu::BufferSlice<'_>)> {
-        let IndexedInstance { triangle_ids, .. } =
-            self.index.get(instance_handle)?;
+        let IndexedMesh { triangle_ids, .. } = self.index.get(mesh_handle)?;
 
         let vertices = 3 * triangle_ids.len();
 
@@ -201,6 +262,20 @@ where
         Some((vertices, vertex_buffer))
     }
 
+    /// Returns vertex buffer containing positions the mesh's vertices had in
+    /// the previous frame, laid out just like [`Self::as_vertex_buffer()`].
+    pub fn as_prev_vertex_buffer(
+        &self,
+        mesh_handle: &P::MeshHandle,
+    ) -> Option<wgpu::BufferSlice<'_>> {
+        let IndexedMesh { triangle_ids, .. } = self.index.get(mesh_handle)?;
+
+        let min = 3 * triangle_ids.start * mem::size_of::<Vec4>();
+        let min = min as wgpu::BufferAddress;
+
+        Some(self.prev_positions.as_buffer().slice(min..))
+    }
+
     pub fn flush(
         &mut self,
         device: &wgpu::Device,
@@ -211,36 +286,87 @@ where
         }
 
         let reallocated = self.buffer.reallocate(device, queue);
+        let prev_reallocated = self.prev_positions.reallocate(device, queue);
 
-        if reallocated {
-            // Reallocating already flushes the entire buffer, so there's no
-            // need to flush it again
-        } else {
-            for instance in self.index.values_mut() {
-                if !mem::take(&mut instance.dirty) {
-                    continue;
-                }
+        for mesh in self.index.values_mut() {
+            let updated = mem::take(&mut mesh.dirty);
+
+            if updated {
+                if !reallocated {
+                    let offset = mesh.triangle_ids.start
+                        * mem::size_of::<gpu::Triangle>();
+
+                    let size = mesh.triangle_ids.len()
+                        * mem::size_of::<gpu::Triangle>();
 
-                let offset = instance.triangle_ids.start
-                    * mem::size_of::<gpu::Triangle>();
+                    self.buffer.flush_part(queue, offset, size);
+                }
+            } else if mesh.deformed {
+                // Mesh has been deformed in the previous frame, but not in
+                // this one - from now on its previous positions are the same
+                // as the current ones (otherwise it'd keep reporting motion
+                // vectors even though it doesn't move anymore)
+                Self::copy_positions(
+                    &self.buffer,
+                    &mut self.prev_positions,
+                    &mesh.triangle_ids,
+                );
+
+                mesh.deformed = false;
+            } else {
+                continue;
+            }
 
-                let size = instance.triangle_ids.len()
-                    * mem::size_of::<gpu::Triangle>();
+            if !prev_reallocated {
+                let offset =
+                    3 * mesh.triangle_ids.start * mem::size_of::<Vec4>();
+                let size = 3 * mesh.triangle_ids.len() * mem::size_of::<Vec4>();
 
-                self.buffer.flush_part(queue, offset, size);
+                self.prev_positions.flush_part(queue, offset, size);
             }
+
+            // Deformed meshes have to be revisited in the next frame, even if
+            // nothing else changes
+            self.dirty |= mesh.deformed;
         }
 
-        BufferFlushOutcome { reallocated }
+        BufferFlushOutcome {
+            reallocated: reallocated || prev_reallocated,
+        }
     }
 
     pub fn bind_readable(&self) -> impl Bindable + '_ {
         self.buffer.bind_readable()
     }
+
+    fn copy_positions(
+        triangles: &[gpu::Triangle],
+        prev_positions: &mut [Vec4],
+        triangle_ids: &Range<usize>,
+    ) {
+        for (triangle, prev_positions) in triangles[triangle_ids.clone()]
+            .iter()
+            .zip(prev_positions[(3 * triangle_ids.start)..].chunks_exact_mut(3))
+        {
+            for (position, prev_position) in
+                triangle.positions().into_iter().zip(prev_positions)
+            {
+                *prev_position = position.extend(1.0);
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
-struct IndexedInstance {
+struct IndexedMesh {
     triangle_ids: Range<usize>,
+    blas_id: BlasId,
     dirty: bool,
+
+    /// Whether mesh's previous positions (might) differ from the current
+    /// ones.
+    deformed: bool,
+
+    /// See: [`Triangles::version()`].
+    version: u64,
 }
@@ -1,6 +1,7 @@
 use std::ops::{Add, AddAssign};
 
-use spirv_std::glam::Vec3;
+use glam::Affine3A;
+use spirv_std::glam::{vec3, Vec3};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct BoundingBox {
@@ -21,6 +22,10 @@ impl BoundingBox {
         self.max
     }
 
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+
     pub fn extent(&self) -> Vec3 {
         self.max() - self.min()
     }
@@ -34,6 +39,36 @@ impl BoundingBox {
     pub fn is_set(&self) -> bool {
         self.min.x != Self::default().min.x
     }
+
+    /// Returns whether this bounding box is not empty (e.g. it's not a result
+    /// of intersecting two disjoint bounding boxes).
+    pub fn is_valid(&self) -> bool {
+        self.min.cmple(self.max).all()
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        Self::new(self.min.max(other.min), self.max.min(other.max))
+    }
+
+    /// Returns the smallest bounding box containing this bounding box after
+    /// transforming it.
+    pub fn transform(&self, xform: Affine3A) -> Self {
+        let corners = [
+            vec3(self.min.x, self.min.y, self.min.z),
+            vec3(self.min.x, self.min.y, self.max.z),
+            vec3(self.min.x, self.max.y, self.min.z),
+            vec3(self.min.x, self.max.y, self.max.z),
+            vec3(self.max.x, self.min.y, self.min.z),
+            vec3(self.max.x, self.min.y, self.max.z),
+            vec3(self.max.x, self.max.y, self.min.z),
+            vec3(self.max.x, self.max.y, self.max.z),
+        ];
+
+        corners
+            .into_iter()
+            .map(|corner| xform.transform_point3(corner))
+            .collect()
+    }
 }
 
 impl Default for BoundingBox {